@@ -1,12 +1,21 @@
-use std::collections::HashMap;
+use std::collections::{BTreeSet, HashMap};
 use std::fs;
+use std::path::{Path, PathBuf};
 
-use rusqlite::{named_params, Connection, Error};
+use rusqlite::{named_params, Connection, Error, OptionalExtension};
 use tauri::AppHandle;
 
+use crate::filescan::{folder_id, ScanSettings};
+use crate::history::{WatchEvent, WatchEventKind, WatchStats};
+use crate::identity::IdentityStrategy;
+use crate::jobs::{Job, JobKind, JobStatus};
+use crate::mediainfo::{MediaInfoQuery, VideoMediaInfo, MEDIA_INFO_VERSION};
+use crate::player::{PlayerProfile, ResumePosition};
+use crate::scanreport::{ScanIssue, ScanReport, ScanReportEntry};
+use crate::scanrules::ScanRule;
 use crate::state::VideoCacheItem;
-use crate::util::get_app_dir;
-use crate::video::VideoEntry;
+use crate::util::{self, get_app_dir};
+use crate::video::{FolderEntry, VideoCategory, VideoEntry, VideoTag};
 
 pub fn load_database(app_handle: &AppHandle) -> Result<Connection, Error> {
     let path = get_app_dir(app_handle);
@@ -18,6 +27,14 @@ pub fn load_database(app_handle: &AppHandle) -> Result<Connection, Error> {
     Ok(db)
 }
 
+// Fresh database for the tests of the modules using one
+#[cfg(test)]
+pub(crate) fn open_in_memory() -> Connection {
+    let mut db = Connection::open_in_memory().unwrap();
+    upgrade_database(&mut db, 0).unwrap();
+    db
+}
+
 fn upgrade_database(connection: &mut Connection, version: u32) -> Result<(), Error> {
     let transaction = connection.transaction()?;
     if version < 1 {
@@ -49,6 +66,195 @@ fn upgrade_database(connection: &mut Connection, version: u32) -> Result<(), Err
         transaction.execute(sql, [])?;
         transaction.pragma_update(None, "user_version", 3)?;
     }
+    if version < 4 {
+        transaction.execute("ALTER TABLE VIDEO_CACHE ADD COLUMN mtime NUMBER", [])?;
+        transaction.execute("ALTER TABLE VIDEO_CACHE ADD COLUMN video INTEGER", [])?;
+        transaction.pragma_update(None, "user_version", 4)?;
+    }
+    if version < 5 {
+        transaction.execute("ALTER TABLE PATHS ADD COLUMN identity TEXT", [])?;
+        transaction.pragma_update(None, "user_version", 5)?;
+    }
+    if version < 6 {
+        // Copies of a video are its VIDEO_CACHE rows, the one to play is
+        // flagged among them
+        transaction.execute(
+            "ALTER TABLE VIDEO_CACHE ADD COLUMN preferred INTEGER DEFAULT 0",
+            [],
+        )?;
+        transaction.execute("CREATE INDEX VIDEO_CACHE_ID ON VIDEO_CACHE(id)", [])?;
+        transaction.pragma_update(None, "user_version", 6)?;
+    }
+    if version < 7 {
+        let sql = "CREATE TABLE SCAN_RULES (
+            id Integer PRIMARY KEY AUTOINCREMENT,
+            path TEXT NOT NULL,
+            pattern TEXT NOT NULL,
+            include INTEGER NOT NULL
+        )";
+        transaction.execute(sql, [])?;
+        transaction.pragma_update(None, "user_version", 7)?;
+    }
+    if version < 8 {
+        transaction.execute(
+            "ALTER TABLE PATHS ADD COLUMN follow_symlinks INTEGER DEFAULT 1",
+            [],
+        )?;
+        transaction.pragma_update(None, "user_version", 8)?;
+    }
+    if version < 9 {
+        let sql = "CREATE TABLE SCAN_JOBS (
+            id Integer PRIMARY KEY AUTOINCREMENT,
+            kind TEXT NOT NULL,
+            path TEXT,
+            status TEXT NOT NULL,
+            updated NUMBER,
+            position TEXT
+        )";
+        transaction.execute(sql, [])?;
+        transaction.pragma_update(None, "user_version", 9)?;
+    }
+    if version < 10 {
+        let sql = "CREATE TABLE SCAN_REPORTS (
+            id Integer PRIMARY KEY AUTOINCREMENT,
+            path TEXT NOT NULL,
+            job INTEGER,
+            created NUMBER NOT NULL,
+            files INTEGER NOT NULL,
+            videos INTEGER NOT NULL
+        )";
+        transaction.execute(sql, [])?;
+        let sql = "CREATE TABLE SCAN_REPORT_ENTRIES (
+            report INTEGER NOT NULL,
+            path TEXT NOT NULL,
+            issue TEXT NOT NULL,
+            message TEXT
+        )";
+        transaction.execute(sql, [])?;
+        transaction.execute(
+            "CREATE INDEX SCAN_REPORT_ENTRIES_REPORT ON SCAN_REPORT_ENTRIES(report)",
+            [],
+        )?;
+        transaction.pragma_update(None, "user_version", 10)?;
+    }
+    if version < 11 {
+        let sql = "CREATE TABLE FOLDERS (
+            id TEXT PRIMARY KEY,
+            path TEXT NOT NULL,
+            name TEXT,
+            rating INTEGER DEFAULT 0,
+            notes TEXT DEFAULT '',
+            cover TEXT
+        )";
+        transaction.execute(sql, [])?;
+        transaction.pragma_update(None, "user_version", 11)?;
+    }
+    if version < 12 {
+        let sql = "CREATE TABLE CATEGORIES (
+            id Integer PRIMARY KEY AUTOINCREMENT,
+            name TEXT NOT NULL,
+            icon TEXT DEFAULT ''
+        )";
+        transaction.execute(sql, [])?;
+        transaction.pragma_update(None, "user_version", 12)?;
+    }
+    if version < 13 {
+        let sql = "CREATE TABLE TAGS (
+            id Integer PRIMARY KEY AUTOINCREMENT,
+            name TEXT NOT NULL,
+            parent INTEGER,
+            color TEXT,
+            icon TEXT
+        )";
+        transaction.execute(sql, [])?;
+        let sql = "CREATE TABLE VIDEO_TAGS (
+            video TEXT NOT NULL,
+            tag INTEGER NOT NULL,
+            PRIMARY KEY (video, tag)
+        )";
+        transaction.execute(sql, [])?;
+        transaction.execute("CREATE INDEX VIDEO_TAGS_TAG ON VIDEO_TAGS(tag)", [])?;
+        transaction.execute(
+            "CREATE UNIQUE INDEX TAGS_PARENT_NAME ON TAGS(ifnull(parent, 0), name)",
+            [],
+        )?;
+        transaction.pragma_update(None, "user_version", 13)?;
+    }
+    if version < 14 {
+        let sql = "CREATE TABLE WATCH_HISTORY (
+            id Integer PRIMARY KEY AUTOINCREMENT,
+            video TEXT NOT NULL,
+            kind TEXT NOT NULL,
+            time NUMBER NOT NULL,
+            path TEXT,
+            session TEXT NOT NULL,
+            user TEXT
+        )";
+        transaction.execute(sql, [])?;
+        transaction.execute(
+            "CREATE INDEX WATCH_HISTORY_VIDEO ON WATCH_HISTORY(video)",
+            [],
+        )?;
+        transaction.pragma_update(None, "user_version", 14)?;
+    }
+    if version < 15 {
+        let sql = "CREATE TABLE SETTINGS (
+            key TEXT PRIMARY KEY,
+            value TEXT
+        )";
+        transaction.execute(sql, [])?;
+        let sql = "CREATE TABLE RESUME (
+            video TEXT PRIMARY KEY,
+            position REAL NOT NULL,
+            duration REAL,
+            updated NUMBER NOT NULL
+        )";
+        transaction.execute(sql, [])?;
+        transaction.pragma_update(None, "user_version", 15)?;
+    }
+    if version < 16 {
+        let sql = "CREATE TABLE PLAYER_PROFILES (
+            id Integer PRIMARY KEY AUTOINCREMENT,
+            name TEXT NOT NULL,
+            executable TEXT NOT NULL,
+            arguments TEXT NOT NULL,
+            working_dir TEXT,
+            mpv_ipc INTEGER DEFAULT 0
+        )";
+        transaction.execute(sql, [])?;
+        transaction.execute("ALTER TABLE PATHS ADD COLUMN player INTEGER", [])?;
+        transaction.execute("ALTER TABLE CATEGORIES ADD COLUMN player INTEGER", [])?;
+        transaction.pragma_update(None, "user_version", 16)?;
+    }
+    if version < 17 {
+        // path, size and mtime are those of the file the info was read from,
+        // details holds the full info as JSON and version the layout it has
+        let sql = "CREATE TABLE MEDIA_INFO (
+            video TEXT PRIMARY KEY,
+            path TEXT,
+            size NUMBER,
+            mtime NUMBER,
+            duration REAL,
+            bitrate INTEGER,
+            width INTEGER,
+            height INTEGER,
+            fps REAL,
+            codec INTEGER,
+            acodec INTEGER,
+            abitrate INTEGER,
+            sample_rate INTEGER,
+            details TEXT,
+            version INTEGER NOT NULL DEFAULT 0
+        )";
+        transaction.execute(sql, [])?;
+        // Videos created while the metadata import was on, their tags are
+        // imported once the media info was read
+        let sql = "CREATE TABLE METADATA_IMPORTS (
+            video TEXT PRIMARY KEY
+        )";
+        transaction.execute(sql, [])?;
+        transaction.pragma_update(None, "user_version", 17)?;
+    }
     transaction.commit()?;
     Ok(())
 }
@@ -59,6 +265,90 @@ pub fn get_paths(connection: &Connection) -> Result<Vec<String>, Error> {
     rows.collect::<Result<Vec<_>, _>>()
 }
 
+pub fn get_paths_with_settings(
+    connection: &Connection,
+) -> Result<Vec<(String, ScanSettings)>, Error> {
+    let mut query =
+        connection.prepare("SELECT path, identity, follow_symlinks FROM PATHS ORDER BY id")?;
+    let rows = query.query_map([], |row| {
+        Ok((
+            row.get::<_, String>("path")?,
+            IdentityStrategy::from_name(row.get::<_, Option<String>>("identity")?.as_deref()),
+            row.get::<_, Option<bool>>("follow_symlinks")?
+                .unwrap_or(true),
+        ))
+    })?;
+    rows.collect::<Result<Vec<_>, _>>()?
+        .into_iter()
+        .map(|(path, identity, follow_symlinks)| {
+            let rules = get_scan_rules(connection, &path)?;
+            Ok((
+                path,
+                ScanSettings {
+                    identity,
+                    rules,
+                    follow_symlinks,
+                },
+            ))
+        })
+        .collect()
+}
+
+// Scan settings of the library root that contains the given path
+pub fn get_scan_settings<P: AsRef<Path>>(connection: &Connection, path: P) -> ScanSettings {
+    get_paths_with_settings(connection)
+        .unwrap_or_default()
+        .into_iter()
+        .filter(|(root, _)| path.as_ref().starts_with(root))
+        .max_by_key(|(root, _)| root.len())
+        .map(|(_, settings)| settings)
+        .unwrap_or_default()
+}
+
+pub fn get_scan_rules(connection: &Connection, path: &str) -> Result<Vec<ScanRule>, Error> {
+    let mut query = connection
+        .prepare("SELECT pattern, include FROM SCAN_RULES WHERE path = @path ORDER BY id")?;
+    let rows = query.query_map(named_params! {"@path": path}, |row| {
+        Ok(ScanRule::new(row.get("pattern")?, row.get("include")?))
+    })?;
+    rows.collect::<Result<Vec<_>, _>>()
+}
+
+pub(crate) fn set_scan_rules(
+    db: &mut Connection,
+    path: &str,
+    rules: &[ScanRule],
+) -> Result<(), Error> {
+    let transaction = db.transaction()?;
+    transaction
+        .prepare("DELETE FROM SCAN_RULES WHERE path = @path")?
+        .execute(named_params! {"@path": path})?;
+    for rule in rules {
+        transaction
+            .prepare(
+                "INSERT INTO SCAN_RULES(path, pattern, include) VALUES (@path, @pattern, @include)",
+            )?
+            .execute(named_params! {
+                "@path": path,
+                "@pattern": rule.pattern(),
+                "@include": rule.include(),
+            })?;
+    }
+    transaction.commit()?;
+    Ok(())
+}
+
+pub(crate) fn set_follow_symlinks(
+    connection: &Connection,
+    path: &str,
+    follow: bool,
+) -> Result<(), Error> {
+    connection
+        .prepare("UPDATE PATHS SET follow_symlinks = @follow WHERE path = @path")?
+        .execute(named_params! {"@follow": follow, "@path": path})?;
+    Ok(())
+}
+
 pub fn add_path(connection: &Connection, path: &String) -> Result<(), Error> {
     connection
         .prepare("INSERT INTO PATHS(path) VALUES (@path)")?
@@ -67,19 +357,36 @@ pub fn add_path(connection: &Connection, path: &String) -> Result<(), Error> {
 }
 
 pub fn get_videos(connection: &Connection) -> Result<HashMap<String, VideoEntry>, Error> {
-    let mut query = connection.prepare("SELECT * FROM VIDEOS")?;
+    let mut query = connection.prepare(
+        "SELECT VIDEOS.*, CATEGORIES.name AS category_name, CATEGORIES.icon AS category_icon FROM VIDEOS LEFT JOIN CATEGORIES ON CATEGORIES.id = VIDEOS.category",
+    )?;
     let rows = query.query_map([], |row| {
-        Ok((
-            row.get("id")?,
-            VideoEntry::new(
-                row.get("name")?,
-                row.get("rating")?,
-                row.get("notes")?,
-                row.get("watched")?,
-            ),
-        ))
+        let mut entry = VideoEntry::new(
+            row.get("name")?,
+            row.get("rating")?,
+            row.get("notes")?,
+            row.get("watched")?,
+        );
+        if let (Some(id), Some(name)) = (
+            row.get::<_, Option<usize>>("category")?,
+            row.get::<_, Option<String>>("category_name")?,
+        ) {
+            entry.set_category(Some(VideoCategory::new(
+                id,
+                name,
+                row.get::<_, Option<String>>("category_icon")?
+                    .unwrap_or_default(),
+            )));
+        }
+        Ok((row.get("id")?, entry))
     })?;
-    rows.collect::<Result<HashMap<_, _>, _>>()
+    let mut videos = rows.collect::<Result<HashMap<String, VideoEntry>, _>>()?;
+    for (id, tags) in get_video_tags(connection)? {
+        if let Some(entry) = videos.get_mut(&id) {
+            entry.set_tags(tags);
+        }
+    }
+    Ok(videos)
 }
 
 pub fn add_video(
@@ -87,16 +394,51 @@ pub fn add_video(
     id: &String,
     video_entry: &VideoEntry,
 ) -> Result<(), Error> {
-    connection.prepare("INSERT INTO VIDEOS(id, name, rating, notes, watched) VALUES (@id, @name, @rating, @notes, @watched)")?.execute(named_params! {
+    connection.prepare("INSERT INTO VIDEOS(id, name, rating, notes, watched, category) VALUES (@id, @name, @rating, @notes, @watched, @category)")?.execute(named_params! {
         "@id": id,
         "@name": video_entry.name(),
         "@rating": video_entry.rating(),
         "@notes": video_entry.notes(),
         "@watched": video_entry.watched(),
+        "@category": video_entry.category().map(|c| c.id()),
     })?;
     Ok(())
 }
 
+// Writes many video entries in one transaction, entries that are not stored
+// yet are added
+pub(crate) fn save_videos(
+    connection: &Connection,
+    entries: &[(&String, &VideoEntry)],
+) -> Result<(), Error> {
+    let transaction = connection.unchecked_transaction()?;
+    {
+        let mut query = transaction.prepare(
+            "INSERT INTO VIDEOS(id, name, rating, notes, watched, category) VALUES (@id, @name, @rating, @notes, @watched, @category) ON CONFLICT(id) DO UPDATE SET name = excluded.name, rating = excluded.rating, notes = excluded.notes, watched = excluded.watched, category = excluded.category",
+        )?;
+        for (id, entry) in entries {
+            query.execute(named_params! {
+                "@id": id,
+                "@name": entry.name(),
+                "@rating": entry.rating(),
+                "@notes": entry.notes(),
+                "@watched": entry.watched(),
+                "@category": entry.category().map(|c| c.id()),
+            })?;
+        }
+        let mut delete = transaction.prepare("DELETE FROM VIDEO_TAGS WHERE video = @id")?;
+        let mut insert = transaction
+            .prepare("INSERT OR IGNORE INTO VIDEO_TAGS(video, tag) VALUES (@id, @tag)")?;
+        for (id, entry) in entries {
+            delete.execute(named_params! {"@id": id})?;
+            for tag in entry.tags() {
+                insert.execute(named_params! {"@id": id, "@tag": tag.id()})?;
+            }
+        }
+    }
+    transaction.commit()
+}
+
 pub(crate) fn update_rating(connection: &Connection, id: &String, new_rating: usize) -> Option<()> {
     connection
         .prepare("UPDATE VIDEOS SET RATING = @rating WHERE ID = @id")
@@ -125,16 +467,43 @@ pub(crate) fn update_watched(
     Some(())
 }
 
-pub(crate) fn add_video_cache(connection: &Connection, path: &String, size: &u64, id: &String) {
+// Returns the ids of the videos whose media info was dropped
+pub(crate) fn add_video_cache(
+    connection: &Connection,
+    path: &String,
+    item: &VideoCacheItem,
+) -> Vec<String> {
+    // The stored media info is stale once the file it was read from changed
+    let dropped = connection
+        .prepare(
+            "DELETE FROM MEDIA_INFO WHERE path = @path AND (video IS NOT @id OR size IS NOT @size OR mtime IS NOT @mtime) RETURNING video",
+        )
+        .expect("Query Failed")
+        .query_map(
+            named_params! {
+                "@path": path,
+                "@id": item.id(),
+                "@size": item.filesize(),
+                "@mtime": item.mtime(),
+            },
+            |row| row.get("video"),
+        )
+        .and_then(|rows| rows.collect::<Result<Vec<String>, _>>())
+        .expect("Execute failed");
     connection
-        .prepare("INSERT INTO VIDEO_CACHE(path, size, id) VALUES(@path, @size, @id)")
+        .prepare(
+            "INSERT INTO VIDEO_CACHE(path, size, id, mtime, video) VALUES(@path, @size, @id, @mtime, @video) ON CONFLICT(path) DO UPDATE SET size = excluded.size, mtime = excluded.mtime, video = excluded.video, preferred = CASE WHEN id = excluded.id THEN preferred ELSE 0 END, id = excluded.id",
+        )
         .expect("Query Failed")
         .execute(named_params! {
             "@path": path,
-            "@size": size,
-            "@id": id
+            "@size": item.filesize(),
+            "@id": item.id(),
+            "@mtime": item.mtime(),
+            "@video": item.is_video(),
         })
         .expect("Execute failed");
+    dropped
 }
 
 pub(crate) fn delete_video_cache(connection: &Connection, path: &String) {
@@ -147,6 +516,33 @@ pub(crate) fn delete_video_cache(connection: &Connection, path: &String) {
         .expect("Execute failed");
 }
 
+// Paths of every copy of the video and whether it is the preferred one
+pub(crate) fn get_locations(
+    connection: &Connection,
+    id: &str,
+) -> Result<Vec<(PathBuf, bool)>, Error> {
+    let mut query = connection.prepare(
+        "SELECT path, preferred FROM VIDEO_CACHE WHERE id = @id AND (video IS NULL OR video = 1) ORDER BY path",
+    )?;
+    let rows = query.query_map(named_params! {"@id": id}, |row| {
+        Ok((
+            PathBuf::from(row.get::<_, String>("path")?),
+            row.get::<_, Option<bool>>("preferred")?.unwrap_or(false),
+        ))
+    })?;
+    rows.collect::<Result<Vec<_>, _>>()
+}
+
+pub(crate) fn set_preferred_location(
+    connection: &Connection,
+    id: &str,
+    path: &str,
+) -> Result<usize, Error> {
+    connection
+        .prepare("UPDATE VIDEO_CACHE SET preferred = (path = @path) WHERE id = @id")?
+        .execute(named_params! {"@path": path, "@id": id})
+}
+
 pub(crate) fn get_video_cache_items(
     connection: &Connection,
 ) -> Result<HashMap<String, VideoCacheItem>, Error> {
@@ -154,7 +550,12 @@ pub(crate) fn get_video_cache_items(
     let rows = query.query_map([], |row| {
         Ok((
             row.get("path")?,
-            VideoCacheItem::new(row.get("size")?, row.get("id")?),
+            VideoCacheItem::new(
+                row.get("size")?,
+                row.get("mtime")?,
+                row.get("id")?,
+                row.get::<_, Option<bool>>("video")?.unwrap_or(true),
+            ),
         ))
     })?;
     rows.collect::<Result<HashMap<_, _>, _>>()
@@ -182,25 +583,1351 @@ pub(crate) fn update_notes(c: &Connection, i: &String, n: &String) -> Option<()>
     Some(())
 }
 
+pub(crate) fn get_categories(connection: &Connection) -> Result<Vec<VideoCategory>, Error> {
+    let mut query = connection.prepare("SELECT * FROM CATEGORIES ORDER BY name COLLATE NOCASE")?;
+    let rows = query.query_map([], |row| {
+        Ok(VideoCategory::new(
+            row.get("id")?,
+            row.get("name")?,
+            row.get::<_, Option<String>>("icon")?.unwrap_or_default(),
+        ))
+    })?;
+    rows.collect()
+}
+
+pub(crate) fn get_category(
+    connection: &Connection,
+    id: usize,
+) -> Result<Option<VideoCategory>, Error> {
+    connection
+        .query_row(
+            "SELECT * FROM CATEGORIES WHERE id = @id",
+            named_params! {"@id": id},
+            |row| {
+                Ok(VideoCategory::new(
+                    row.get("id")?,
+                    row.get("name")?,
+                    row.get::<_, Option<String>>("icon")?.unwrap_or_default(),
+                ))
+            },
+        )
+        .optional()
+}
+
+pub(crate) fn add_category(
+    connection: &Connection,
+    name: &str,
+    icon: &str,
+) -> Result<usize, Error> {
+    connection
+        .prepare("INSERT INTO CATEGORIES(name, icon) VALUES (@name, @icon)")?
+        .execute(named_params! {"@name": name, "@icon": icon})?;
+    Ok(connection.last_insert_rowid() as usize)
+}
+
+// Returns the number of updated rows, 0 when the category does not exist
+pub(crate) fn save_category(
+    connection: &Connection,
+    category: &VideoCategory,
+) -> Result<usize, Error> {
+    connection
+        .prepare("UPDATE CATEGORIES SET name = @name, icon = @icon WHERE id = @id")?
+        .execute(named_params! {
+            "@name": category.name(),
+            "@icon": category.icon(),
+            "@id": category.id(),
+        })
+}
+
+// Videos of a deleted category are left without one
+pub(crate) fn delete_category(connection: &Connection, id: usize) -> Result<(), Error> {
+    let transaction = connection.unchecked_transaction()?;
+    transaction
+        .prepare("UPDATE VIDEOS SET category = NULL WHERE category = @id")?
+        .execute(named_params! {"@id": id})?;
+    transaction
+        .prepare("DELETE FROM CATEGORIES WHERE id = @id")?
+        .execute(named_params! {"@id": id})?;
+    transaction.commit()
+}
+
+fn player_profile_from_row(row: &rusqlite::Row) -> Result<PlayerProfile, Error> {
+    Ok(PlayerProfile::new(
+        row.get("id")?,
+        row.get("name")?,
+        row.get("executable")?,
+        row.get("arguments")?,
+        row.get::<_, Option<String>>("working_dir")?
+            .map(PathBuf::from),
+        row.get::<_, Option<bool>>("mpv_ipc")?.unwrap_or(false),
+    ))
+}
+
+pub(crate) fn get_player_profiles(connection: &Connection) -> Result<Vec<PlayerProfile>, Error> {
+    let mut query =
+        connection.prepare("SELECT * FROM PLAYER_PROFILES ORDER BY name COLLATE NOCASE")?;
+    let rows = query.query_map([], player_profile_from_row)?;
+    rows.collect()
+}
+
+pub(crate) fn get_player_profile(
+    connection: &Connection,
+    id: usize,
+) -> Result<Option<PlayerProfile>, Error> {
+    connection
+        .query_row(
+            "SELECT * FROM PLAYER_PROFILES WHERE id = @id",
+            named_params! {"@id": id},
+            player_profile_from_row,
+        )
+        .optional()
+}
+
+// Inserts the profile when its id is not stored yet, returns the id
+pub(crate) fn save_player_profile(
+    connection: &Connection,
+    profile: &PlayerProfile,
+) -> Result<usize, Error> {
+    let params = named_params! {
+        "@id": profile.id(),
+        "@name": profile.name(),
+        "@executable": profile.executable(),
+        "@arguments": profile.arguments(),
+        "@working_dir": profile.working_dir().map(|d| d.display().to_string()),
+        "@mpv_ipc": profile.mpv_ipc(),
+    };
+    let updated = connection
+        .prepare(
+            "UPDATE PLAYER_PROFILES SET name = @name, executable = @executable, arguments = @arguments, working_dir = @working_dir, mpv_ipc = @mpv_ipc WHERE id = @id",
+        )?
+        .execute(params)?;
+    if updated > 0 {
+        return Ok(profile.id());
+    }
+    connection
+        .prepare(
+            "INSERT INTO PLAYER_PROFILES(name, executable, arguments, working_dir, mpv_ipc) VALUES (@name, @executable, @arguments, @working_dir, @mpv_ipc)",
+        )?
+        .execute(&params[1..])?;
+    Ok(connection.last_insert_rowid() as usize)
+}
+
+// Libraries and categories using the profile fall back to the next choice
+pub(crate) fn delete_player_profile(connection: &Connection, id: usize) -> Result<(), Error> {
+    let transaction = connection.unchecked_transaction()?;
+    transaction
+        .prepare("UPDATE PATHS SET player = NULL WHERE player = @id")?
+        .execute(named_params! {"@id": id})?;
+    transaction
+        .prepare("UPDATE CATEGORIES SET player = NULL WHERE player = @id")?
+        .execute(named_params! {"@id": id})?;
+    transaction
+        .prepare("DELETE FROM PLAYER_PROFILES WHERE id = @id")?
+        .execute(named_params! {"@id": id})?;
+    transaction.commit()
+}
+
+pub(crate) fn set_path_player(
+    connection: &Connection,
+    path: &str,
+    player: Option<usize>,
+) -> Result<(), Error> {
+    connection
+        .prepare("UPDATE PATHS SET player = @player WHERE path = @path")?
+        .execute(named_params! {"@player": player, "@path": path})?;
+    Ok(())
+}
+
+pub(crate) fn set_category_player(
+    connection: &Connection,
+    category: usize,
+    player: Option<usize>,
+) -> Result<(), Error> {
+    connection
+        .prepare("UPDATE CATEGORIES SET player = @player WHERE id = @id")?
+        .execute(named_params! {"@player": player, "@id": category})?;
+    Ok(())
+}
+
+// Library paths with their player profile
+pub(crate) fn get_path_players(connection: &Connection) -> Result<HashMap<String, usize>, Error> {
+    let mut query =
+        connection.prepare("SELECT path, player FROM PATHS WHERE player IS NOT NULL")?;
+    let rows = query.query_map([], |row| Ok((row.get("path")?, row.get("player")?)))?;
+    rows.collect()
+}
+
+// Category ids with their player profile
+pub(crate) fn get_category_players(
+    connection: &Connection,
+) -> Result<HashMap<usize, usize>, Error> {
+    let mut query =
+        connection.prepare("SELECT id, player FROM CATEGORIES WHERE player IS NOT NULL")?;
+    let rows = query.query_map([], |row| Ok((row.get("id")?, row.get("player")?)))?;
+    rows.collect()
+}
+
+pub(crate) fn save_media_info(
+    connection: &Connection,
+    id: &str,
+    path: &Path,
+    size: u64,
+    mtime: Option<u64>,
+    info: &VideoMediaInfo,
+) -> Result<(), Error> {
+    let details =
+        serde_json::to_string(info).map_err(|e| Error::ToSqlConversionFailure(Box::new(e)))?;
+    connection
+        .prepare(
+            "INSERT OR REPLACE INTO MEDIA_INFO(video, path, size, mtime, duration, bitrate, width, height, fps, codec, acodec, abitrate, sample_rate, details, version) VALUES (@video, @path, @size, @mtime, @duration, @bitrate, @width, @height, @fps, @codec, @acodec, @abitrate, @sample_rate, @details, @version)",
+        )?
+        .execute(named_params! {
+            "@video": id,
+            "@path": path.display().to_string(),
+            "@size": size,
+            "@mtime": mtime,
+            "@duration": info.duration(),
+            "@bitrate": info.bit_rate(),
+            "@width": info.width(),
+            "@height": info.height(),
+            "@fps": info.framerate(),
+            "@codec": info.codec_id(),
+            "@acodec": info.acodec_id(),
+            "@abitrate": info.abit_rate(),
+            "@sample_rate": info.sample_rate(),
+            "@details": details,
+            "@version": MEDIA_INFO_VERSION,
+        })?;
+    Ok(())
+}
+
+// The stored info with the path, size and mtime of the file it was read from.
+// Info written by an older version counts as missing, it is read again.
+pub(crate) fn get_media_info(
+    connection: &Connection,
+    id: &str,
+) -> Result<Option<(PathBuf, u64, Option<u64>, VideoMediaInfo)>, Error> {
+    let row = connection
+        .query_row(
+            "SELECT path, size, mtime, details FROM MEDIA_INFO WHERE video = @id AND version = @version",
+            named_params! {"@id": id, "@version": MEDIA_INFO_VERSION},
+            |row| {
+                Ok((
+                    PathBuf::from(row.get::<_, String>("path")?),
+                    row.get::<_, u64>("size")?,
+                    row.get::<_, Option<u64>>("mtime")?,
+                    row.get::<_, String>("details")?,
+                ))
+            },
+        )
+        .optional()?;
+    Ok(row.and_then(|(path, size, mtime, details)| {
+        serde_json::from_str(&details)
+            .ok()
+            .map(|info| (path, size, mtime, info))
+    }))
+}
+
+pub(crate) fn add_metadata_import(connection: &Connection, id: &str) -> Result<(), Error> {
+    connection
+        .prepare("INSERT OR IGNORE INTO METADATA_IMPORTS(video) VALUES (@id)")?
+        .execute(named_params! {"@id": id})?;
+    Ok(())
+}
+
+// Whether the tags of the video are still to be imported, they only are once
+pub(crate) fn take_metadata_import(connection: &Connection, id: &str) -> Result<bool, Error> {
+    let deleted = connection
+        .prepare("DELETE FROM METADATA_IMPORTS WHERE video = @id")?
+        .execute(named_params! {"@id": id})?;
+    Ok(deleted > 0)
+}
+
+// Durations of every video whose media info was read, for the folder totals
+pub(crate) fn get_media_durations(connection: &Connection) -> Result<HashMap<String, f64>, Error> {
+    let mut query =
+        connection.prepare("SELECT video, duration FROM MEDIA_INFO WHERE duration IS NOT NULL")?;
+    let rows = query.query_map([], |row| Ok((row.get("video")?, row.get("duration")?)))?;
+    rows.collect()
+}
+
+pub(crate) fn find_media_info(
+    connection: &Connection,
+    query: &MediaInfoQuery,
+) -> Result<Vec<String>, Error> {
+    let mut conditions = Vec::new();
+    let mut values: Vec<Box<dyn rusqlite::ToSql>> = Vec::new();
+    for range in &query.ranges {
+        if let Some(min) = range.min {
+            conditions.push(format!("{} >= ?", range.field.column()));
+            values.push(Box::new(min));
+        }
+        if let Some(max) = range.max {
+            conditions.push(format!("{} <= ?", range.field.column()));
+            values.push(Box::new(max));
+        }
+    }
+    if let Some(codec) = query.codec {
+        conditions.push("codec = ?".to_string());
+        values.push(Box::new(codec));
+    }
+    if let Some(acodec) = query.acodec {
+        conditions.push("acodec = ?".to_string());
+        values.push(Box::new(acodec));
+    }
+    let mut sql = "SELECT video FROM MEDIA_INFO".to_string();
+    if !conditions.is_empty() {
+        sql.push_str(&format!(" WHERE {}", conditions.join(" AND ")));
+    }
+    if let Some(sort) = query.sort {
+        // Videos without the value go last either way
+        sql.push_str(&format!(
+            " ORDER BY {0} IS NULL, {0} {1}",
+            sort.column(),
+            if query.descending { "DESC" } else { "ASC" }
+        ));
+    }
+    if let Some(limit) = query.limit {
+        sql.push_str(&format!(" LIMIT {}", limit));
+    }
+    let mut statement = connection.prepare(&sql)?;
+    let rows = statement.query_map(
+        rusqlite::params_from_iter(values.iter().map(|v| v.as_ref())),
+        |row| row.get("video"),
+    )?;
+    rows.collect()
+}
+
+// Every tag by id, with the paths of nested tags filled in
+pub(crate) fn get_tags(connection: &Connection) -> Result<HashMap<usize, VideoTag>, Error> {
+    let mut query = connection.prepare("SELECT * FROM TAGS")?;
+    let mut tags = query
+        .query_map([], |row| {
+            Ok(VideoTag::new(
+                row.get("id")?,
+                row.get("name")?,
+                row.get("parent")?,
+                row.get("color")?,
+                row.get("icon")?,
+            ))
+        })?
+        .map(|tag| tag.map(|t| (t.id(), t)))
+        .collect::<Result<HashMap<_, _>, _>>()?;
+    let paths: Vec<(usize, String)> = tags
+        .values()
+        .map(|tag| {
+            let mut names = vec![tag.name()];
+            let mut parent = tag.parent();
+            // The depth limit guards against parents that point at each other
+            while let Some(p) = parent
+                .and_then(|p| tags.get(&p))
+                .filter(|_| names.len() < 32)
+            {
+                names.push(p.name());
+                parent = p.parent();
+            }
+            names.reverse();
+            (tag.id(), names.join("/"))
+        })
+        .collect();
+    for (id, path) in paths {
+        if let Some(tag) = tags.get_mut(&id) {
+            tag.set_path(path);
+        }
+    }
+    Ok(tags)
+}
+
+pub(crate) fn get_video_tags(
+    connection: &Connection,
+) -> Result<HashMap<String, Vec<VideoTag>>, Error> {
+    let tags = get_tags(connection)?;
+    let mut query = connection.prepare("SELECT video, tag FROM VIDEO_TAGS")?;
+    let rows = query.query_map([], |row| {
+        Ok((row.get::<_, String>("video")?, row.get::<_, usize>("tag")?))
+    })?;
+    let mut videos: HashMap<String, Vec<VideoTag>> = HashMap::new();
+    for row in rows {
+        let (video, tag) = row?;
+        if let Some(tag) = tags.get(&tag) {
+            videos.entry(video).or_default().push(tag.clone());
+        }
+    }
+    videos
+        .values_mut()
+        .for_each(|t| t.sort_by(|a, b| a.path().cmp(b.path())));
+    Ok(videos)
+}
+
+pub(crate) fn add_tag(
+    connection: &Connection,
+    name: &str,
+    parent: Option<usize>,
+    color: Option<&String>,
+    icon: Option<&String>,
+) -> Result<usize, Error> {
+    connection
+        .prepare(
+            "INSERT INTO TAGS(name, parent, color, icon) VALUES (@name, @parent, @color, @icon)",
+        )?
+        .execute(named_params! {
+            "@name": name,
+            "@parent": parent,
+            "@color": color,
+            "@icon": icon,
+        })?;
+    Ok(connection.last_insert_rowid() as usize)
+}
+
+pub(crate) fn save_tag(connection: &Connection, tag: &VideoTag) -> Result<(), Error> {
+    connection
+        .prepare("UPDATE TAGS SET name = @name, color = @color, icon = @icon WHERE id = @id")?
+        .execute(named_params! {
+            "@name": tag.name(),
+            "@color": tag.color(),
+            "@icon": tag.icon(),
+            "@id": tag.id(),
+        })?;
+    Ok(())
+}
+
+// Moves the videos and child tags of one tag over to another and drops it
+pub(crate) fn merge_tags(connection: &Connection, from: usize, into: usize) -> Result<(), Error> {
+    let transaction = connection.unchecked_transaction()?;
+    merge_tag_into(&transaction, from, into)?;
+    transaction.commit()
+}
+
+// Child tags whose name already exists below the target are merged into that
+// tag as well, the others are moved over
+fn merge_tag_into(connection: &Connection, from: usize, into: usize) -> Result<(), Error> {
+    connection
+        .prepare("INSERT OR IGNORE INTO VIDEO_TAGS(video, tag) SELECT video, @into FROM VIDEO_TAGS WHERE tag = @from")?
+        .execute(named_params! {"@from": from, "@into": into})?;
+    let children = connection
+        .prepare("SELECT id, name FROM TAGS WHERE parent = @from")?
+        .query_map(named_params! {"@from": from}, |row| {
+            Ok((row.get::<_, usize>("id")?, row.get::<_, String>("name")?))
+        })?
+        .collect::<Result<Vec<_>, _>>()?;
+    for (child, name) in children {
+        let existing = connection
+            .query_row(
+                "SELECT id FROM TAGS WHERE parent = @into AND name = @name",
+                named_params! {"@into": into, "@name": name},
+                |row| row.get::<_, usize>("id"),
+            )
+            .optional()?;
+        match existing {
+            Some(existing) => merge_tag_into(connection, child, existing)?,
+            None => {
+                connection
+                    .prepare("UPDATE TAGS SET parent = @into WHERE id = @child")?
+                    .execute(named_params! {"@into": into, "@child": child})?;
+            }
+        }
+    }
+    connection
+        .prepare("DELETE FROM VIDEO_TAGS WHERE tag = @from")?
+        .execute(named_params! {"@from": from})?;
+    connection
+        .prepare("DELETE FROM TAGS WHERE id = @from")?
+        .execute(named_params! {"@from": from})?;
+    Ok(())
+}
+
+// Deletes the tags and takes them off every video
+pub(crate) fn delete_tags(connection: &Connection, ids: &[usize]) -> Result<(), Error> {
+    let transaction = connection.unchecked_transaction()?;
+    for id in ids {
+        transaction
+            .prepare("DELETE FROM VIDEO_TAGS WHERE tag = @id")?
+            .execute(named_params! {"@id": id})?;
+        transaction
+            .prepare("DELETE FROM TAGS WHERE id = @id")?
+            .execute(named_params! {"@id": id})?;
+    }
+    transaction.commit()
+}
+
+// Ids of the videos carrying any of the tags
+pub(crate) fn get_tagged_videos(
+    connection: &Connection,
+    tags: &[usize],
+) -> Result<Vec<String>, Error> {
+    let mut query = connection.prepare("SELECT DISTINCT video FROM VIDEO_TAGS WHERE tag = @tag")?;
+    let mut videos = Vec::new();
+    for tag in tags {
+        let rows = query.query_map(named_params! {"@tag": tag}, |row| row.get("video"))?;
+        videos.extend(rows.collect::<Result<Vec<String>, _>>()?);
+    }
+    videos.sort();
+    videos.dedup();
+    Ok(videos)
+}
+
+pub(crate) fn add_watch_events(
+    connection: &Connection,
+    events: &[WatchEvent],
+) -> Result<(), Error> {
+    let transaction = connection.unchecked_transaction()?;
+    {
+        let mut query = transaction.prepare(
+            "INSERT INTO WATCH_HISTORY(video, kind, time, path, session, user) VALUES (@video, @kind, @time, @path, @session, @user)",
+        )?;
+        for event in events {
+            query.execute(named_params! {
+                "@video": event.video(),
+                "@kind": event.kind().name(),
+                "@time": event.time(),
+                "@path": event.path().map(|p| p.display().to_string()),
+                "@session": event.session(),
+                "@user": event.user(),
+            })?;
+        }
+    }
+    transaction.commit()
+}
+
+// Newest events first, of one video or of the whole library
+pub(crate) fn get_watch_history(
+    connection: &Connection,
+    video: Option<&str>,
+    limit: usize,
+) -> Result<Vec<WatchEvent>, Error> {
+    let mut query = connection.prepare(
+        "SELECT * FROM WATCH_HISTORY WHERE @video IS NULL OR video = @video ORDER BY time DESC, id DESC LIMIT @limit",
+    )?;
+    let rows = query.query_map(named_params! {"@video": video, "@limit": limit}, |row| {
+        Ok(WatchEvent::load(
+            row.get("video")?,
+            WatchEventKind::from_name(&row.get::<_, String>("kind")?),
+            row.get("time")?,
+            row.get::<_, Option<String>>("path")?.map(PathBuf::from),
+            row.get("session")?,
+            row.get("user")?,
+        ))
+    })?;
+    rows.collect()
+}
+
+// Play count and first and last watch of each video, most recently watched
+// first
+pub(crate) fn get_watch_stats(
+    connection: &Connection,
+    video: Option<&str>,
+    limit: usize,
+) -> Result<Vec<WatchStats>, Error> {
+    let mut query = connection.prepare(
+        "SELECT video, SUM(kind = 'played') AS plays, MIN(CASE WHEN kind != 'unwatched' THEN time END) AS first, MAX(CASE WHEN kind != 'unwatched' THEN time END) AS last FROM WATCH_HISTORY WHERE @video IS NULL OR video = @video GROUP BY video ORDER BY last IS NULL, last DESC LIMIT @limit",
+    )?;
+    let rows = query.query_map(named_params! {"@video": video, "@limit": limit}, |row| {
+        Ok(WatchStats::new(
+            row.get("video")?,
+            row.get("plays")?,
+            row.get("first")?,
+            row.get("last")?,
+        ))
+    })?;
+    rows.collect()
+}
+
+pub(crate) fn get_setting(connection: &Connection, key: &str) -> Result<Option<String>, Error> {
+    connection
+        .query_row(
+            "SELECT value FROM SETTINGS WHERE key = @key",
+            named_params! {"@key": key},
+            |row| row.get("value"),
+        )
+        .optional()
+        .map(Option::flatten)
+}
+
+pub(crate) fn set_setting(connection: &Connection, key: &str, value: &str) -> Result<(), Error> {
+    connection
+        .prepare("INSERT OR REPLACE INTO SETTINGS(key, value) VALUES (@key, @value)")?
+        .execute(named_params! {"@key": key, "@value": value})?;
+    Ok(())
+}
+
+pub(crate) fn get_resume(
+    connection: &Connection,
+    video: &str,
+) -> Result<Option<ResumePosition>, Error> {
+    connection
+        .query_row(
+            "SELECT * FROM RESUME WHERE video = @video",
+            named_params! {"@video": video},
+            |row| {
+                Ok(ResumePosition::load(
+                    row.get("video")?,
+                    row.get("position")?,
+                    row.get("duration")?,
+                    row.get("updated")?,
+                ))
+            },
+        )
+        .optional()
+}
+
+pub(crate) fn set_resume(connection: &Connection, resume: &ResumePosition) -> Result<(), Error> {
+    connection
+        .prepare(
+            "INSERT OR REPLACE INTO RESUME(video, position, duration, updated) VALUES (@video, @position, @duration, @updated)",
+        )?
+        .execute(named_params! {
+            "@video": resume.video(),
+            "@position": resume.position(),
+            "@duration": resume.duration(),
+            "@updated": resume.updated(),
+        })?;
+    Ok(())
+}
+
+pub(crate) fn delete_resume(connection: &Connection, video: &str) -> Result<(), Error> {
+    connection
+        .prepare("DELETE FROM RESUME WHERE video = @video")?
+        .execute(named_params! {"@video": video})?;
+    Ok(())
+}
+
+pub(crate) fn get_folder_entries(
+    connection: &Connection,
+) -> Result<HashMap<String, FolderEntry>, Error> {
+    let mut query = connection.prepare("SELECT * FROM FOLDERS")?;
+    let rows = query.query_map([], |row| {
+        Ok((
+            row.get("id")?,
+            FolderEntry::new(
+                row.get("name")?,
+                row.get("rating")?,
+                row.get("notes")?,
+                row.get::<_, Option<String>>("cover")?.map(PathBuf::from),
+            ),
+        ))
+    })?;
+    rows.collect::<Result<HashMap<_, _>, _>>()
+}
+
+pub(crate) fn set_folder_entry<P: AsRef<Path>>(
+    connection: &Connection,
+    id: &str,
+    path: P,
+    entry: &FolderEntry,
+) -> Result<(), Error> {
+    connection
+        .prepare(
+            "INSERT OR REPLACE INTO FOLDERS(id, path, name, rating, notes, cover) VALUES (@id, @path, @name, @rating, @notes, @cover)",
+        )?
+        .execute(named_params! {
+            "@id": id,
+            "@path": path.as_ref().display().to_string(),
+            "@name": entry.name(),
+            "@rating": entry.rating(),
+            "@notes": entry.notes(),
+            "@cover": entry.cover().map(|c| c.display().to_string()),
+        })?;
+    Ok(())
+}
+
+// Folder ids are path hashes, so entries of a moved folder and the folders
+// below it are stored again under their new ids. Returns the old and new ids.
+pub(crate) fn move_folder_entries(
+    connection: &Connection,
+    from: &Path,
+    to: &Path,
+) -> Result<Vec<(String, String)>, Error> {
+    let transaction = connection.unchecked_transaction()?;
+    let rows = transaction
+        .prepare("SELECT id, path FROM FOLDERS")?
+        .query_map([], |row| {
+            Ok((row.get::<_, String>("id")?, row.get::<_, String>("path")?))
+        })?
+        .collect::<Result<Vec<_>, _>>()?;
+    let mut changes = Vec::new();
+    for (id, path) in rows {
+        let new_path = match PathBuf::from(&path).strip_prefix(from) {
+            Ok(rest) if rest.as_os_str().is_empty() => to.to_path_buf(),
+            Ok(rest) => to.join(rest),
+            Err(_) => continue,
+        };
+        let new_id = folder_id(&new_path);
+        transaction
+            .prepare("UPDATE OR REPLACE FOLDERS SET id = @new, path = @path WHERE id = @old")?
+            .execute(named_params! {
+                "@new": new_id,
+                "@path": new_path.display().to_string(),
+                "@old": id,
+            })?;
+        changes.push((id, new_id));
+    }
+    transaction.commit()?;
+    Ok(changes)
+}
+
 pub(crate) fn delete_path(db: &mut Connection, path: &str) -> Result<(), Error> {
     let transaction = db.transaction()?;
     transaction
         .prepare("DELETE FROM PATHS WHERE PATH = @path")?
         .execute(named_params! {"@path": path})?;
+    transaction
+        .prepare("DELETE FROM SCAN_RULES WHERE path = @path")?
+        .execute(named_params! {"@path": path})?;
+    transaction
+        .prepare(
+            "DELETE FROM SCAN_REPORT_ENTRIES WHERE report IN (SELECT id FROM SCAN_REPORTS WHERE path = @path)",
+        )?
+        .execute(named_params! {"@path": path})?;
+    transaction
+        .prepare("DELETE FROM SCAN_REPORTS WHERE path = @path")?
+        .execute(named_params! {"@path": path})?;
+    transaction
+        .prepare("DELETE FROM SCAN_JOBS WHERE path = @path")?
+        .execute(named_params! {"@path": path})?;
+    // Entries of the folders below the path go with it
+    transaction
+        .prepare(
+            "DELETE FROM FOLDERS WHERE path = @path OR substr(path, 1, length(@prefix)) = @prefix",
+        )?
+        .execute(named_params! {
+            "@path": path,
+            "@prefix": format!("{}{}", path, std::path::MAIN_SEPARATOR),
+        })?;
     transaction.commit()?;
     Ok(())
 }
 
+// The path itself and everything below it, sibling paths sharing its prefix
+// are not matched
 pub(crate) fn get_cache_items_with_path(
     db: &mut Connection,
     path: &str,
 ) -> Result<Vec<String>, Error> {
-    let mut query = db.prepare("SELECT path FROM VIDEO_CACHE WHERE path LIKE @path")?;
+    let mut query = db.prepare(
+        "SELECT path FROM VIDEO_CACHE WHERE path = @path OR path LIKE @prefix ESCAPE '\\'",
+    )?;
+    let prefix = format!("{}{}", path, std::path::MAIN_SEPARATOR);
     let rows = query.query_map(
         named_params! {
-            "@path": format!("{}%", path)
+            "@path": path,
+            "@prefix": format!("{}%", escape_like(&prefix)),
         },
         |row| row.get("path"),
     )?;
     rows.collect::<Result<Vec<_>, _>>()
 }
+
+fn escape_like(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_")
+}
+
+pub(crate) fn add_job(
+    connection: &Connection,
+    kind: JobKind,
+    path: Option<&str>,
+) -> Result<i64, Error> {
+    connection
+        .prepare(
+            "INSERT INTO SCAN_JOBS(kind, path, status, updated) VALUES (@kind, @path, @status, @updated)",
+        )?
+        .execute(named_params! {
+            "@kind": kind.name(),
+            "@path": path,
+            "@status": JobStatus::Running.name(),
+            "@updated": util::now_millis(),
+        })?;
+    Ok(connection.last_insert_rowid())
+}
+
+pub(crate) fn update_job_status(
+    connection: &Connection,
+    id: i64,
+    status: JobStatus,
+) -> Result<(), Error> {
+    connection
+        .prepare("UPDATE SCAN_JOBS SET status = @status, updated = @updated WHERE id = @id")?
+        .execute(named_params! {
+            "@status": status.name(),
+            "@updated": util::now_millis(),
+            "@id": id,
+        })?;
+    Ok(())
+}
+
+pub(crate) fn update_job_position(
+    connection: &Connection,
+    id: i64,
+    position: &Path,
+) -> Result<(), Error> {
+    connection
+        .prepare("UPDATE SCAN_JOBS SET position = @position, updated = @updated WHERE id = @id")?
+        .execute(named_params! {
+            "@position": position.display().to_string(),
+            "@updated": util::now_millis(),
+            "@id": id,
+        })?;
+    Ok(())
+}
+
+// Jobs that are done are kept for this long
+const SCAN_JOBS_KEPT_DAYS: u64 = 30;
+
+pub(crate) fn prune_jobs(connection: &Connection) -> Result<usize, Error> {
+    let before = util::now_millis().saturating_sub(SCAN_JOBS_KEPT_DAYS * 24 * 60 * 60 * 1000);
+    connection
+        .prepare("DELETE FROM SCAN_JOBS WHERE status <> @running AND (updated IS NULL OR updated < @before)")?
+        .execute(named_params! {"@running": JobStatus::Running.name(), "@before": before})
+}
+
+pub(crate) fn get_running_jobs(connection: &Connection) -> Result<Vec<Job>, Error> {
+    let mut query = connection.prepare(
+        "SELECT id, kind, path, position FROM SCAN_JOBS WHERE status = @status ORDER BY id",
+    )?;
+    let rows = query.query_map(
+        named_params! {"@status": JobStatus::Running.name()},
+        |row| {
+            Ok((
+                row.get::<_, i64>("id")?,
+                row.get::<_, String>("kind")?,
+                row.get::<_, Option<String>>("path")?,
+                row.get::<_, Option<String>>("position")?,
+            ))
+        },
+    )?;
+    Ok(rows
+        .collect::<Result<Vec<_>, _>>()?
+        .into_iter()
+        .filter_map(|(id, kind, path, position)| {
+            JobKind::from_name(&kind)
+                .map(|k| Job::new(id, k, path).with_position(position.map(PathBuf::from)))
+        })
+        .collect())
+}
+
+// Only the latest reports of a library path are kept
+const SCAN_REPORTS_KEPT: usize = 10;
+
+pub(crate) fn add_scan_report(connection: &Connection, report: &ScanReport) -> Result<(), Error> {
+    let transaction = connection.unchecked_transaction()?;
+    let path = report.path().display().to_string();
+    transaction
+        .prepare(
+            "INSERT INTO SCAN_REPORTS(path, job, created, files, videos) VALUES (@path, @job, @created, @files, @videos)",
+        )?
+        .execute(named_params! {
+            "@path": path,
+            "@job": report.job(),
+            "@created": report.created(),
+            "@files": report.files(),
+            "@videos": report.videos(),
+        })?;
+    let id = transaction.last_insert_rowid();
+    for entry in report.entries() {
+        transaction
+            .prepare(
+                "INSERT INTO SCAN_REPORT_ENTRIES(report, path, issue, message) VALUES (@report, @path, @issue, @message)",
+            )?
+            .execute(named_params! {
+                "@report": id,
+                "@path": entry.path().display().to_string(),
+                "@issue": entry.issue().name(),
+                "@message": entry.message(),
+            })?;
+    }
+    transaction
+        .prepare(
+            "DELETE FROM SCAN_REPORT_ENTRIES WHERE report IN (SELECT id FROM SCAN_REPORTS WHERE path = @path ORDER BY id DESC LIMIT -1 OFFSET @kept)",
+        )?
+        .execute(named_params! {"@path": path, "@kept": SCAN_REPORTS_KEPT})?;
+    transaction
+        .prepare(
+            "DELETE FROM SCAN_REPORTS WHERE id IN (SELECT id FROM SCAN_REPORTS WHERE path = @path ORDER BY id DESC LIMIT -1 OFFSET @kept)",
+        )?
+        .execute(named_params! {"@path": path, "@kept": SCAN_REPORTS_KEPT})?;
+    transaction.commit()
+}
+
+pub(crate) fn get_scan_reports(
+    connection: &Connection,
+    path: &str,
+) -> Result<Vec<ScanReport>, Error> {
+    let mut query = connection.prepare(
+        "SELECT id, path, job, created, files, videos FROM SCAN_REPORTS WHERE path = @path ORDER BY id DESC",
+    )?;
+    let rows = query.query_map(named_params! {"@path": path}, |row| {
+        Ok((
+            row.get::<_, i64>("id")?,
+            row.get::<_, String>("path")?,
+            row.get::<_, Option<i64>>("job")?,
+            row.get::<_, u64>("created")?,
+            row.get::<_, usize>("files")?,
+            row.get::<_, usize>("videos")?,
+        ))
+    })?;
+    let mut entries_query = connection.prepare(
+        "SELECT path, issue, message FROM SCAN_REPORT_ENTRIES WHERE report = @report ORDER BY path",
+    )?;
+    rows.collect::<Result<Vec<_>, _>>()?
+        .into_iter()
+        .map(|(id, path, job, created, files, videos)| {
+            let entries = entries_query
+                .query_map(named_params! {"@report": id}, |row| {
+                    Ok(ScanReportEntry::new(
+                        PathBuf::from(row.get::<_, String>("path")?),
+                        ScanIssue::from_name(&row.get::<_, String>("issue")?),
+                        row.get::<_, Option<String>>("message")?.unwrap_or_default(),
+                    ))
+                })?
+                .collect::<Result<Vec<_>, _>>()?;
+            Ok(ScanReport::load(path, job, created, files, videos, entries))
+        })
+        .collect()
+}
+
+pub(crate) fn set_identity(
+    connection: &Connection,
+    path: &str,
+    identity: IdentityStrategy,
+) -> Result<(), Error> {
+    connection
+        .prepare("UPDATE PATHS SET identity = @identity WHERE path = @path")?
+        .execute(named_params! {"@identity": identity.name(), "@path": path})?;
+    Ok(())
+}
+
+pub(crate) fn rekey_videos(
+    db: &mut Connection,
+    changes: &[(String, String, String)],
+) -> Result<(), Error> {
+    let transaction = db.transaction()?;
+    for (p, _, new) in changes {
+        transaction
+            .prepare("UPDATE VIDEO_CACHE SET id = @id WHERE path = @path")?
+            .execute(named_params! {"@id": new, "@path": p})?;
+    }
+    // Rows of an old id still used by another path are copied, the rows of
+    // ids no longer in the cache are moved
+    let rekeyed: BTreeSet<(&str, &str)> = changes
+        .iter()
+        .map(|(_, old, new)| (old.as_str(), new.as_str()))
+        .collect();
+    for (old, new) in &rekeyed {
+        rekey_video(&transaction, old, new)?;
+    }
+    let old_ids: BTreeSet<&str> = rekeyed.iter().map(|(old, _)| *old).collect();
+    for old in old_ids {
+        let referenced: bool = transaction
+            .prepare("SELECT EXISTS (SELECT 1 FROM VIDEO_CACHE WHERE id = @id)")?
+            .query_row(named_params! {"@id": old}, |row| row.get(0))?;
+        if referenced {
+            continue;
+        }
+        for sql in [
+            "DELETE FROM VIDEOS WHERE id = @id",
+            "DELETE FROM VIDEO_TAGS WHERE video = @id",
+            "DELETE FROM RESUME WHERE video = @id",
+            "DELETE FROM MEDIA_INFO WHERE video = @id",
+            "DELETE FROM WATCH_HISTORY WHERE video = @id",
+            "DELETE FROM METADATA_IMPORTS WHERE video = @id",
+        ] {
+            transaction
+                .prepare(sql)?
+                .execute(named_params! {"@id": old})?;
+        }
+    }
+    transaction.commit()?;
+    Ok(())
+}
+
+// Copies everything stored for a video id over to its new id
+fn rekey_video(connection: &Connection, old: &str, new: &str) -> Result<(), Error> {
+    connection
+        .prepare(
+            "INSERT OR IGNORE INTO VIDEOS(id, name, rating, notes, watched, category) SELECT @new, name, rating, notes, watched, category FROM VIDEOS WHERE id = @old",
+        )?
+        .execute(named_params! {"@new": new, "@old": old})?;
+    // Ids collapsing into one keep the events of all of them, events copied
+    // by an earlier rekey of another path are not copied twice
+    connection
+        .prepare(
+            "INSERT INTO WATCH_HISTORY(video, kind, time, path, session, user) SELECT @new, kind, time, path, session, user FROM WATCH_HISTORY AS h WHERE video = @old AND NOT EXISTS (SELECT 1 FROM WATCH_HISTORY WHERE video = @new AND kind = h.kind AND time = h.time AND session = h.session) ORDER BY id",
+        )?
+        .execute(named_params! {"@new": new, "@old": old})?;
+    connection
+        .prepare(
+            "INSERT OR IGNORE INTO RESUME(video, position, duration, updated) SELECT @new, position, duration, updated FROM RESUME WHERE video = @old",
+        )?
+        .execute(named_params! {"@new": new, "@old": old})?;
+    connection
+        .prepare(
+            "INSERT OR IGNORE INTO VIDEO_TAGS(video, tag) SELECT @new, tag FROM VIDEO_TAGS WHERE video = @old",
+        )?
+        .execute(named_params! {"@new": new, "@old": old})?;
+    connection
+        .prepare(
+            "INSERT OR IGNORE INTO MEDIA_INFO(video, path, size, mtime, duration, bitrate, width, height, fps, codec, acodec, abitrate, sample_rate, details, version) SELECT @new, path, size, mtime, duration, bitrate, width, height, fps, codec, acodec, abitrate, sample_rate, details, version FROM MEDIA_INFO WHERE video = @old",
+        )?
+        .execute(named_params! {"@new": new, "@old": old})?;
+    connection
+        .prepare(
+            "INSERT OR IGNORE INTO METADATA_IMPORTS(video) SELECT @new FROM METADATA_IMPORTS WHERE video = @old",
+        )?
+        .execute(named_params! {"@new": new, "@old": old})?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mediainfo::VideoMediaInfoBuilder;
+
+    fn media_info(duration: f64) -> VideoMediaInfo {
+        VideoMediaInfoBuilder::default()
+            .length("0:01:00".to_string())
+            .duration(Some(duration))
+            .width(Some(1920))
+            .height(Some(1080))
+            .codec_id(Some(27))
+            .build()
+            .unwrap()
+    }
+
+    fn tag(db: &Connection, name: &str, parent: Option<usize>) -> usize {
+        add_tag(db, name, parent, None, None).unwrap()
+    }
+
+    fn tag_video(db: &Connection, video: &str, tag: usize) {
+        db.execute(
+            "INSERT INTO VIDEO_TAGS(video, tag) VALUES (?1, ?2)",
+            rusqlite::params![video, tag],
+        )
+        .unwrap();
+    }
+
+    // Child names below the tag, sorted
+    fn children(db: &Connection, parent: usize) -> Vec<String> {
+        let mut names: Vec<String> = get_tags(db)
+            .unwrap()
+            .values()
+            .filter(|t| t.parent() == Some(parent))
+            .map(|t| t.name().to_string())
+            .collect();
+        names.sort();
+        names
+    }
+
+    fn child(db: &Connection, parent: usize, name: &str) -> usize {
+        get_tags(db)
+            .unwrap()
+            .values()
+            .find(|t| t.parent() == Some(parent) && t.name() == name)
+            .unwrap()
+            .id()
+    }
+
+    #[test]
+    fn merge_tags_merges_children_with_the_same_name() {
+        let db = open_in_memory();
+        let shows = tag(&db, "shows", None);
+        let series = tag(&db, "series", None);
+        let drama = tag(&db, "drama", Some(shows));
+        tag(&db, "comedy", Some(shows));
+        let series_drama = tag(&db, "drama", Some(series));
+        tag(&db, "crime", Some(series_drama));
+        tag(&db, "anime", Some(series));
+        tag_video(&db, "a", drama);
+        tag_video(&db, "b", series_drama);
+        tag_video(&db, "c", series);
+
+        merge_tags(&db, series, shows).unwrap();
+
+        let tags = get_tags(&db).unwrap();
+        assert!(!tags.contains_key(&series));
+        assert!(!tags.contains_key(&series_drama));
+        assert_eq!(children(&db, shows), ["anime", "comedy", "drama"]);
+        assert_eq!(child(&db, shows, "drama"), drama);
+        assert_eq!(children(&db, drama), ["crime"]);
+        assert_eq!(get_tagged_videos(&db, &[drama]).unwrap(), ["a", "b"]);
+        assert_eq!(get_tagged_videos(&db, &[shows]).unwrap(), ["c"]);
+        assert!(get_tagged_videos(&db, &[series, series_drama])
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn tags_with_the_same_parent_need_different_names() {
+        let db = open_in_memory();
+        let shows = tag(&db, "shows", None);
+        tag(&db, "drama", Some(shows));
+
+        assert!(add_tag(&db, "drama", Some(shows), None, None).is_err());
+        assert!(add_tag(&db, "shows", None, None, None).is_err());
+        assert!(add_tag(&db, "drama", None, None, None).is_ok());
+    }
+
+    #[test]
+    fn upgrade_keeps_the_rows_of_the_last_release() {
+        let mut db = Connection::open_in_memory().unwrap();
+        for sql in [
+            "CREATE TABLE PATHS (id Integer PRIMARY KEY AUTOINCREMENT, path TEXT NOT NULL)",
+            "CREATE TABLE VIDEOS (id TEXT PRIMARY KEY, name TEXT, rating INTEGER, notes TEXT, watched INTEGER, category INTEGER)",
+            "CREATE TABLE VIDEO_CACHE (path TEXT PRIMARY KEY, size NUMBER, id TEXT)",
+            "INSERT INTO PATHS(path) VALUES ('/v')",
+            "INSERT INTO VIDEO_CACHE(path, size, id) VALUES ('/v/a.mkv', 1, 'a')",
+        ] {
+            db.execute(sql, []).unwrap();
+        }
+        db.pragma_update(None, "user_version", 3).unwrap();
+
+        upgrade_database(&mut db, 3).unwrap();
+
+        let version: u32 = db
+            .pragma_query_value(None, "user_version", |row| row.get(0))
+            .unwrap();
+        assert_eq!(version, 17);
+        assert_eq!(get_paths(&db).unwrap(), ["/v"]);
+        let cache = get_video_cache_items(&db).unwrap();
+        assert_eq!(cache.get("/v/a.mkv").map(|v| v.id()), Some("a"));
+        assert!(add_tag(&db, "shows", None, None, None).is_ok());
+        assert!(add_tag(&db, "shows", None, None, None).is_err());
+    }
+
+    #[test]
+    fn rekey_videos_keeps_the_history_of_collapsed_ids() {
+        let mut db = open_in_memory();
+        for (video, time) in [("a", 1), ("b", 2), ("b", 3)] {
+            db.execute(
+                "INSERT INTO WATCH_HISTORY(video, kind, time, session) VALUES (?1, 'played', ?2, 's')",
+                rusqlite::params![video, time],
+            )
+            .unwrap();
+        }
+        let changes = [
+            ("/v/a.mkv".to_string(), "a".to_string(), "c".to_string()),
+            ("/v/b.mkv".to_string(), "b".to_string(), "c".to_string()),
+        ];
+
+        rekey_videos(&mut db, &changes).unwrap();
+
+        let count = |video: &str| -> usize {
+            db.query_row(
+                "SELECT COUNT(*) FROM WATCH_HISTORY WHERE video = ?1",
+                [video],
+                |row| row.get(0),
+            )
+            .unwrap()
+        };
+        assert_eq!(count("c"), 3);
+        assert_eq!(count("a") + count("b"), 0);
+    }
+
+    #[test]
+    fn rekey_videos_copies_ids_still_in_the_cache() {
+        let mut db = open_in_memory();
+        for (path, id) in [("/v/a.mkv", "a"), ("/w/a.mkv", "a")] {
+            db.execute(
+                "INSERT INTO VIDEO_CACHE(path, size, id, video) VALUES (?1, 1, ?2, 1)",
+                [path, id],
+            )
+            .unwrap();
+        }
+        db.execute(
+            "INSERT INTO WATCH_HISTORY(video, kind, time, session) VALUES ('a', 'played', 1, 's')",
+            [],
+        )
+        .unwrap();
+        db.execute(
+            "INSERT INTO RESUME(video, position, updated) VALUES ('a', 10, 1)",
+            [],
+        )
+        .unwrap();
+        add_metadata_import(&db, "a").unwrap();
+        let changes = [("/v/a.mkv".to_string(), "a".to_string(), "b".to_string())];
+
+        rekey_videos(&mut db, &changes).unwrap();
+
+        let count = |table: &str, video: &str| -> usize {
+            db.query_row(
+                &format!("SELECT COUNT(*) FROM {} WHERE video = ?1", table),
+                [video],
+                |row| row.get(0),
+            )
+            .unwrap()
+        };
+        for table in ["WATCH_HISTORY", "RESUME", "METADATA_IMPORTS"] {
+            assert_eq!((count(table, "a"), count(table, "b")), (1, 1), "{}", table);
+        }
+
+        let changes = [("/w/a.mkv".to_string(), "a".to_string(), "b".to_string())];
+        rekey_videos(&mut db, &changes).unwrap();
+
+        for table in ["WATCH_HISTORY", "RESUME", "METADATA_IMPORTS"] {
+            assert_eq!((count(table, "a"), count(table, "b")), (0, 1), "{}", table);
+        }
+    }
+
+    #[test]
+    fn cache_items_with_path_only_match_the_folder() {
+        let mut db = open_in_memory();
+        for path in [
+            "/v/a_b",
+            "/v/a_b/x.mkv",
+            "/v/a_b2/y.mkv",
+            "/v/axb/z.mkv",
+            "/v/a%/w.mkv",
+        ] {
+            db.execute(
+                "INSERT INTO VIDEO_CACHE(path, size, id, video) VALUES (?1, 1, 'a', 1)",
+                [path],
+            )
+            .unwrap();
+        }
+        let mut items = |path: &str| {
+            let mut items = get_cache_items_with_path(&mut db, path).unwrap();
+            items.sort();
+            items
+        };
+        assert_eq!(items("/v/a_b"), ["/v/a_b", "/v/a_b/x.mkv"]);
+        assert_eq!(items("/v/a%"), ["/v/a%/w.mkv"]);
+        assert!(items("/v/a").is_empty());
+    }
+
+    #[test]
+    fn running_jobs_come_back_with_their_position() {
+        let db = open_in_memory();
+        let scan = add_job(&db, JobKind::FileScan, Some("/v")).unwrap();
+        let done = add_job(&db, JobKind::GetFolders, None).unwrap();
+        update_job_position(&db, scan, Path::new("/v/b.mkv")).unwrap();
+        update_job_status(&db, done, JobStatus::Finished).unwrap();
+
+        let jobs = get_running_jobs(&db).unwrap();
+
+        assert_eq!(jobs.len(), 1);
+        assert_eq!(jobs[0].id(), scan);
+        assert_eq!(jobs[0].kind(), JobKind::FileScan);
+        assert_eq!(jobs[0].path().map(String::as_str), Some("/v"));
+        assert_eq!(jobs[0].position(), Some(Path::new("/v/b.mkv")));
+    }
+
+    #[test]
+    fn finished_jobs_are_pruned_by_age() {
+        let db = open_in_memory();
+        let old = add_job(&db, JobKind::FileScan, Some("/v")).unwrap();
+        let running = add_job(&db, JobKind::FileScan, Some("/w")).unwrap();
+        update_job_status(&db, old, JobStatus::Cancelled).unwrap();
+        db.execute("UPDATE SCAN_JOBS SET updated = 0", []).unwrap();
+
+        assert_eq!(prune_jobs(&db).unwrap(), 1);
+        let jobs = get_running_jobs(&db).unwrap();
+        assert_eq!(jobs.iter().map(Job::id).collect::<Vec<_>>(), [running]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn scan_reports_keep_unreadable_and_skipped_files() {
+        use crate::filescan::FileScan;
+        use crate::EmitProgress;
+
+        let root = std::env::temp_dir().join(format!("vidlib-report-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(root.join(".hidden")).unwrap();
+        std::os::unix::fs::symlink(root.join("missing.mkv"), root.join("broken.mkv")).unwrap();
+        let mut scan = FileScan::new(&root, None);
+        scan.run(&|_: EmitProgress| {}).unwrap();
+        let db = open_in_memory();
+
+        add_scan_report(&db, &scan.into_report()).unwrap();
+
+        let reports = get_scan_reports(&db, &root.display().to_string()).unwrap();
+        assert_eq!(reports.len(), 1);
+        let entries: Vec<(&PathBuf, ScanIssue)> = reports[0]
+            .entries()
+            .iter()
+            .map(|e| (e.path(), e.issue()))
+            .collect();
+        assert_eq!(
+            entries,
+            [
+                (&root.join(".hidden"), ScanIssue::Skipped),
+                (&root.join("broken.mkv"), ScanIssue::Unreadable),
+            ]
+        );
+        assert_eq!(reports[0].entries()[0].message(), "Hidden folder");
+        assert!(reports[0].entries()[1]
+            .message()
+            .starts_with("Broken symbolic link"));
+    }
+
+    #[test]
+    fn media_info_round_trip() {
+        let db = open_in_memory();
+        save_media_info(
+            &db,
+            "a",
+            Path::new("/v/a.mkv"),
+            100,
+            Some(7),
+            &media_info(60.0),
+        )
+        .unwrap();
+
+        let (path, size, mtime, info) = get_media_info(&db, "a").unwrap().unwrap();
+        assert_eq!(path, Path::new("/v/a.mkv"));
+        assert_eq!((size, mtime), (100, Some(7)));
+        assert_eq!(info.duration(), Some(60.0));
+        assert_eq!((info.width(), info.height()), (Some(1920), Some(1080)));
+        assert_eq!(info.codec_id(), Some(27));
+        assert!(get_media_info(&db, "b").unwrap().is_none());
+        assert_eq!(get_media_durations(&db).unwrap().get("a"), Some(&60.0));
+    }
+
+    #[test]
+    fn media_info_of_an_older_version_is_missing() {
+        let db = open_in_memory();
+        save_media_info(
+            &db,
+            "a",
+            Path::new("/v/a.mkv"),
+            100,
+            None,
+            &media_info(60.0),
+        )
+        .unwrap();
+        db.execute("UPDATE MEDIA_INFO SET version = 0", []).unwrap();
+
+        assert!(get_media_info(&db, "a").unwrap().is_none());
+    }
+
+    #[test]
+    fn metadata_imports_are_taken_once() {
+        let db = open_in_memory();
+        add_metadata_import(&db, "a").unwrap();
+        add_metadata_import(&db, "a").unwrap();
+
+        assert!(take_metadata_import(&db, "a").unwrap());
+        assert!(!take_metadata_import(&db, "a").unwrap());
+        assert!(!take_metadata_import(&db, "b").unwrap());
+    }
+
+    #[test]
+    fn media_info_is_dropped_when_its_file_changes() {
+        let db = open_in_memory();
+        let a = "/v/a.mkv".to_string();
+        let copy = "/w/a.mkv".to_string();
+        let item = |size, mtime| VideoCacheItem::new(size, Some(mtime), "a".to_string(), true);
+        add_video_cache(&db, &a, &item(100, 7));
+        add_video_cache(&db, &copy, &item(100, 8));
+        save_media_info(&db, "a", Path::new(&a), 100, Some(7), &media_info(60.0)).unwrap();
+
+        // Another copy changing leaves the info alone
+        assert!(add_video_cache(&db, &copy, &item(100, 9)).is_empty());
+        assert!(add_video_cache(&db, &a, &item(100, 7)).is_empty());
+        assert!(get_media_info(&db, "a").unwrap().is_some());
+
+        assert_eq!(add_video_cache(&db, &a, &item(120, 9)), ["a"]);
+        assert!(get_media_info(&db, "a").unwrap().is_none());
+    }
+}
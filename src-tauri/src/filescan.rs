@@ -1,19 +1,22 @@
-use std::collections::hash_map::DefaultHasher;
-use std::collections::HashMap;
-use std::fs::{read_dir, read_link, symlink_metadata, DirEntry, File};
-use std::hash::{Hash, Hasher};
-use std::io::{BufReader, Read, Seek, SeekFrom};
-use std::os::windows::prelude::MetadataExt;
+use std::collections::{HashMap, HashSet};
+use std::fs::{read_dir, DirEntry, Metadata};
+use std::panic::{self, AssertUnwindSafe};
 use std::path::{Path, PathBuf};
+use std::sync::{mpsc, Mutex};
+use std::thread;
+use std::time::UNIX_EPOCH;
 
 use serde::{Deserialize, Serialize};
-use xxhash_rust::xxh3::Xxh3;
+use tauri::AppHandle;
+use xxhash_rust::xxh3::xxh3_64;
 
+use crate::identity::IdentityStrategy;
+use crate::jobs::{Checkpoint, Job};
+use crate::scanreport::{ScanIssue, ScanReport, ScanReportEntry};
+use crate::scanrules::{ScanRule, ScanRules};
 use crate::state::{VideoCache, VideoCacheItem};
-use crate::video::{is_video, VideoEntry};
-use crate::EmitProgress;
-
-const CHUNK_SIZE: u64 = 1 * 1024 * 1024;
+use crate::video::{has_video_extension, is_video, VideoEntry};
+use crate::{util, EmitProgress};
 
 #[derive(Deserialize, Serialize, Clone)]
 pub struct VideoFile {
@@ -23,60 +26,32 @@ pub struct VideoFile {
     depth: usize,
     video: Option<VideoEntry>,
     watched: bool,
+    #[serde(default = "default_locations")]
+    locations: usize,
+}
+
+fn default_locations() -> usize {
+    1
 }
 
 impl VideoFile {
-    pub fn new<P: AsRef<Path>>(path: P, depth: usize, c: &mut Option<&mut VideoCache>) -> Self {
+    pub fn new<P: AsRef<Path>>(path: P, depth: usize, id: String) -> Self {
         let path_ref = path.as_ref().to_owned();
-        let name = path_ref.file_name().unwrap().to_str().unwrap().to_string();
-        let hash = VideoFile::hash_file(&path_ref, c);
+        let name = path_ref
+            .file_name()
+            .map(|n| n.to_string_lossy().to_string())
+            .unwrap_or_default();
         Self {
-            id: hash,
+            id,
             path: path_ref,
             name,
             depth,
             video: None,
             watched: false,
+            locations: 1,
         }
     }
 
-    fn hash_file(path_ref: &PathBuf, c: &mut Option<&mut VideoCache>) -> String {
-        let file = File::open(path_ref).expect("Failed to open file");
-        let file_size = file.metadata().expect("Failed to get file metadata").len();
-        if let Some(v) = c.as_ref().and_then(|c| c.get_video(path_ref)) {
-            if file_size == v.filesize() {
-                return v.id().into();
-            } else if let Some(c) = c.as_mut() {
-                c.delete_video(path_ref)
-            }
-        }
-
-        let mut reader = BufReader::new(file);
-        let mut hasher = Xxh3::new();
-        let mut buffer = Vec::new();
-
-        if file_size > CHUNK_SIZE {
-            reader
-                .by_ref()
-                .take(CHUNK_SIZE)
-                .read_to_end(&mut buffer)
-                .unwrap();
-            hasher.update(&buffer);
-            reader.seek(SeekFrom::End(-(CHUNK_SIZE as i64))).unwrap();
-            reader.read_to_end(&mut buffer).unwrap();
-            hasher.update(&buffer);
-        } else {
-            reader.read_to_end(&mut buffer).unwrap();
-            hasher.update(&buffer);
-        }
-
-        let id = format!("{:x}", hasher.finish());
-        if let Some(c) = c.as_mut() {
-            c.add_video(path_ref, VideoCacheItem::new(file_size, id.clone()))
-        }
-        id
-    }
-
     pub fn name(&self) -> &str {
         &self.name
     }
@@ -93,6 +68,14 @@ impl VideoFile {
         self.watched = watched;
     }
 
+    pub fn watched(&self) -> bool {
+        self.watched
+    }
+
+    pub(crate) fn set_depth(&mut self, depth: usize) {
+        self.depth = depth;
+    }
+
     pub(crate) fn update_meta(&mut self, p0: Option<&VideoEntry>) {
         if let Some(e) = p0 {
             let _ = &self.set_watched(e.watched());
@@ -100,6 +83,25 @@ impl VideoFile {
     }
 }
 
+#[derive(Deserialize, Serialize, Clone)]
+pub struct VideoMove {
+    id: String,
+    from: PathBuf,
+    to: PathBuf,
+}
+
+impl VideoMove {
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+    pub fn from(&self) -> &PathBuf {
+        &self.from
+    }
+    pub fn to(&self) -> &PathBuf {
+        &self.to
+    }
+}
+
 #[derive(Deserialize, Serialize)]
 pub struct FolderInfo {
     id: String,
@@ -110,6 +112,12 @@ pub struct FolderInfo {
     empty: bool,
     depth: usize,
     watched: bool,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    moved: Vec<VideoMove>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    report: Option<ScanReport>,
+    #[serde(skip)]
+    pending: Vec<PathBuf>,
 }
 
 impl FolderInfo {
@@ -118,14 +126,10 @@ impl FolderInfo {
         let name = path_ref
             .file_name()
             .unwrap_or_else(|| path_ref.as_os_str())
-            .to_str()
-            .unwrap()
+            .to_string_lossy()
             .to_string();
-        let mut hasher = DefaultHasher::new();
-        path_ref.hash(&mut hasher);
-        let id = format!("{:x}", hasher.finish());
         Self {
-            id,
+            id: folder_id(&path_ref),
             path: path_ref,
             folders: Vec::new(),
             videos: Vec::new(),
@@ -133,9 +137,45 @@ impl FolderInfo {
             empty: true,
             depth,
             watched: false,
+            moved: Vec::new(),
+            report: None,
+            pending: Vec::new(),
         }
     }
 
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+    pub fn path(&self) -> &PathBuf {
+        &self.path
+    }
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+    pub fn depth(&self) -> usize {
+        self.depth
+    }
+    pub fn is_empty(&self) -> bool {
+        self.empty
+    }
+    pub fn watched(&self) -> bool {
+        self.watched
+    }
+    pub fn folders(&self) -> &Vec<FolderInfo> {
+        &self.folders
+    }
+    pub fn videos(&self) -> &Vec<VideoFile> {
+        &self.videos
+    }
+
+    pub fn moved(&self) -> &[VideoMove] {
+        &self.moved
+    }
+
+    pub fn set_report(&mut self, report: ScanReport) {
+        self.report = Some(report);
+    }
+
     pub fn push_folder(&mut self, folder: FolderInfo) {
         self.folders.push(folder);
     }
@@ -144,58 +184,198 @@ impl FolderInfo {
         self.videos.push(video);
     }
 
-    fn count_files_in_folder(&self, emitter: &impl Fn(EmitProgress)) -> usize {
-        let count = read_dir(&self.path).map_or(0, |entries| {
-            entries
-                .filter_map(|entry| entry.ok())
-                .filter(|entry| {
-                    let path = if symlink_metadata(entry.path())
-                        .unwrap()
-                        .file_type()
-                        .is_symlink()
-                    {
-                        read_link(entry.path()).unwrap()
-                    } else {
-                        entry.path()
+    // Returns the videos whose path did not hold their id before this scan
+    pub fn read_folder(
+        &mut self,
+        c: &mut Option<&mut VideoCache>,
+        workers: usize,
+        settings: &ScanSettings,
+        checkpoint: &mut Option<Checkpoint>,
+        report: &mut ScanReport,
+        emitter: &impl Fn(EmitProgress),
+    ) -> Vec<(PathBuf, String)> {
+        let mut files = Vec::new();
+        let rules = ScanRules::new(&self.path, &settings.rules);
+        let mut traversal = Traversal::new(settings.follow_symlinks);
+        let job = checkpoint.as_ref().map(|c| c.job().clone());
+        traversal.enter(&self.path);
+        self.collect_files(&mut files, &rules, &mut traversal, job.as_ref(), emitter);
+        report.extend(traversal.entries);
+        // Files the rules leave out now are no longer part of the library
+        if let Some(c) = c.as_mut() {
+            c.get_videos_in(&self.path)
+                .into_iter()
+                .filter(|(p, _)| p.ancestors().any(|a| traversal.ignored.contains(a)))
+                .for_each(|(p, _)| c.delete_video(p));
+        }
+        // Files are probed in path order so a checkpoint can save how far the
+        // scan got. A resumed scan takes the files up to there from the cache.
+        files.sort();
+        let resume = job
+            .as_ref()
+            .and_then(Job::position)
+            .filter(|p| p.starts_with(&self.path))
+            .map(Path::to_path_buf);
+        let (resumed, jobs) = split_resumed(files, c.as_deref(), resume.as_deref());
+        if !resumed.is_empty() {
+            debug!("Resuming after {} files", resumed.len());
+        }
+        for _ in 0..resumed.len() {
+            emitter(EmitProgress {
+                total: None,
+                name: None,
+                folder: false,
+            });
+        }
+        let previous: HashMap<PathBuf, String> = jobs
+            .iter()
+            .filter_map(|(path, cached)| {
+                cached
+                    .as_ref()
+                    .filter(|v| v.is_video())
+                    .map(|v| (path.clone(), v.id().to_string()))
+            })
+            .collect();
+        let order: Vec<PathBuf> = jobs.iter().map(|(path, _)| path.clone()).collect();
+        let mut done = HashSet::new();
+        let mut next = 0;
+        let (mut probed, errors) = probe_files(
+            jobs,
+            workers,
+            settings.identity,
+            job.as_ref(),
+            emitter,
+            |path, result| {
+                done.insert(path.to_path_buf());
+                while next < order.len() && done.contains(&order[next]) {
+                    next += 1;
+                }
+                if let Some(c) = c.as_mut() {
+                    if let Some((item, true)) = result {
+                        c.add_video(path, item.clone());
+                    }
+                    if let Some(checkpoint) = checkpoint.as_mut() {
+                        let position = next.checked_sub(1).map(|i| order[i].as_path());
+                        checkpoint.processed(c, position);
+                    }
+                }
+            },
+        );
+        probed.extend(resumed);
+        report.extend(errors);
+        probed
+            .iter()
+            .filter(|(_, (item, _))| !item.is_video())
+            .for_each(|(path, _)| {
+                report.add(ScanReportEntry::new(
+                    path,
+                    ScanIssue::Rejected,
+                    "No video stream found by ffmpeg".into(),
+                ))
+            });
+        report.set_counts(
+            probed.len(),
+            probed.values().filter(|(i, _)| i.is_video()).count(),
+        );
+        report.sort();
+        self.resolve_files(&probed);
+        probed
+            .into_iter()
+            .filter(|(path, (item, _))| {
+                item.is_video() && previous.get(path).map(String::as_str) != Some(item.id())
+            })
+            .map(|(path, (item, _))| (path, item.id().to_string()))
+            .collect()
+    }
+
+    fn collect_files(
+        &mut self,
+        files: &mut Vec<PathBuf>,
+        rules: &ScanRules,
+        traversal: &mut Traversal,
+        job: Option<&Job>,
+        emitter: &impl Fn(EmitProgress),
+    ) {
+        let mut folder_paths = Vec::new();
+        match read_dir(&self.path) {
+            Ok(entries) => {
+                for entry in entries {
+                    let entry = match entry {
+                        Ok(entry) => entry,
+                        Err(e) => {
+                            traversal.add(ScanReportEntry::from_io(&self.path, &e));
+                            continue;
+                        }
                     };
-                    path.metadata().unwrap().is_file() && is_video(path)
-                })
-                .count()
-        });
+                    let path = entry.path();
+                    let Some(metadata) = traversal.metadata(&path) else {
+                        continue;
+                    };
+                    if metadata.is_file() {
+                        if !has_video_extension(&path) {
+                            continue;
+                        }
+                        if rules.is_ignored(&path, false) {
+                            traversal.ignore(&path);
+                        } else {
+                            self.pending.push(path);
+                        }
+                    } else if metadata.is_dir() {
+                        if is_hidden(&entry) {
+                            traversal.skip(&path, "Hidden folder");
+                        } else if rules.is_ignored(&path, true) {
+                            traversal.ignore(&path);
+                        } else {
+                            folder_paths.push(path);
+                        }
+                    }
+                }
+            }
+            Err(e) => traversal.add(ScanReportEntry::from_io(&self.path, &e)),
+        }
         emitter(EmitProgress {
-            total: Some(count),
+            total: Some(self.pending.len()),
             name: Some(self.path.display().to_string()),
             folder: true,
         });
-        count
+        files.extend(self.pending.iter().cloned());
+        for path in folder_paths {
+            if job.map_or(false, Job::is_cancelled) {
+                break;
+            }
+            if !traversal.enter(&path) {
+                continue;
+            }
+            let mut folder = FolderInfo::new(path, self.depth + 1);
+            folder.collect_files(files, &rules.enter(&folder.path), traversal, job, emitter);
+            self.push_folder(folder);
+        }
     }
 
-    pub fn read_folder(
-        &mut self,
-        c: &mut Option<&mut VideoCache>,
-        emitter: &impl Fn(EmitProgress),
-    ) {
-        let _ = &self.count_files_in_folder(&emitter);
-        if let Ok(entries) = read_dir(&self.path) {
-            for entry in entries.filter_map(Result::ok) {
-                let path = entry.path();
-                if path.is_file() && is_video(&path) {
-                    let video_file = VideoFile::new(path, self.depth, c);
-                    emitter(EmitProgress {
-                        name: Some(video_file.name().to_string()),
-                        total: None,
-                        folder: false,
-                    });
-                    self.push_video(video_file);
-                    self.empty = false;
-                } else if path.is_dir() && !is_hidden(&entry) {
-                    let mut folder = FolderInfo::new(path, self.depth + 1);
-                    folder.read_folder(c, emitter);
-                    self.push_folder(folder);
-                    self.empty = false;
-                }
+    fn resolve_files(&mut self, probed: &HashMap<PathBuf, (VideoCacheItem, bool)>) {
+        for path in std::mem::take(&mut self.pending) {
+            if let Some((item, _)) = probed.get(&path).filter(|(i, _)| i.is_video()) {
+                let id = item.id().to_string();
+                self.push_video(VideoFile::new(path, self.depth, id));
             }
         }
+        self.folders
+            .iter_mut()
+            .for_each(|f| f.resolve_files(probed));
+        self.empty = self.videos.is_empty() && self.folders.is_empty();
+        self.videos
+            .sort_by(|a, b| natord::compare(a.name(), b.name()));
+        self.folders
+            .sort_by(|a, b| natord::compare(&a.name, &b.name));
+    }
+
+    pub(crate) fn add_locations(&mut self, counts: &HashMap<String, usize>) {
+        self.videos
+            .iter_mut()
+            .for_each(|v| v.locations = counts.get(&v.id).copied().unwrap_or(1));
+        self.folders
+            .iter_mut()
+            .for_each(|f| f.add_locations(counts));
     }
 
     pub(crate) fn add_meta(&mut self, p0: &HashMap<String, VideoEntry>) {
@@ -213,9 +393,114 @@ impl FolderInfo {
     }
 }
 
+// Folders are identified by a hash of their normalised path. The id is
+// stored in FOLDERS, so the hash has to stay the same across builds.
+pub(crate) fn folder_id<P: AsRef<Path>>(path: P) -> String {
+    let path: PathBuf = path.as_ref().components().collect();
+    format!("{:x}", xxh3_64(path.to_string_lossy().as_bytes()))
+}
+
+#[derive(Clone)]
+pub struct ScanSettings {
+    pub identity: IdentityStrategy,
+    pub rules: Vec<ScanRule>,
+    pub follow_symlinks: bool,
+}
+
+impl Default for ScanSettings {
+    fn default() -> Self {
+        Self {
+            identity: IdentityStrategy::default(),
+            rules: Vec::new(),
+            follow_symlinks: true,
+        }
+    }
+}
+
+// Directories already walked during a scan. They are keyed by device and
+// inode so a directory reached through several links is read once and
+// symlink loops end.
+pub(crate) struct Traversal {
+    follow_symlinks: bool,
+    visited: HashSet<DirKey>,
+    ignored: HashSet<PathBuf>,
+    entries: Vec<ScanReportEntry>,
+}
+
+impl Traversal {
+    pub(crate) fn new(follow_symlinks: bool) -> Self {
+        Self {
+            follow_symlinks,
+            visited: HashSet::new(),
+            ignored: HashSet::new(),
+            entries: Vec::new(),
+        }
+    }
+
+    // Metadata of the link target, or None when the entry is a skipped or
+    // broken symlink
+    pub(crate) fn metadata(&mut self, path: &Path) -> Option<Metadata> {
+        let is_link = path
+            .symlink_metadata()
+            .map(|m| m.file_type().is_symlink())
+            .unwrap_or(false);
+        if is_link && !self.follow_symlinks {
+            self.skip(path, "Symbolic links are not followed in this library");
+            return None;
+        }
+        match path.metadata() {
+            Ok(metadata) => Some(metadata),
+            Err(e) if is_link => {
+                self.add(ScanReportEntry::new(
+                    path,
+                    ScanIssue::Unreadable,
+                    format!("Broken symbolic link: {}", e),
+                ));
+                None
+            }
+            Err(e) => {
+                self.add(ScanReportEntry::from_io(path, &e));
+                None
+            }
+        }
+    }
+
+    // Returns false when the directory was already walked
+    pub(crate) fn enter(&mut self, path: &Path) -> bool {
+        match dir_key(path) {
+            Some(key) if !self.visited.insert(key) => {
+                self.skip(path, "Folder was already scanned through another path");
+                false
+            }
+            _ => true,
+        }
+    }
+
+    pub(crate) fn ignore(&mut self, path: &Path) {
+        self.skip(path, "Ignored by scan rules");
+        self.ignored.insert(path.to_path_buf());
+    }
+
+    pub(crate) fn skip(&mut self, path: &Path, reason: &str) {
+        self.add(ScanReportEntry::new(
+            path,
+            ScanIssue::Skipped,
+            reason.to_string(),
+        ));
+    }
+
+    fn add(&mut self, entry: ScanReportEntry) {
+        self.entries.push(entry);
+    }
+}
+
 pub struct FileScan<'a> {
     pub path: PathBuf,
     cache: Option<&'a mut VideoCache>,
+    workers: usize,
+    settings: ScanSettings,
+    checkpoint: Option<Checkpoint<'a>>,
+    report: ScanReport,
 }
 
 impl<'a> FileScan<'a> {
@@ -223,26 +508,275 @@ impl<'a> FileScan<'a> {
         Self {
             path: path.as_ref().to_path_buf(),
             cache,
+            workers: util::get_scan_workers(),
+            settings: ScanSettings::default(),
+            checkpoint: None,
+            report: ScanReport::new(path, None),
         }
     }
 
-    pub fn run(&mut self, emitter: &impl Fn(EmitProgress)) -> Result<FolderInfo, &str> {
-        let is_dir = &self.path.is_dir();
-        if !is_dir {
-            return Err("Invalid path");
+    pub fn with_settings(mut self, settings: ScanSettings) -> Self {
+        self.settings = settings;
+        self
+    }
+
+    // Lets the job cancel the scan and checkpoints its progress to the
+    // database and the shared cache
+    pub fn with_job(mut self, job: &'a Job, app: &'a AppHandle) -> Self {
+        self.checkpoint = Some(Checkpoint::new(job, app));
+        self.report = ScanReport::new(&self.path, Some(job.id()));
+        self
+    }
+
+    // Failures of single files end up in the report, only a root that can't
+    // be scanned at all fails the scan
+    pub fn run(&mut self, emitter: &impl Fn(EmitProgress)) -> Result<FolderInfo, String> {
+        if let Err(e) = self.path.metadata() {
+            self.report.add(ScanReportEntry::from_io(&self.path, &e));
+            return Err(format!("Invalid path: {}", e));
+        }
+        if !self.path.is_dir() {
+            return Err("Invalid path".into());
         }
 
         let mut root = FolderInfo::new(&self.path, 0);
-        root.read_folder(&mut self.cache, emitter);
-        root.videos
-            .sort_by(|a, b| natord::compare(a.name(), b.name()));
+        let new_videos = root.read_folder(
+            &mut self.cache,
+            self.workers,
+            &self.settings,
+            &mut self.checkpoint,
+            &mut self.report,
+            emitter,
+        );
+        // A cancelled scan has not seen every file, so nothing is retired
+        if self
+            .checkpoint
+            .as_ref()
+            .map_or(false, |c| c.job().is_cancelled())
+        {
+            return Err("Scan cancelled".into());
+        }
+        if let Some(cache) = self.cache.as_mut() {
+            root.moved = retire_vanished(cache, &self.path, new_videos);
+        }
 
         Ok(root)
     }
+
+    pub fn into_report(self) -> ScanReport {
+        self.report
+    }
+}
+
+// Cache rows whose file is gone are dropped. If a video with the same id
+// showed up somewhere else during the scan it is reported as moved.
+fn retire_vanished(
+    cache: &mut VideoCache,
+    root: &Path,
+    new_videos: Vec<(PathBuf, String)>,
+) -> Vec<VideoMove> {
+    let mut moved = Vec::new();
+    let mut paths = cache.paths_by_id();
+    for (path, id) in new_videos {
+        let old = paths.get_mut(&id).and_then(|paths| {
+            let index = paths.iter().position(|p| p != &path && !p.exists())?;
+            Some(paths.swap_remove(index))
+        });
+        if let Some(old) = old {
+            debug!(
+                "Video {} moved from {} to {}",
+                id,
+                old.display(),
+                path.display()
+            );
+            cache.delete_video(&old);
+            moved.push(VideoMove {
+                id,
+                from: old,
+                to: path,
+            });
+        }
+    }
+    cache
+        .get_videos_in(root)
+        .into_iter()
+        .filter(|(p, _)| !p.exists())
+        .for_each(|(p, _)| cache.delete_video(p));
+    moved.sort_by(|a, b| a.to.cmp(&b.to));
+    moved
+}
+
+// Splits sorted files into those an interrupted scan got past, taken from the
+// cache, and those still to probe with their cached entry
+fn split_resumed(
+    files: Vec<PathBuf>,
+    cache: Option<&VideoCache>,
+    resume: Option<&Path>,
+) -> (
+    HashMap<PathBuf, (VideoCacheItem, bool)>,
+    Vec<(PathBuf, Option<VideoCacheItem>)>,
+) {
+    let mut resumed = HashMap::new();
+    let mut jobs = Vec::new();
+    for path in files {
+        let cached = cache.and_then(|c| c.get_video(&path)).cloned();
+        match cached {
+            Some(item) if resume.map_or(false, |r| path.as_path() <= r) => {
+                resumed.insert(path, (item, false));
+            }
+            cached => jobs.push((path, cached)),
+        }
+    }
+    (resumed, jobs)
+}
+
+// Probes and hashes files on a bounded pool of worker threads. Progress is
+// emitted and results are handed over on the calling thread as they come in.
+fn probe_files(
+    jobs: Vec<(PathBuf, Option<VideoCacheItem>)>,
+    workers: usize,
+    identity: IdentityStrategy,
+    job: Option<&Job>,
+    emitter: &impl Fn(EmitProgress),
+    mut on_result: impl FnMut(&Path, Option<&(VideoCacheItem, bool)>),
+) -> (
+    HashMap<PathBuf, (VideoCacheItem, bool)>,
+    Vec<ScanReportEntry>,
+) {
+    let workers = workers.max(1).min(jobs.len().max(1));
+    let queue = Mutex::new(jobs.into_iter());
+    let (result_tx, result_rx) = mpsc::channel();
+    let mut results = HashMap::new();
+    let mut errors = Vec::new();
+    thread::scope(|s| {
+        for _ in 0..workers {
+            let queue = &queue;
+            let result_tx = result_tx.clone();
+            s.spawn(move || loop {
+                if job.map_or(false, Job::is_cancelled) {
+                    break;
+                }
+                let next = queue.lock().unwrap().next();
+                let Some((path, cached)) = next else {
+                    break;
+                };
+                // A file that makes ffmpeg panic only fails itself
+                let result = panic::catch_unwind(AssertUnwindSafe(|| {
+                    probe_file(&path, cached.as_ref(), identity)
+                }))
+                .unwrap_or_else(|_| {
+                    Err(ScanReportEntry::new(
+                        &path,
+                        ScanIssue::Unreadable,
+                        "Probing the file failed".into(),
+                    ))
+                });
+                if result_tx.send((path, result)).is_err() {
+                    break;
+                }
+            });
+        }
+        drop(result_tx);
+        for (path, result) in result_rx {
+            emitter(EmitProgress {
+                name: path.file_name().map(|n| n.to_string_lossy().to_string()),
+                total: None,
+                folder: false,
+            });
+            match result {
+                Ok(r) => {
+                    on_result(&path, Some(&r));
+                    results.insert(path, r);
+                }
+                Err(e) => {
+                    on_result(&path, None);
+                    errors.push(e);
+                }
+            }
+        }
+    });
+    (results, errors)
+}
+
+// Returns the up to date cache entry for the file and whether it differs from
+// the cached one. Only new or modified files are probed with ffmpeg.
+pub(crate) fn probe_file(
+    path: &Path,
+    cached: Option<&VideoCacheItem>,
+    identity: IdentityStrategy,
+) -> Result<(VideoCacheItem, bool), ScanReportEntry> {
+    let metadata = path
+        .metadata()
+        .map_err(|e| ScanReportEntry::from_io(path, &e))?;
+    let file_size = metadata.len();
+    let mtime = modified_time(&metadata);
+    if let Some(v) = cached {
+        if v.is_current(file_size, mtime) && (!v.is_video() || !v.id().is_empty()) {
+            let item = VideoCacheItem::new(file_size, mtime, v.id().to_string(), v.is_video());
+            return Ok((item, v.mtime().is_none()));
+        }
+    }
+    let video = is_video(path);
+    let id = if video {
+        identity
+            .hash_file(path, file_size)
+            .map_err(|e| ScanReportEntry::from_io(path, &e))?
+    } else {
+        String::new()
+    };
+    Ok((VideoCacheItem::new(file_size, mtime, id, video), true))
+}
+
+pub(crate) fn probe_cached(
+    path: &Path,
+    cache: &mut VideoCache,
+    identity: IdentityStrategy,
+) -> Option<VideoCacheItem> {
+    let (item, changed) = match probe_file(path, cache.get_video(path), identity) {
+        Ok(probed) => probed,
+        Err(e) => {
+            error!("Probing {} failed: {}", path.display(), e.message());
+            return None;
+        }
+    };
+    if changed {
+        cache.add_video(path, item.clone());
+    }
+    Some(item)
+}
+
+pub(crate) fn modified_time(metadata: &Metadata) -> Option<u64> {
+    metadata
+        .modified()
+        .ok()
+        .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+        .map(|d| d.as_millis() as u64)
+}
+
+#[cfg(unix)]
+type DirKey = (u64, u64);
+
+#[cfg(unix)]
+fn dir_key(path: &Path) -> Option<DirKey> {
+    use std::os::unix::fs::MetadataExt;
+
+    path.metadata().ok().map(|m| (m.dev(), m.ino()))
+}
+
+// File ids are not exposed on stable for other platforms, the resolved
+// path stands in for them
+#[cfg(not(unix))]
+type DirKey = PathBuf;
+
+#[cfg(not(unix))]
+fn dir_key(path: &Path) -> Option<DirKey> {
+    std::fs::canonicalize(path).ok()
 }
 
 #[cfg(target_os = "windows")]
 fn is_hidden(entry: &DirEntry) -> bool {
+    use std::os::windows::fs::MetadataExt;
+
     entry
         .metadata()
         .map(|metadata| metadata.file_attributes() & 0x2 != 0)
@@ -257,3 +791,52 @@ fn is_hidden(entry: &DirEntry) -> bool {
         .map(|s| s.starts_with('.'))
         .unwrap_or(false)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cache(paths: &[&str]) -> VideoCache {
+        let mut cache = VideoCache::new();
+        for path in paths {
+            cache.add_video(
+                path,
+                VideoCacheItem::new(1, Some(1), path.to_string(), true),
+            );
+        }
+        cache.take_changes();
+        cache
+    }
+
+    fn files(paths: &[&str]) -> Vec<PathBuf> {
+        paths.iter().map(PathBuf::from).collect()
+    }
+
+    #[test]
+    fn resumed_scans_take_the_files_up_to_the_position_from_the_cache() {
+        let cache = cache(&["/v/a.mkv", "/v/b.mkv", "/v/d.mkv"]);
+        let files = files(&["/v/a.mkv", "/v/b.mkv", "/v/c.mkv", "/v/d.mkv"]);
+
+        let (resumed, jobs) = split_resumed(files, Some(&cache), Some(Path::new("/v/c.mkv")));
+
+        let mut resumed: Vec<&PathBuf> = resumed.keys().collect();
+        resumed.sort();
+        assert_eq!(resumed, [Path::new("/v/a.mkv"), Path::new("/v/b.mkv")]);
+        let jobs: Vec<(&PathBuf, bool)> = jobs.iter().map(|(p, c)| (p, c.is_some())).collect();
+        assert_eq!(
+            jobs,
+            [
+                (&PathBuf::from("/v/c.mkv"), false),
+                (&PathBuf::from("/v/d.mkv"), true)
+            ]
+        );
+    }
+
+    #[test]
+    fn scans_without_a_position_probe_every_file() {
+        let cache = cache(&["/v/a.mkv"]);
+        let (resumed, jobs) = split_resumed(files(&["/v/a.mkv"]), Some(&cache), None);
+        assert!(resumed.is_empty());
+        assert_eq!(jobs.len(), 1);
+    }
+}
@@ -1,52 +1,630 @@
-use std::path::{Path, PathBuf};
+use std::collections::{HashMap, HashSet};
+use std::path::{Component, Path, PathBuf};
 
 use serde::Serialize;
-use slab_tree::{NodeId, Tree};
+use slab_tree::{NodeId, NodeRef, RemoveBehavior, Tree, TreeBuilder};
 use tauri::{AppHandle, Manager};
 use tokio::sync::mpsc::Receiver;
 
-use crate::state::AppState;
+use crate::filescan::{folder_id, FolderInfo, VideoFile};
+use crate::jobs::{self, JobKind};
+use crate::service::ResponseType;
+use crate::state::{AppState, VideoCache};
+use crate::video::FolderEntry;
 
-#[derive(Clone, Serialize, Debug)]
+#[derive(Clone, Serialize)]
 pub struct Folder {
-    path: String,
+    // Name of the folder, one segment of its path
+    segment: String,
+    // Set for folders that were read by a scan, path segments above the
+    // library roots only have their segment
+    summary: Option<FolderSummary>,
+    #[serde(skip)]
+    videos: Vec<IndexedVideo>,
 }
 
 impl Folder {
-    pub fn new(path: String) -> Self {
-        Self { path }
+    pub fn new(segment: String) -> Self {
+        Self {
+            segment,
+            summary: None,
+            videos: Vec::new(),
+        }
+    }
+
+    pub fn segment(&self) -> &str {
+        &self.segment
     }
+}
 
-    pub fn path(&self) -> &str {
+#[derive(Clone)]
+struct IndexedVideo {
+    video: VideoFile,
+    size: u64,
+}
+
+// One folder level without its contents, so the views can expand folders on
+// demand. The total fields cover the whole subtree.
+#[derive(Clone, Serialize, Debug)]
+pub struct FolderSummary {
+    id: String,
+    path: PathBuf,
+    name: String,
+    depth: usize,
+    empty: bool,
+    watched: bool,
+    folders: usize,
+    videos: usize,
+    watched_videos: usize,
+    total_videos: usize,
+    total_watched: usize,
+    total_size: u64,
+    // Seconds, only videos whose media info was read are counted
+    total_duration: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    entry: Option<FolderEntry>,
+}
+
+impl FolderSummary {
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+    pub fn path(&self) -> &PathBuf {
         &self.path
     }
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+    pub fn total_videos(&self) -> usize {
+        self.total_videos
+    }
+    pub fn total_size(&self) -> u64 {
+        self.total_size
+    }
+    pub fn total_watched(&self) -> usize {
+        self.total_watched
+    }
+
+    fn new<P: AsRef<Path>>(path: P, depth: usize) -> Self {
+        let path = path.as_ref();
+        Self {
+            id: folder_id(path),
+            path: path.to_path_buf(),
+            name: path
+                .file_name()
+                .unwrap_or_else(|| path.as_os_str())
+                .to_string_lossy()
+                .to_string(),
+            depth,
+            empty: true,
+            watched: false,
+            folders: 0,
+            videos: 0,
+            watched_videos: 0,
+            total_videos: 0,
+            total_watched: 0,
+            total_size: 0,
+            total_duration: 0.0,
+            entry: None,
+        }
+    }
+}
+
+#[derive(Clone, Serialize)]
+pub struct FolderPage {
+    folder: FolderSummary,
+    offset: usize,
+    // Child folders and videos together, folders come first
+    total: usize,
+    folders: Vec<FolderSummary>,
+    videos: Vec<VideoFile>,
+}
+
+impl FolderPage {
+    pub fn folders(&self) -> &[FolderSummary] {
+        &self.folders
+    }
+    pub fn videos(&self) -> &[VideoFile] {
+        &self.videos
+    }
+}
+
+// Every known folder of the libraries, looked up by FolderInfo id or path.
+// Scans replace whole subtrees, watcher and media info updates change single
+// folders and refresh the totals of their ancestors.
+pub struct FolderTree {
+    tree: Tree<Folder>,
+    ids: HashMap<String, NodeId>,
+    // Folder of every copy of a video, by video id
+    video_folders: HashMap<String, Vec<NodeId>>,
+    durations: HashMap<String, f64>,
+    entries: HashMap<String, FolderEntry>,
+    // Set once every library root was scanned
+    indexed: bool,
+}
+
+impl Default for FolderTree {
+    fn default() -> Self {
+        Self {
+            tree: TreeBuilder::new()
+                .with_root(Folder::new("/".into()))
+                .build(),
+            ids: HashMap::new(),
+            video_folders: HashMap::new(),
+            durations: HashMap::new(),
+            entries: HashMap::new(),
+            indexed: false,
+        }
+    }
+}
+
+impl FolderTree {
+    pub fn new(entries: HashMap<String, FolderEntry>, durations: HashMap<String, f64>) -> Self {
+        Self {
+            entries,
+            durations,
+            ..Default::default()
+        }
+    }
+
+    pub fn is_indexed(&self) -> bool {
+        self.indexed
+    }
+
+    pub fn set_indexed(&mut self) {
+        self.indexed = true;
+    }
+
+    // Replaces everything below the scanned folder with the scan result
+    pub fn insert(&mut self, info: &FolderInfo, cache: &VideoCache) {
+        let node_id = get_tree_node(&mut self.tree, info.path());
+        self.clear_children(node_id);
+        self.fill(node_id, info, cache);
+        self.refresh_ancestors(node_id);
+    }
+
+    pub fn remove<P: AsRef<Path>>(&mut self, path: P) {
+        let Some(node_id) = find_tree_node(&self.tree, path) else {
+            return;
+        };
+        let parent = self.parent_id(node_id);
+        self.clear_children(node_id);
+        self.unindex_videos(node_id);
+        if let Some(mut node) = self.tree.get_mut(node_id) {
+            node.data().videos.clear();
+        }
+        if let Some(summary) = self
+            .tree
+            .get_mut(node_id)
+            .and_then(|mut n| n.data().summary.take())
+        {
+            self.ids.remove(&summary.id);
+        }
+        if parent.is_some() {
+            self.tree.remove(node_id, RemoveBehavior::DropChildren);
+        }
+        if let Some(parent) = parent {
+            self.refresh(parent);
+            self.refresh_ancestors(parent);
+        }
+    }
+
+    fn clear_children(&mut self, node_id: NodeId) {
+        let children: Vec<NodeId> = match self.tree.get(node_id) {
+            Some(node) => node.children().map(|c| c.node_id()).collect(),
+            None => return,
+        };
+        for child in children {
+            let nodes: Vec<(NodeId, Option<String>)> = self
+                .tree
+                .get(child)
+                .map(|c| {
+                    c.traverse_pre_order()
+                        .map(|n| (n.node_id(), n.data().summary.as_ref().map(|s| s.id.clone())))
+                        .collect()
+                })
+                .unwrap_or_default();
+            for (node_id, id) in nodes {
+                self.unindex_videos(node_id);
+                if let Some(id) = id {
+                    self.ids.remove(&id);
+                }
+            }
+            self.tree.remove(child, RemoveBehavior::DropChildren);
+        }
+    }
+
+    fn index_video(&mut self, id: &str, node_id: NodeId) {
+        self.video_folders
+            .entry(id.to_string())
+            .or_default()
+            .push(node_id);
+    }
+
+    fn unindex_video(&mut self, id: &str, node_id: NodeId) {
+        if let Some(nodes) = self.video_folders.get_mut(id) {
+            if let Some(index) = nodes.iter().position(|n| *n == node_id) {
+                nodes.swap_remove(index);
+            }
+            if nodes.is_empty() {
+                self.video_folders.remove(id);
+            }
+        }
+    }
+
+    fn unindex_videos(&mut self, node_id: NodeId) {
+        let ids: Vec<String> = match self.tree.get(node_id) {
+            Some(node) => node
+                .data()
+                .videos
+                .iter()
+                .map(|v| v.video.id.clone())
+                .collect(),
+            None => return,
+        };
+        ids.iter().for_each(|id| self.unindex_video(id, node_id));
+    }
+
+    fn fill(&mut self, node_id: NodeId, info: &FolderInfo, cache: &VideoCache) {
+        let summary = self.summary(info.path(), info.depth());
+        self.unindex_videos(node_id);
+        info.videos()
+            .iter()
+            .for_each(|v| self.index_video(&v.id, node_id));
+        if let Some(mut node) = self.tree.get_mut(node_id) {
+            let folder = node.data();
+            folder.summary = Some(summary);
+            folder.videos = info
+                .videos()
+                .iter()
+                .map(|v| IndexedVideo {
+                    video: v.clone(),
+                    size: cache.get_video(v.path()).map_or(0, |c| c.filesize()),
+                })
+                .collect();
+        }
+        self.ids.insert(info.id().to_string(), node_id);
+        for child in info.folders() {
+            let child_id = match self.tree.get_mut(node_id) {
+                Some(mut node) => node.append(Folder::new(child.name().to_string())).node_id(),
+                None => return,
+            };
+            self.fill(child_id, child, cache);
+        }
+        self.refresh(node_id);
+    }
+
+    fn summary<P: AsRef<Path>>(&self, path: P, depth: usize) -> FolderSummary {
+        let mut summary = FolderSummary::new(path, depth);
+        summary.entry = self.entries.get(&summary.id).cloned();
+        summary
+    }
+
+    fn parent_id(&self, node_id: NodeId) -> Option<NodeId> {
+        self.tree.get(node_id)?.parent().map(|p| p.node_id())
+    }
+
+    // Recomputes the counts of a folder from its videos and child folders
+    fn refresh(&mut self, node_id: NodeId) {
+        let Some(node) = self.tree.get(node_id) else {
+            return;
+        };
+        let children: Vec<&FolderSummary> = node
+            .children()
+            .filter_map(|c| c.data().summary.as_ref())
+            .collect();
+        let videos = &node.data().videos;
+        let watched_videos = videos.iter().filter(|v| v.video.watched()).count();
+        let folders = children.len();
+        let total_videos = videos.len() + children.iter().map(|c| c.total_videos).sum::<usize>();
+        let total_watched =
+            watched_videos + children.iter().map(|c| c.total_watched).sum::<usize>();
+        let total_size = videos.iter().map(|v| v.size).sum::<u64>()
+            + children.iter().map(|c| c.total_size).sum::<u64>();
+        let total_duration = videos
+            .iter()
+            .filter_map(|v| self.durations.get(&v.video.id))
+            .sum::<f64>()
+            + children.iter().map(|c| c.total_duration).sum::<f64>();
+        let video_count = videos.len();
+        let Some(mut node) = self.tree.get_mut(node_id) else {
+            return;
+        };
+        if let Some(summary) = node.data().summary.as_mut() {
+            summary.folders = folders;
+            summary.videos = video_count;
+            summary.watched_videos = watched_videos;
+            summary.watched = video_count > 0 && watched_videos == video_count;
+            summary.empty = video_count == 0 && folders == 0;
+            summary.total_videos = total_videos;
+            summary.total_watched = total_watched;
+            summary.total_size = total_size;
+            summary.total_duration = total_duration;
+        }
+    }
+
+    fn refresh_ancestors(&mut self, node_id: NodeId) {
+        let mut current = self.parent_id(node_id);
+        while let Some(id) = current {
+            self.refresh(id);
+            current = self.parent_id(id);
+        }
+    }
+
+    // Scanned folders that have no scanned parent. With a filter only folders
+    // with a matching video somewhere below them are listed.
+    pub fn roots(&self, filter: Option<&VideoFilter>) -> Vec<FolderSummary> {
+        let mut roots: Vec<FolderSummary> = self
+            .ids
+            .values()
+            .filter_map(|id| self.tree.get(*id))
+            .filter(|node| node.parent().map_or(true, |p| p.data().summary.is_none()))
+            .filter(|node| filter.map_or(true, |f| has_match(node, f)))
+            .filter_map(|node| node.data().summary.clone())
+            .collect();
+        roots.sort_by(|a, b| a.path.cmp(&b.path));
+        roots
+    }
+
+    pub fn get(&self, id: &str) -> Option<FolderSummary> {
+        self.tree.get(*self.ids.get(id)?)?.data().summary.clone()
+    }
+
+    pub fn get_parent(&self, id: &str) -> Option<FolderSummary> {
+        self.tree
+            .get(*self.ids.get(id)?)?
+            .parent()?
+            .data()
+            .summary
+            .clone()
+    }
+
+    pub fn get_by_path<P: AsRef<Path>>(&self, path: P) -> Option<FolderSummary> {
+        let node_id = find_tree_node(&self.tree, path)?;
+        self.tree.get(node_id)?.data().summary.clone()
+    }
+
+    pub fn get_children(
+        &self,
+        id: &str,
+        offset: usize,
+        limit: usize,
+        filter: Option<&VideoFilter>,
+    ) -> Option<FolderPage> {
+        let node = self.tree.get(*self.ids.get(id)?)?;
+        let folder = node.data().summary.clone()?;
+        let folders: Vec<&FolderSummary> = node
+            .children()
+            .filter(|c| filter.map_or(true, |f| has_match(c, f)))
+            .filter_map(|c| c.data().summary.as_ref())
+            .collect();
+        let videos: Vec<&IndexedVideo> = node
+            .data()
+            .videos
+            .iter()
+            .filter(|v| filter.map_or(true, |f| f(&v.video)))
+            .collect();
+        let total = folders.len() + videos.len();
+        let end = offset.saturating_add(limit).min(total);
+        let start = offset.min(end);
+        Some(FolderPage {
+            folder,
+            offset: start,
+            total,
+            folders: folders[start.min(folders.len())..end.min(folders.len())]
+                .iter()
+                .map(|f| (*f).clone())
+                .collect(),
+            videos: videos[start.saturating_sub(folders.len())..end.saturating_sub(folders.len())]
+                .iter()
+                .map(|v| v.video.clone())
+                .collect(),
+        })
+    }
+
+    // Folders that hold a copy of the video
+    fn folders_with_video(&self, id: &str) -> Vec<NodeId> {
+        let mut node_ids: Vec<NodeId> = Vec::new();
+        for node_id in self.video_folders.get(id).into_iter().flatten() {
+            if !node_ids.contains(node_id) {
+                node_ids.push(*node_id);
+            }
+        }
+        node_ids
+    }
+
+    pub fn set_watched(&mut self, id: &str, watched: bool) {
+        self.set_watched_all(&HashSet::from([id.to_string()]), watched);
+    }
+
+    // Marks many videos at once, every touched folder is refreshed once
+    pub fn set_watched_all(&mut self, ids: &HashSet<String>, watched: bool) {
+        let mut node_ids: Vec<NodeId> = Vec::new();
+        for node_id in ids.iter().flat_map(|id| self.folders_with_video(id)) {
+            if !node_ids.contains(&node_id) {
+                node_ids.push(node_id);
+            }
+        }
+        for node_id in node_ids {
+            if let Some(mut node) = self.tree.get_mut(node_id) {
+                node.data()
+                    .videos
+                    .iter_mut()
+                    .filter(|v| ids.contains(&v.video.id))
+                    .for_each(|v| v.video.set_watched(watched));
+            }
+            self.refresh(node_id);
+            self.refresh_ancestors(node_id);
+        }
+    }
+
+    // Every video of the folder and the folders below it
+    pub fn get_subtree_videos(&self, id: &str) -> Option<Vec<VideoFile>> {
+        let node = self.tree.get(*self.ids.get(id)?)?;
+        Some(
+            node.traverse_pre_order()
+                .flat_map(|n| n.data().videos.iter().map(|v| v.video.clone()))
+                .collect(),
+        )
+    }
+
+    pub fn set_duration(&mut self, id: &str, duration: f64) {
+        if self.durations.insert(id.to_string(), duration) == Some(duration) {
+            return;
+        }
+        for node_id in self.folders_with_video(id) {
+            self.refresh(node_id);
+            self.refresh_ancestors(node_id);
+        }
+    }
+
+    pub fn get_entry(&self, id: &str) -> Option<&FolderEntry> {
+        self.entries.get(id)
+    }
+
+    pub fn set_entry(&mut self, id: &str, entry: FolderEntry) {
+        if let Some(mut node) = self.ids.get(id).and_then(|n| self.tree.get_mut(*n)) {
+            if let Some(summary) = node.data().summary.as_mut() {
+                summary.entry = Some(entry.clone());
+            }
+        }
+        self.entries.insert(id.to_string(), entry);
+    }
+
+    // Follows database::move_folder_entries after a folder was moved
+    pub fn move_entries(&mut self, changes: &[(String, String)]) {
+        let moved: Vec<(String, FolderEntry)> = changes
+            .iter()
+            .filter_map(|(old, new)| Some((new.clone(), self.entries.remove(old)?)))
+            .collect();
+        moved
+            .into_iter()
+            .for_each(|(id, entry)| self.set_entry(&id, entry));
+    }
+
+    // Adds or replaces a video found outside of a full scan. Missing folders
+    // between the video and the closest known folder are created.
+    pub fn add_video(&mut self, mut video: VideoFile, size: u64) {
+        let Some(parent) = video.path().parent().map(Path::to_path_buf) else {
+            return;
+        };
+        let Some(node_id) = self.known_folder(&parent) else {
+            return;
+        };
+        self.remove_video(video.path());
+        let depth = self
+            .tree
+            .get(node_id)
+            .and_then(|n| n.data().summary.as_ref().map(|s| s.depth))
+            .unwrap_or(0);
+        video.set_depth(depth);
+        self.index_video(&video.id, node_id);
+        if let Some(mut node) = self.tree.get_mut(node_id) {
+            let videos = &mut node.data().videos;
+            videos.push(IndexedVideo { video, size });
+            videos.sort_by(|a, b| natord::compare(a.video.name(), b.video.name()));
+        }
+        self.refresh(node_id);
+        self.refresh_ancestors(node_id);
+    }
+
+    pub fn remove_video<P: AsRef<Path>>(&mut self, path: P) -> Option<VideoFile> {
+        let path = path.as_ref();
+        let node_id = find_tree_node(&self.tree, path.parent()?)?;
+        let removed = {
+            let mut node = self.tree.get_mut(node_id)?;
+            let videos = &mut node.data().videos;
+            let index = videos.iter().position(|v| v.video.path() == path)?;
+            videos.remove(index)
+        };
+        self.unindex_video(&removed.video.id, node_id);
+        self.refresh(node_id);
+        self.refresh_ancestors(node_id);
+        Some(removed.video)
+    }
+
+    pub fn move_video<P: AsRef<Path>, Q: AsRef<Path>>(&mut self, from: P, to: Q) {
+        let Some(old) = self.remove_video(from) else {
+            return;
+        };
+        let size = to.as_ref().metadata().map(|m| m.len()).unwrap_or(0);
+        let mut video = VideoFile::new(to, 0, old.id.clone());
+        video.set_watched(old.watched());
+        self.add_video(video, size);
+    }
+
+    // Node of the folder, creating it and any missing folders above it when
+    // one of its ancestors is a known folder
+    fn known_folder(&mut self, path: &Path) -> Option<NodeId> {
+        if let Some(node_id) = find_tree_node(&self.tree, path) {
+            if self.tree.get(node_id)?.data().summary.is_some() {
+                return Some(node_id);
+            }
+        }
+        let parent = self.known_folder(path.parent()?)?;
+        let depth = self.tree.get(parent)?.data().summary.as_ref()?.depth + 1;
+        let node_id = get_tree_node(&mut self.tree, path);
+        let summary = self.summary(path, depth);
+        self.ids.insert(summary.id.clone(), node_id);
+        self.tree.get_mut(node_id)?.data().summary = Some(summary);
+        Some(node_id)
+    }
 }
 
-pub fn scan_folder<P: AsRef<Path>>(path: P) {}
+// Limits folder listings to some of the videos, e.g. those of a category
+pub type VideoFilter<'a> = dyn Fn(&VideoFile) -> bool + 'a;
+
+fn has_match(node: &NodeRef<Folder>, filter: &VideoFilter) -> bool {
+    node.traverse_pre_order()
+        .any(|n| n.data().videos.iter().any(|v| filter(&v.video)))
+}
 
 #[derive(Clone, Serialize)]
 pub struct FolderInfoEmitEvent {
-    media_info: Folder,
+    folder: FolderSummary,
 }
 
 impl FolderInfoEmitEvent {
-    pub fn new(media_info: Folder) -> Self {
-        Self { media_info }
+    pub fn new(folder: FolderSummary) -> Self {
+        Self { folder }
     }
 }
 
+// Rescans library roots one at a time as jobs, which replace their part of
+// the index and save their reports
 pub(crate) async fn process_folder_output_channels(
     app: &AppHandle,
     mut folder_output_rx: Receiver<PathBuf>,
 ) -> Result<(), anyhow::Error> {
     debug!("Folder output channel started");
-    let state = app.state::<AppState>();
     while let Some(input) = folder_output_rx.recv().await {
         debug!("Folder output message received");
-        let mut tree_mutex = state.folders.lock().unwrap();
-        let tree = tree_mutex.as_mut().unwrap();
-        let node_id = get_tree_node(tree, &input);
-        debug!("Media info output message send for: {}", input.display());
+        let path = input.display().to_string();
+        let job = match jobs::start_job(app, JobKind::FileScan, Some(path.clone())) {
+            Ok(job) => job,
+            Err(e) => {
+                error!("Folder scan of {} can't be started: {}", path, e);
+                continue;
+            }
+        };
+        let response = jobs::run_job(app.clone(), job, move |app, job| {
+            crate::scan_path(app, job, path)
+        })
+        .await;
+        if response.result != ResponseType::Success {
+            continue;
+        }
+        let summary = app
+            .state::<AppState>()
+            .folders
+            .lock()
+            .unwrap()
+            .as_ref()
+            .and_then(|folders| folders.get_by_path(&input));
+        if let Some(summary) = summary {
+            let _ = app.emit_all("folder_indexed", FolderInfoEmitEvent::new(summary));
+            debug!("Folder index updated for: {}", input.display());
+        }
     }
 
     Ok(())
@@ -54,12 +632,12 @@ pub(crate) async fn process_folder_output_channels(
 
 fn get_tree_node<P: AsRef<Path>>(tree: &mut Tree<Folder>, path: P) -> NodeId {
     let mut root_id = tree.root_id().unwrap();
-    for p in path.as_ref().display().to_string().split('\\') {
+    for p in path_segments(path.as_ref()) {
         let new_root_id = match tree
             .get(root_id)
             .unwrap()
             .children()
-            .find(|n| n.data().path == p)
+            .find(|n| n.data().segment == p)
         {
             None => {
                 let new_folder = Folder::new(p.into());
@@ -71,3 +649,197 @@ fn get_tree_node<P: AsRef<Path>>(tree: &mut Tree<Folder>, path: P) -> NodeId {
     }
     root_id
 }
+
+fn find_tree_node<P: AsRef<Path>>(tree: &Tree<Folder>, path: P) -> Option<NodeId> {
+    let mut root_id = tree.root_id()?;
+    for p in path_segments(path.as_ref()) {
+        root_id = tree
+            .get(root_id)?
+            .children()
+            .find(|n| n.data().segment == p)?
+            .node_id();
+    }
+    Some(root_id)
+}
+
+fn path_segments(path: &Path) -> Vec<String> {
+    path.components()
+        .filter_map(|c| match c {
+            Component::Prefix(prefix) => Some(prefix.as_os_str().to_string_lossy().to_string()),
+            Component::Normal(name) => Some(name.to_string_lossy().to_string()),
+            Component::RootDir | Component::CurDir | Component::ParentDir => None,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::VideoCacheItem;
+
+    // /v with a.mkv, /v/s with s.mkv and /v/s/t with t.mkv, every video is
+    // 10 bytes
+    fn library() -> (FolderTree, VideoCache) {
+        let mut cache = VideoCache::new();
+        let mut root = FolderInfo::new("/v", 0);
+        let mut s = FolderInfo::new("/v/s", 1);
+        let mut t = FolderInfo::new("/v/s/t", 2);
+        for (folder, path, depth) in [
+            (&mut root, "/v/a.mkv", 0),
+            (&mut s, "/v/s/s.mkv", 1),
+            (&mut t, "/v/s/t/t.mkv", 2),
+        ] {
+            let id = Path::new(path)
+                .file_stem()
+                .unwrap()
+                .to_string_lossy()
+                .to_string();
+            cache.add_video(path, VideoCacheItem::new(10, None, id.clone(), true));
+            folder.push_video(VideoFile::new(path, depth, id));
+        }
+        cache.take_changes();
+        s.push_folder(t);
+        root.push_folder(s);
+        let mut folders = FolderTree::default();
+        folders.insert(&root, &cache);
+        (folders, cache)
+    }
+
+    fn summary(folders: &FolderTree, path: &str) -> FolderSummary {
+        folders.get(&folder_id(path)).unwrap()
+    }
+
+    fn video_names(videos: &[VideoFile]) -> Vec<&str> {
+        videos.iter().map(VideoFile::name).collect()
+    }
+
+    #[test]
+    fn children_are_listed_one_level_at_a_time() {
+        let (folders, _) = library();
+
+        let page = folders.get_children(&folder_id("/v"), 0, 10, None).unwrap();
+
+        assert_eq!(page.total, 2);
+        assert_eq!(
+            page.folders()
+                .iter()
+                .map(FolderSummary::name)
+                .collect::<Vec<_>>(),
+            ["s"]
+        );
+        assert_eq!(video_names(page.videos()), ["a.mkv"]);
+        assert_eq!(page.folders()[0].folders, 1);
+        assert_eq!(page.folders()[0].total_videos(), 2);
+    }
+
+    #[test]
+    fn children_are_paged_folders_first() {
+        let (folders, _) = library();
+
+        let first = folders.get_children(&folder_id("/v"), 0, 1, None).unwrap();
+        let second = folders.get_children(&folder_id("/v"), 1, 1, None).unwrap();
+
+        assert_eq!((first.folders().len(), first.videos().len()), (1, 0));
+        assert_eq!((second.folders().len(), second.videos().len()), (0, 1));
+        assert!(folders
+            .get_children(&folder_id("/v"), 5, 1, None)
+            .unwrap()
+            .videos()
+            .is_empty());
+    }
+
+    #[test]
+    fn roots_are_the_scanned_folders_without_a_scanned_parent() {
+        let (folders, _) = library();
+        let roots = folders.roots(None);
+        assert_eq!(roots.len(), 1);
+        assert_eq!(roots[0].path(), Path::new("/v"));
+        assert_eq!((roots[0].total_videos(), roots[0].total_size()), (3, 30));
+    }
+
+    #[test]
+    fn totals_follow_added_and_removed_videos() {
+        let (mut folders, _) = library();
+
+        folders.add_video(VideoFile::new("/v/s/t/u/n.mkv", 0, "n".into()), 5);
+
+        let u = summary(&folders, "/v/s/t/u");
+        assert_eq!((u.depth, u.total_videos()), (3, 1));
+        assert_eq!(summary(&folders, "/v/s").total_videos(), 3);
+        let root = summary(&folders, "/v");
+        assert_eq!((root.total_videos(), root.total_size()), (4, 35));
+
+        let removed = folders.remove_video("/v/s/s.mkv").unwrap();
+
+        assert_eq!(removed.id, "s");
+        assert_eq!(summary(&folders, "/v/s").videos, 0);
+        let root = summary(&folders, "/v");
+        assert_eq!((root.total_videos(), root.total_size()), (3, 25));
+        assert!(folders.remove_video("/v/s/s.mkv").is_none());
+    }
+
+    #[test]
+    fn removed_folders_drop_their_videos() {
+        let (mut folders, _) = library();
+
+        folders.remove("/v/s");
+
+        assert!(folders.get(&folder_id("/v/s")).is_none());
+        assert!(folders.get(&folder_id("/v/s/t")).is_none());
+        assert_eq!(summary(&folders, "/v").total_videos(), 1);
+        assert!(folders.folders_with_video("t").is_empty());
+    }
+
+    #[test]
+    fn moved_videos_keep_their_watched_state() {
+        let (mut folders, _) = library();
+        folders.set_watched("a", true);
+
+        folders.move_video("/v/a.mkv", "/v/s/t/a.mkv");
+
+        let t = folders
+            .get_children(&folder_id("/v/s/t"), 0, 10, None)
+            .unwrap();
+        assert_eq!(video_names(t.videos()), ["a.mkv", "t.mkv"]);
+        assert!(t.videos()[0].watched());
+        assert_eq!(summary(&folders, "/v/s/t").total_watched, 1);
+        assert_eq!(summary(&folders, "/v").videos, 0);
+        assert_eq!(summary(&folders, "/v").total_watched, 1);
+
+        folders.set_watched("a", false);
+
+        assert_eq!(summary(&folders, "/v").total_watched, 0);
+        assert_eq!(
+            folders.folders_with_video("a"),
+            [find_tree_node(&folders.tree, "/v/s/t").unwrap()]
+        );
+    }
+
+    #[test]
+    fn every_copy_of_a_video_is_updated() {
+        let (mut folders, _) = library();
+        folders.add_video(VideoFile::new("/v/s/copy.mkv", 0, "a".into()), 10);
+
+        folders.set_watched_all(&HashSet::from(["a".to_string()]), true);
+        folders.set_duration("a", 60.0);
+
+        let root = summary(&folders, "/v");
+        assert_eq!(root.total_watched, 2);
+        assert_eq!(root.total_duration, 120.0);
+        assert_eq!(summary(&folders, "/v/s").total_duration, 60.0);
+    }
+
+    #[test]
+    fn rescans_replace_the_videos_of_a_folder() {
+        let (mut folders, cache) = library();
+        let mut s = FolderInfo::new("/v/s", 1);
+        s.push_video(VideoFile::new("/v/s/s.mkv", 1, "s".into()));
+
+        folders.insert(&s, &cache);
+
+        assert!(folders.get(&folder_id("/v/s/t")).is_none());
+        assert!(folders.folders_with_video("t").is_empty());
+        assert_eq!(folders.folders_with_video("s").len(), 1);
+        assert_eq!(summary(&folders, "/v").total_videos(), 2);
+    }
+}
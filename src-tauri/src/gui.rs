@@ -1,39 +1,60 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::path::{Path, PathBuf};
 
 use native_dialog::FileDialog;
 use rusqlite::Connection;
+use serde::Serialize;
+use tauri::{AppHandle, Manager};
 
-use crate::filescan::{FileScan, FolderInfo, VideoFile};
+use crate::filescan::{modified_time, FileScan, FolderInfo, ScanSettings, VideoFile};
+use crate::folderscan::{FolderSummary, FolderTree};
+use crate::history::{WatchEvent, WatchEventKind, WatchSession, WatchStats};
+use crate::identity::IdentityStrategy;
+use crate::jobs::Job;
+use crate::scanreport::ScanReport;
+use crate::scanrules::ScanRule;
 use crate::service::{wrap_failure, wrap_success, Response, ResponseType};
-use crate::state::VideoCache;
-use crate::video::VideoEntry;
-use crate::{database, EmitProgress};
+use crate::state::{AppState, VideoCache, VideoCacheItem};
+use crate::video::{
+    FolderEntry, VideoCategory, VideoChange, VideoEntry, VideoLocation, VideoSelection, VideoTag,
+};
+use crate::{database, mediainfo, thumbnail, EmitProgress};
 
+// Probes on the given copy of the cache without holding any lock, the app
+// state is only locked to save the report and to read the video entries
 pub fn file_scan(
+    app: &AppHandle,
     path: String,
+    settings: ScanSettings,
     cache: &mut VideoCache,
-    x: &HashMap<String, VideoEntry>,
+    job: &Job,
     emitter: impl Fn(EmitProgress),
-) -> Result<Response<FolderInfo>, ()> {
-    let mut scan = FileScan::new(Path::new(path.as_str()), Some(cache));
+) -> Response<FolderInfo> {
+    let mut scan = FileScan::new(Path::new(path.as_str()), Some(&mut *cache))
+        .with_settings(settings)
+        .with_job(job, app);
     let result = scan.run(&emitter);
-    let response = match result {
+    let report = scan.into_report();
+    let state = app.state::<AppState>();
+    save_report(state.db.lock().unwrap().as_ref().unwrap(), &report);
+    match result {
         Ok(mut folder_info) => {
-            folder_info.add_meta(x);
+            folder_info.add_meta(state.videos.lock().unwrap().as_ref().unwrap());
+            folder_info.add_locations(&cache.location_counts());
+            folder_info.set_report(report);
             Response {
                 result: ResponseType::Success,
                 response: Some(folder_info),
                 error: None,
             }
         }
+        Err(_) if job.is_cancelled() => canceled(),
         Err(error) => Response {
             result: ResponseType::Failure,
             response: None,
             error: Some(error.to_string()),
         },
-    };
-    Ok(response)
+    }
 }
 
 pub fn select_folder() -> Result<Response<PathBuf>, ()> {
@@ -54,26 +75,58 @@ pub fn select_folder() -> Result<Response<PathBuf>, ()> {
 }
 
 pub fn get_folders(
-    folders: &[String],
+    app: &AppHandle,
+    folders: &[(String, ScanSettings)],
     cache: &mut VideoCache,
-    entries: &HashMap<String, VideoEntry>,
+    job: &Job,
     emitter: impl Fn(EmitProgress),
-) -> Result<Response<Vec<FolderInfo>>, ()> {
+) -> Response<Vec<FolderInfo>> {
+    let state = app.state::<AppState>();
     let mut folder_infos = Vec::new();
-    for folder in folders.iter() {
+    for (folder, settings) in folders.iter() {
         let path = Path::new(&folder);
-        let mut scan = FileScan::new(path, Some(cache));
+        let mut scan = FileScan::new(path, Some(&mut *cache))
+            .with_settings(settings.clone())
+            .with_job(job, app);
         let folder_scan = scan.run(&emitter);
+        let report = scan.into_report();
+        save_report(state.db.lock().unwrap().as_ref().unwrap(), &report);
+        if job.is_cancelled() {
+            return canceled();
+        }
         if let Ok(mut folder_info) = folder_scan {
-            folder_info.add_meta(entries);
+            folder_info.add_meta(state.videos.lock().unwrap().as_ref().unwrap());
+            folder_info.set_report(report);
             folder_infos.push(folder_info);
         }
     }
-    Ok(Response {
+    let counts = cache.location_counts();
+    folder_infos
+        .iter_mut()
+        .for_each(|f| f.add_locations(&counts));
+    Response {
         result: ResponseType::Success,
         response: Some(folder_infos),
         error: None,
-    })
+    }
+}
+
+fn save_report(db: &Connection, report: &ScanReport) {
+    if let Err(e) = database::add_scan_report(db, report) {
+        error!(
+            "Scan report of {} can't be saved: {}",
+            report.path().display(),
+            e
+        );
+    }
+}
+
+fn canceled<T>() -> Response<T> {
+    Response {
+        result: ResponseType::Canceled,
+        response: None,
+        error: None,
+    }
 }
 
 pub fn get_video(
@@ -84,6 +137,12 @@ pub fn get_video(
     let e = videos.entry(video.id.clone()).or_insert_with(|| {
         let new_video = VideoEntry::new(video.name().clone().to_string(), 0, "".to_string(), false);
         database::add_video(connection, &video.id, &new_video).expect("Add video failed");
+        // The tags are imported with the media info, the file isn't opened here
+        if mediainfo::import_tags_enabled(connection) {
+            if let Err(e) = database::add_metadata_import(connection, &video.id) {
+                error!("Metadata import can't be queued for {}: {}", video.id, e);
+            }
+        }
         new_video
     });
     video.set_video(Some(e.clone()));
@@ -158,7 +217,398 @@ pub(crate) fn update_notes(
     Ok(wrap_success(n.to_owned()))
 }
 
-pub(crate) fn validate_path(db: &Connection, path: &str) -> Result<bool, Response<bool>> {
+#[derive(Clone, Serialize)]
+pub struct VideoUpdate {
+    id: String,
+    video: VideoEntry,
+}
+
+// Applies one change to every selected video in a single transaction. Videos
+// without an entry yet get one, like get_video does.
+pub(crate) fn update_videos(
+    db: &Connection,
+    cache: &VideoCache,
+    videos: &mut HashMap<String, VideoEntry>,
+    folders: &mut FolderTree,
+    session: &WatchSession,
+    selection: &VideoSelection,
+    change: &VideoChange,
+) -> Response<Vec<VideoUpdate>> {
+    let files = match selection {
+        VideoSelection::Ids(ids) => {
+            let paths = cache.paths_by_id();
+            let missing: Vec<&str> = ids
+                .iter()
+                .filter(|id| !paths.contains_key(*id))
+                .map(String::as_str)
+                .collect();
+            if !missing.is_empty() {
+                return wrap_failure(format!("Videos not found: {}", missing.join(", ")));
+            }
+            ids.iter()
+                .map(|id| VideoFile::new(&paths[id][0], 0, id.clone()))
+                .collect()
+        }
+        VideoSelection::Folder(id) => match folders.get_subtree_videos(id) {
+            Some(files) => files,
+            None => return wrap_failure("Folder not found".to_string()),
+        },
+    };
+    let category = match change {
+        VideoChange::Category(Some(id)) => match database::get_category(db, *id) {
+            Ok(Some(category)) => Some(category),
+            Ok(None) => return wrap_failure("Category not found".to_string()),
+            Err(e) => return wrap_failure(e.to_string()),
+        },
+        _ => None,
+    };
+    let tag = match change {
+        VideoChange::AddTag(id) => match database::get_tags(db).map(|mut t| t.remove(id)) {
+            Ok(Some(tag)) => Some(tag),
+            Ok(None) => return wrap_failure("Tag not found".to_string()),
+            Err(e) => return wrap_failure(e.to_string()),
+        },
+        _ => None,
+    };
+    let mut seen = HashSet::new();
+    // Videos whose watched state actually flips, only those go to the history
+    let mut toggled = HashSet::new();
+    let updates: Vec<VideoUpdate> = files
+        .iter()
+        .filter(|f| seen.insert(f.id.clone()))
+        .map(|f| {
+            let mut video = videos
+                .get(&f.id)
+                .cloned()
+                .unwrap_or_else(|| VideoEntry::new(f.name().to_string(), 0, "".to_string(), false));
+            let watched = video.watched();
+            change.apply(&mut video, category.as_ref(), tag.as_ref());
+            if video.watched() != watched {
+                toggled.insert(f.id.clone());
+            }
+            VideoUpdate {
+                id: f.id.clone(),
+                video,
+            }
+        })
+        .collect();
+    let entries: Vec<(&String, &VideoEntry)> = updates.iter().map(|u| (&u.id, &u.video)).collect();
+    if let Err(e) = database::save_videos(db, &entries) {
+        return wrap_failure(e.to_string());
+    }
+    updates.iter().for_each(|u| {
+        videos.insert(u.id.clone(), u.video.clone());
+    });
+    if let VideoChange::Watched(watched) = change {
+        folders.set_watched_all(&seen, *watched);
+        let kind = WatchEventKind::from_watched(*watched);
+        let events: Vec<WatchEvent> = updates
+            .iter()
+            .filter(|u| toggled.contains(&u.id))
+            .map(|u| WatchEvent::new(&u.id, kind, None::<&Path>, session))
+            .collect();
+        if let Err(e) = database::add_watch_events(db, &events) {
+            error!("Watch history can't be saved: {}", e);
+        }
+    }
+    wrap_success(updates)
+}
+
+// Changes the entry of an indexed folder, folders without an entry start from
+// the defaults
+pub(crate) fn update_folder_entry(
+    db: &Connection,
+    folders: &mut FolderTree,
+    id: &str,
+    update: impl FnOnce(&mut FolderEntry),
+) -> Response<FolderSummary> {
+    let Some(folder) = folders.get(id) else {
+        return wrap_failure("Folder not found".to_string());
+    };
+    let mut entry = folders.get_entry(id).cloned().unwrap_or_default();
+    update(&mut entry);
+    if let Err(e) = database::set_folder_entry(db, id, folder.path(), &entry) {
+        return wrap_failure(e.to_string());
+    }
+    folders.set_entry(id, entry);
+    match folders.get(id) {
+        Some(folder) => wrap_success(folder),
+        None => wrap_failure("Folder not found".to_string()),
+    }
+}
+
+pub(crate) fn update_category(
+    c: &Connection,
+    v: &mut HashMap<String, VideoEntry>,
+    f: &VideoFile,
+    category: Option<usize>,
+) -> Response<Option<VideoCategory>> {
+    let category = match category.map(|id| database::get_category(c, id)) {
+        Some(Ok(Some(category))) => Some(category),
+        Some(Ok(None)) => return wrap_failure("Category not found".to_string()),
+        Some(Err(e)) => return wrap_failure(e.to_string()),
+        None => None,
+    };
+    // Videos without an entry get one, the same way update_videos does it
+    let mut entry = v
+        .get(&f.id)
+        .cloned()
+        .unwrap_or_else(|| VideoEntry::new(f.name().to_string(), 0, "".to_string(), false));
+    entry.set_category(category.clone());
+    if let Err(e) = database::save_videos(c, &[(&f.id, &entry)]) {
+        return wrap_failure(e.to_string());
+    }
+    v.insert(f.id.clone(), entry);
+    wrap_success(category)
+}
+
+pub(crate) fn add_category(c: &Connection, name: &str, icon: &str) -> Response<VideoCategory> {
+    match database::add_category(c, name, icon) {
+        Ok(id) => wrap_success(VideoCategory::new(id, name.to_string(), icon.to_string())),
+        Err(e) => wrap_failure(e.to_string()),
+    }
+}
+
+// Stores the new name and icon and updates the videos of the category
+pub(crate) fn save_category(
+    c: &Connection,
+    v: &mut HashMap<String, VideoEntry>,
+    category: VideoCategory,
+) -> Response<VideoCategory> {
+    match database::save_category(c, &category) {
+        Ok(0) => return wrap_failure("Category not found".to_string()),
+        Ok(_) => {}
+        Err(e) => return wrap_failure(e.to_string()),
+    }
+    v.values_mut()
+        .filter(|e| e.category().map_or(false, |c| c.id() == category.id()))
+        .for_each(|e| e.set_category(Some(category.clone())));
+    wrap_success(category)
+}
+
+pub(crate) fn delete_category(
+    c: &Connection,
+    v: &mut HashMap<String, VideoEntry>,
+    id: usize,
+) -> Response<bool> {
+    if let Err(e) = database::delete_category(c, id) {
+        return wrap_failure(e.to_string());
+    }
+    v.values_mut()
+        .filter(|e| e.category().map_or(false, |c| c.id() == id))
+        .for_each(|e| e.set_category(None));
+    wrap_success(true)
+}
+
+#[derive(Clone, Serialize)]
+pub struct TagCount {
+    tag: VideoTag,
+    // Videos carrying the tag itself
+    videos: usize,
+    // Videos carrying the tag or one of the tags below it
+    total: usize,
+}
+
+// The tag and every tag below it
+pub(crate) fn tag_with_children(tags: &HashMap<usize, VideoTag>, id: usize) -> HashSet<usize> {
+    let mut ids = HashSet::from([id]);
+    let mut added = true;
+    while added {
+        added = false;
+        for tag in tags.values() {
+            if tag.parent().map_or(false, |p| ids.contains(&p)) && ids.insert(tag.id()) {
+                added = true;
+            }
+        }
+    }
+    ids
+}
+
+pub(crate) fn get_tags(
+    db: &Connection,
+    videos: &HashMap<String, VideoEntry>,
+) -> Response<Vec<TagCount>> {
+    let tags = match database::get_tags(db) {
+        Ok(tags) => tags,
+        Err(e) => return wrap_failure(e.to_string()),
+    };
+    let mut counts: Vec<TagCount> = tags
+        .values()
+        .map(|tag| {
+            let ids = tag_with_children(&tags, tag.id());
+            let count = |ids: &HashSet<usize>| {
+                videos
+                    .values()
+                    .filter(|v| v.tags().iter().any(|t| ids.contains(&t.id())))
+                    .count()
+            };
+            TagCount {
+                tag: tag.clone(),
+                videos: count(&HashSet::from([tag.id()])),
+                total: count(&ids),
+            }
+        })
+        .collect();
+    counts.sort_by(|a, b| a.tag.path().cmp(b.tag.path()));
+    wrap_success(counts)
+}
+
+// Creates the tags of a path like genre/comedy that don't exist yet. Colour
+// and icon go to the last tag of the path.
+pub(crate) fn add_tag(
+    db: &Connection,
+    path: &str,
+    color: Option<String>,
+    icon: Option<String>,
+) -> Response<VideoTag> {
+    let names: Vec<&str> = path.split('/').map(str::trim).collect();
+    if names.iter().any(|n| n.is_empty()) {
+        return wrap_failure("Tag names can't be empty".to_string());
+    }
+    let result = database::get_tags(db).and_then(|tags| {
+        let mut parent = None;
+        for (i, name) in names.iter().enumerate() {
+            let last = i == names.len() - 1;
+            let existing = tags
+                .values()
+                .find(|t| t.parent() == parent && t.name() == *name);
+            parent = Some(match existing {
+                Some(tag) if last && (color.is_some() || icon.is_some()) => {
+                    let tag = VideoTag::new(
+                        tag.id(),
+                        tag.name().to_string(),
+                        tag.parent(),
+                        color.clone().or_else(|| tag.color().cloned()),
+                        icon.clone().or_else(|| tag.icon().cloned()),
+                    );
+                    database::save_tag(db, &tag)?;
+                    tag.id()
+                }
+                Some(tag) => tag.id(),
+                None if last => database::add_tag(db, name, parent, color.as_ref(), icon.as_ref())?,
+                None => database::add_tag(db, name, parent, None, None)?,
+            });
+        }
+        let mut tags = database::get_tags(db)?;
+        Ok(parent.and_then(|id| tags.remove(&id)))
+    });
+    match result {
+        Ok(Some(tag)) => wrap_success(tag),
+        Ok(None) => wrap_failure("Tag not found".to_string()),
+        Err(e) => wrap_failure(e.to_string()),
+    }
+}
+
+// Renames a tag, the paths of the tags below it and of every tagged video
+// follow
+pub(crate) fn update_tag(
+    db: &Connection,
+    videos: &mut HashMap<String, VideoEntry>,
+    id: usize,
+    name: &str,
+    color: Option<String>,
+    icon: Option<String>,
+) -> Response<VideoTag> {
+    let name = name.trim();
+    if name.is_empty() || name.contains('/') {
+        return wrap_failure("Tag names can't be empty or contain /".to_string());
+    }
+    let tags = match database::get_tags(db) {
+        Ok(tags) => tags,
+        Err(e) => return wrap_failure(e.to_string()),
+    };
+    let Some(tag) = tags.get(&id) else {
+        return wrap_failure("Tag not found".to_string());
+    };
+    if tags
+        .values()
+        .any(|t| t.id() != id && t.parent() == tag.parent() && t.name() == name)
+    {
+        return wrap_failure("A tag with this name already exists".to_string());
+    }
+    let tag = VideoTag::new(id, name.to_string(), tag.parent(), color, icon);
+    if let Err(e) = database::save_tag(db, &tag) {
+        return wrap_failure(e.to_string());
+    }
+    match refresh_tags(db, videos).map(|mut tags| tags.remove(&id)) {
+        Ok(Some(tag)) => wrap_success(tag),
+        Ok(None) => wrap_failure("Tag not found".to_string()),
+        Err(e) => wrap_failure(e.to_string()),
+    }
+}
+
+// Moves the videos and child tags of a tag to another tag and deletes it
+pub(crate) fn merge_tags(
+    db: &Connection,
+    videos: &mut HashMap<String, VideoEntry>,
+    from: usize,
+    into: usize,
+) -> Response<VideoTag> {
+    let tags = match database::get_tags(db) {
+        Ok(tags) => tags,
+        Err(e) => return wrap_failure(e.to_string()),
+    };
+    if !tags.contains_key(&from) || !tags.contains_key(&into) {
+        return wrap_failure("Tag not found".to_string());
+    }
+    if tag_with_children(&tags, from).contains(&into) {
+        return wrap_failure("A tag can't be merged into itself or a tag below it".to_string());
+    }
+    if let Err(e) = database::merge_tags(db, from, into) {
+        return wrap_failure(e.to_string());
+    }
+    match refresh_tags(db, videos).map(|mut tags| tags.remove(&into)) {
+        Ok(Some(tag)) => wrap_success(tag),
+        Ok(None) => wrap_failure("Tag not found".to_string()),
+        Err(e) => wrap_failure(e.to_string()),
+    }
+}
+
+// Deletes a tag together with the tags below it
+pub(crate) fn delete_tag(
+    db: &Connection,
+    videos: &mut HashMap<String, VideoEntry>,
+    id: usize,
+) -> Response<bool> {
+    let ids: Vec<usize> = match database::get_tags(db) {
+        Ok(tags) => tag_with_children(&tags, id).into_iter().collect(),
+        Err(e) => return wrap_failure(e.to_string()),
+    };
+    if let Err(e) = database::delete_tags(db, &ids) {
+        return wrap_failure(e.to_string());
+    }
+    match refresh_tags(db, videos) {
+        Ok(_) => wrap_success(true),
+        Err(e) => wrap_failure(e.to_string()),
+    }
+}
+
+// Ids of the videos carrying the tag or one of the tags below it
+pub(crate) fn get_tagged_videos(db: &Connection, id: usize) -> Response<Vec<String>> {
+    let result = database::get_tags(db).and_then(|tags| {
+        let ids: Vec<usize> = tag_with_children(&tags, id).into_iter().collect();
+        database::get_tagged_videos(db, &ids)
+    });
+    match result {
+        Ok(videos) => wrap_success(videos),
+        Err(e) => wrap_failure(e.to_string()),
+    }
+}
+
+// Loads the tags of every video again after tags were renamed, merged or
+// deleted
+fn refresh_tags(
+    db: &Connection,
+    videos: &mut HashMap<String, VideoEntry>,
+) -> Result<HashMap<usize, VideoTag>, rusqlite::Error> {
+    let mut video_tags = database::get_video_tags(db)?;
+    videos
+        .iter_mut()
+        .for_each(|(id, e)| e.set_tags(video_tags.remove(id).unwrap_or_default()));
+    database::get_tags(db)
+}
+
+pub(crate) fn validate_path<T>(db: &Connection, path: &str) -> Result<bool, Response<T>> {
     database::get_paths(db)
         .map(|paths| paths.contains(&path.to_string()))
         .map_err(|e| wrap_failure(e.to_string()))
@@ -183,14 +633,84 @@ pub(crate) fn delete_path(
     }
 }
 
-pub(crate) fn open_video(v: VideoFile) -> Result<Response<()>, ()> {
-    let path = v.path();
+// Takes the stored locations so the files are checked without holding the
+// database
+pub(crate) fn get_locations(
+    locations: Result<Vec<(PathBuf, bool)>, rusqlite::Error>,
+) -> Result<Response<Vec<VideoLocation>>, ()> {
+    match locations {
+        Ok(locations) => Ok(wrap_success(
+            locations
+                .into_iter()
+                .map(|(path, preferred)| {
+                    let available = path.is_file();
+                    VideoLocation::new(path, preferred, available)
+                })
+                .collect(),
+        )),
+        Err(e) => Ok(wrap_failure(e.to_string())),
+    }
+}
+
+pub(crate) fn set_preferred_location(
+    connection: &Connection,
+    id: &str,
+    path: &str,
+) -> Result<Response<String>, ()> {
+    match database::set_preferred_location(connection, id, path) {
+        Ok(0) => Ok(wrap_failure("Video has no locations".into())),
+        Ok(_) => Ok(wrap_success(path.to_string())),
+        Err(e) => Ok(wrap_failure(e.to_string())),
+    }
+}
+
+// Picks the requested copy of the video, falling back to the preferred one
+pub(crate) fn resolve_location(
+    connection: &Connection,
+    v: &VideoFile,
+    location: Option<String>,
+) -> Result<PathBuf, String> {
+    let locations = database::get_locations(connection, &v.id).unwrap_or_default();
+    match location {
+        Some(l) => {
+            let l = PathBuf::from(l);
+            if &l == v.path() || locations.iter().any(|(x, _)| x == &l) {
+                Ok(l)
+            } else {
+                Err("Location does not belong to video".into())
+            }
+        }
+        None => Ok(locations
+            .iter()
+            .find(|(l, preferred)| *preferred && l.is_file())
+            .map(|(l, _)| l.clone())
+            .unwrap_or_else(|| v.path().clone())),
+    }
+}
+
+pub(crate) fn open_video(
+    connection: &Connection,
+    session: &WatchSession,
+    v: VideoFile,
+    location: Option<String>,
+) -> Result<Response<()>, ()> {
+    let path = match resolve_location(connection, &v, location) {
+        Ok(path) => path,
+        Err(e) => {
+            error!("{}", e);
+            return Ok(wrap_failure(e));
+        }
+    };
     debug!("Checking for video path: {}", path.display());
     if path.exists() && path.is_file() {
         debug!("File exists");
-        match opener::open(path) {
+        match opener::open(&path) {
             Ok(_) => {
                 debug!("File opened");
+                add_watch_event(
+                    connection,
+                    WatchEvent::new(&v.id, WatchEventKind::Played, Some(&path), session),
+                );
                 Ok(wrap_success(()))
             }
             Err(e) => {
@@ -203,3 +723,408 @@ pub(crate) fn open_video(v: VideoFile) -> Result<Response<()>, ()> {
         Ok(wrap_failure("File not found".into()))
     }
 }
+
+// A lost history entry must not fail the action that caused it
+pub(crate) fn add_watch_event(db: &Connection, event: WatchEvent) {
+    if let Err(e) = database::add_watch_events(db, &[event]) {
+        error!("Watch history can't be saved: {}", e);
+    }
+}
+
+pub(crate) fn get_watch_history(
+    db: &Connection,
+    id: Option<&str>,
+    limit: usize,
+) -> Response<Vec<WatchEvent>> {
+    match database::get_watch_history(db, id, limit) {
+        Ok(events) => wrap_success(events),
+        Err(e) => wrap_failure(e.to_string()),
+    }
+}
+
+pub(crate) fn get_watch_stats(
+    db: &Connection,
+    id: Option<&str>,
+    limit: usize,
+) -> Response<Vec<WatchStats>> {
+    match database::get_watch_stats(db, id, limit) {
+        Ok(stats) => wrap_success(stats),
+        Err(e) => wrap_failure(e.to_string()),
+    }
+}
+
+// New ids of the videos below a library root, worked out on a copy of the
+// cache
+pub(crate) struct Rekey {
+    changes: Vec<(PathBuf, String, VideoCacheItem)>,
+    unreadable: Vec<(PathBuf, String)>,
+}
+
+// Hashes the videos below the path again without holding any lock, returns
+// nothing when the job is cancelled
+pub(crate) fn hash_videos(
+    cache: &VideoCache,
+    path: &str,
+    identity: IdentityStrategy,
+    job: &Job,
+    emitter: impl Fn(EmitProgress),
+) -> Option<Rekey> {
+    let videos: Vec<(PathBuf, VideoCacheItem)> = cache
+        .get_videos_in(path)
+        .into_iter()
+        .filter(|(_, item)| item.is_video())
+        .collect();
+    emitter(EmitProgress {
+        total: Some(videos.len()),
+        name: None,
+        folder: true,
+    });
+    let mut rekey = Rekey {
+        changes: Vec::new(),
+        unreadable: Vec::new(),
+    };
+    for (p, item) in videos {
+        if job.is_cancelled() {
+            return None;
+        }
+        emitter(EmitProgress {
+            total: None,
+            name: p.file_name().map(|n| n.to_string_lossy().to_string()),
+            folder: false,
+        });
+        let hash = p.metadata().and_then(|m| {
+            identity
+                .hash_file(&p, m.len())
+                .map(|id| VideoCacheItem::new(m.len(), modified_time(&m), id, true))
+        });
+        match hash {
+            Ok(new_item) if new_item.id() != item.id() => {
+                rekey.changes.push((p, item.id().to_string(), new_item))
+            }
+            Ok(_) => {}
+            Err(e) => {
+                debug!("Dropping {} from cache: {}", p.display(), e);
+                rekey.unreadable.push((p, item.id().to_string()));
+            }
+        }
+    }
+    Some(rekey)
+}
+
+// Applies the new ids to the database and the shared cache. Files the cache
+// has seen change since they were hashed are left alone.
+pub(crate) fn set_identity_strategy(
+    db: &mut Connection,
+    cache: &mut VideoCache,
+    videos: &mut HashMap<String, VideoEntry>,
+    rekey: Rekey,
+    save_location: &Path,
+) -> Response<usize> {
+    let unchanged = |p: &Path, id: &str| cache.get_video(p).map_or(false, |i| i.id() == id);
+    let (items, changes): (Vec<_>, Vec<_>) = rekey
+        .changes
+        .into_iter()
+        .filter(|(p, old, _)| unchanged(p, old))
+        .map(|(p, old, item)| {
+            let change = (p.display().to_string(), old, item.id().to_string());
+            ((p, item), change)
+        })
+        .unzip();
+    let unreadable: Vec<PathBuf> = rekey
+        .unreadable
+        .into_iter()
+        .filter(|(p, old)| unchanged(p, old))
+        .map(|(p, _)| p)
+        .collect();
+    if let Err(e) = database::rekey_videos(db, &changes) {
+        return wrap_failure(e.to_string());
+    }
+    unreadable.iter().for_each(|p| cache.delete_video(p));
+    items
+        .into_iter()
+        .for_each(|(p, item)| cache.add_video(p, item));
+    // The media info of the old ids is dropped or moved to the new ones, their
+    // chapter thumbnails are created again for the new ids
+    let mut dropped = cache.commit(db);
+    dropped.extend(changes.iter().map(|(_, old, _)| old.clone()));
+    thumbnail::delete_chapter_thumbnails(save_location, &dropped);
+    for (_, old, new) in &changes {
+        if let Some(e) = videos.get(old).cloned() {
+            videos.entry(new.clone()).or_insert(e);
+        }
+    }
+    for (_, old, _) in &changes {
+        if cache.get_videos_by_id(old).is_empty() {
+            videos.remove(old);
+        }
+    }
+    wrap_success(changes.len())
+}
+
+pub(crate) fn get_scan_rules(db: &Connection, path: &str) -> Response<Vec<ScanRule>> {
+    match database::get_scan_rules(db, path) {
+        Ok(rules) => wrap_success(rules),
+        Err(e) => wrap_failure(e.to_string()),
+    }
+}
+
+pub(crate) fn set_scan_rules(
+    db: &mut Connection,
+    path: &str,
+    rules: Vec<ScanRule>,
+) -> Response<Vec<ScanRule>> {
+    match database::set_scan_rules(db, path, &rules) {
+        Ok(_) => wrap_success(rules),
+        Err(e) => wrap_failure(e.to_string()),
+    }
+}
+
+pub(crate) fn set_follow_symlinks(db: &Connection, path: &str, follow: bool) -> Response<bool> {
+    match database::set_follow_symlinks(db, path, follow) {
+        Ok(_) => wrap_success(follow),
+        Err(e) => wrap_failure(e.to_string()),
+    }
+}
+
+pub(crate) fn get_scan_reports(db: &Connection, path: &str) -> Response<Vec<ScanReport>> {
+    match database::get_scan_reports(db, path) {
+        Ok(reports) => wrap_success(reports),
+        Err(e) => wrap_failure(e.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::open_in_memory;
+    use crate::filescan::folder_id;
+
+    // /v with a.mkv, /v/s with s.mkv and /v/s/t with t.mkv, the video ids are
+    // the file stems
+    fn library() -> (VideoCache, FolderTree) {
+        let mut cache = VideoCache::new();
+        let mut root = FolderInfo::new("/v", 0);
+        let mut s = FolderInfo::new("/v/s", 1);
+        let mut t = FolderInfo::new("/v/s/t", 2);
+        for (folder, path, depth) in [
+            (&mut root, "/v/a.mkv", 0),
+            (&mut s, "/v/s/s.mkv", 1),
+            (&mut t, "/v/s/t/t.mkv", 2),
+        ] {
+            let id = Path::new(path)
+                .file_stem()
+                .unwrap()
+                .to_string_lossy()
+                .to_string();
+            cache.add_video(path, VideoCacheItem::new(10, None, id.clone(), true));
+            folder.push_video(VideoFile::new(path, depth, id));
+        }
+        cache.take_changes();
+        s.push_folder(t);
+        root.push_folder(s);
+        let mut folders = FolderTree::default();
+        folders.insert(&root, &cache);
+        (cache, folders)
+    }
+
+    fn is_failure<T>(response: &Response<T>) -> bool {
+        response.result == ResponseType::Failure && response.response.is_none()
+    }
+
+    #[test]
+    fn folder_entries_are_saved_for_indexed_folders() {
+        let db = open_in_memory();
+        let (_, mut folders) = library();
+        let id = folder_id("/v/s");
+
+        let response = update_folder_entry(&db, &mut folders, &id, |e| {
+            e.set_notes("season one".into());
+            e.set_rating(4);
+        });
+
+        let folder = response.response.unwrap();
+        assert_eq!(folder.id(), id);
+        let saved = database::get_folder_entries(&db).unwrap();
+        assert_eq!(saved[&id].notes(), "season one");
+        assert_eq!(saved[&id].rating(), 4);
+        assert_eq!(folders.get_entry(&id).unwrap().notes(), "season one");
+
+        update_folder_entry(&db, &mut folders, &id, |e| e.set_name(Some("S1".into())));
+
+        let saved = database::get_folder_entries(&db).unwrap();
+        assert_eq!(saved[&id].name().map(String::as_str), Some("S1"));
+        assert_eq!(saved[&id].notes(), "season one");
+    }
+
+    #[test]
+    fn entries_of_unknown_folders_are_rejected() {
+        let db = open_in_memory();
+        let (_, mut folders) = library();
+
+        let response =
+            update_folder_entry(&db, &mut folders, &folder_id("/w"), |e| e.set_rating(1));
+
+        assert!(is_failure(&response));
+        assert!(database::get_folder_entries(&db).unwrap().is_empty());
+        assert!(folders.get_entry(&folder_id("/w")).is_none());
+    }
+
+    fn update(
+        db: &Connection,
+        (cache, folders): &mut (VideoCache, FolderTree),
+        videos: &mut HashMap<String, VideoEntry>,
+        selection: VideoSelection,
+        change: VideoChange,
+    ) -> Response<Vec<VideoUpdate>> {
+        let session = WatchSession::start();
+        update_videos(db, cache, videos, folders, &session, &selection, &change)
+    }
+
+    fn watched(folders: &FolderTree) -> Vec<String> {
+        let mut ids: Vec<String> = folders
+            .get_subtree_videos(&folder_id("/v"))
+            .unwrap()
+            .into_iter()
+            .filter(VideoFile::watched)
+            .map(|v| v.id)
+            .collect();
+        ids.sort();
+        ids
+    }
+
+    #[test]
+    fn selected_videos_get_an_entry() {
+        let db = open_in_memory();
+        let mut library = library();
+        let mut videos = HashMap::new();
+        let ids = VideoSelection::Ids(vec!["a".into(), "t".into(), "a".into()]);
+
+        let response = update(&db, &mut library, &mut videos, ids, VideoChange::Rating(3));
+
+        assert_eq!(response.response.unwrap().len(), 2);
+        let saved = database::get_videos(&db).unwrap();
+        assert_eq!(saved.len(), 2);
+        assert_eq!(saved["a"].rating(), 3);
+        assert_eq!(saved["t"].name(), "t.mkv");
+        assert_eq!(videos["t"].rating(), 3);
+    }
+
+    #[test]
+    fn unknown_videos_fail_the_whole_selection() {
+        let db = open_in_memory();
+        let mut library = library();
+        let mut videos = HashMap::new();
+        let ids = VideoSelection::Ids(vec!["a".into(), "x".into(), "y".into()]);
+
+        let response = update(&db, &mut library, &mut videos, ids, VideoChange::Rating(3));
+
+        assert!(is_failure(&response));
+        assert_eq!(response.error.as_deref(), Some("Videos not found: x, y"));
+        assert!(videos.is_empty());
+        assert!(database::get_videos(&db).unwrap().is_empty());
+    }
+
+    #[test]
+    fn folder_selections_cover_the_subtree() {
+        let db = open_in_memory();
+        let mut library = library();
+        let mut videos = HashMap::new();
+        let folder = VideoSelection::Folder(folder_id("/v/s"));
+
+        let response = update(
+            &db,
+            &mut library,
+            &mut videos,
+            folder,
+            VideoChange::Watched(true),
+        );
+
+        assert_eq!(response.response.unwrap().len(), 2);
+        let folders = &library.1;
+        assert_eq!(watched(folders), ["s", "t"]);
+        assert_eq!(folders.get(&folder_id("/v/s")).unwrap().total_watched(), 2);
+        assert_eq!(folders.get(&folder_id("/v")).unwrap().total_watched(), 2);
+        assert!(videos["s"].watched() && !videos.contains_key("a"));
+        let history = database::get_watch_history(&db, None, 10).unwrap();
+        assert_eq!(history.len(), 2);
+
+        // Videos already watched don't get another history event
+        let ids = VideoSelection::Ids(vec!["a".into(), "s".into()]);
+        update(
+            &db,
+            &mut library,
+            &mut videos,
+            ids,
+            VideoChange::Watched(true),
+        );
+
+        let history = database::get_watch_history(&db, None, 10).unwrap();
+        let mut ids: Vec<&str> = history.iter().map(WatchEvent::video).collect();
+        ids.sort();
+        assert_eq!(ids, ["a", "s", "t"]);
+    }
+
+    #[test]
+    fn unknown_folders_and_categories_are_rejected() {
+        let db = open_in_memory();
+        let mut library = library();
+        let mut videos = HashMap::new();
+
+        let folder = VideoSelection::Folder(folder_id("/w"));
+        let response = update(
+            &db,
+            &mut library,
+            &mut videos,
+            folder,
+            VideoChange::Watched(true),
+        );
+        assert!(is_failure(&response));
+
+        let folder = VideoSelection::Folder(folder_id("/v"));
+        let category = VideoChange::Category(Some(99));
+        let response = update(&db, &mut library, &mut videos, folder, category);
+        assert!(is_failure(&response));
+
+        assert!(videos.is_empty());
+        assert!(database::get_videos(&db).unwrap().is_empty());
+        assert!(watched(&library.1).is_empty());
+    }
+
+    #[test]
+    fn unknown_categories_are_not_assigned() {
+        let db = open_in_memory();
+        let mut videos = HashMap::new();
+        let file = VideoFile::new("/v/a.mkv", 0, "a".into());
+
+        let response = update_category(&db, &mut videos, &file, Some(99));
+
+        assert!(is_failure(&response));
+        assert!(videos.is_empty());
+        assert!(database::get_videos(&db).unwrap().is_empty());
+    }
+
+    #[test]
+    fn category_changes_reach_the_videos() {
+        let db = open_in_memory();
+        let mut videos = HashMap::new();
+        let file = VideoFile::new("/v/a.mkv", 0, "a".into());
+        let category = add_category(&db, "Movies", "film").response.unwrap();
+
+        let assigned = update_category(&db, &mut videos, &file, Some(category.id()));
+        assert_eq!(assigned.response.unwrap().unwrap().name(), "Movies");
+
+        let renamed = VideoCategory::new(category.id(), "Films".into(), "reel".into());
+        assert!(save_category(&db, &mut videos, renamed).result == ResponseType::Success);
+        assert_eq!(videos["a"].category().unwrap().name(), "Films");
+        let saved = database::get_videos(&db).unwrap();
+        assert_eq!(saved["a"].category().unwrap().icon(), "reel");
+
+        let unknown = VideoCategory::new(99, "Shows".into(), "tv".into());
+        assert!(is_failure(&save_category(&db, &mut videos, unknown)));
+        assert_eq!(videos["a"].category().unwrap().name(), "Films");
+
+        assert!(delete_category(&db, &mut videos, category.id()).result == ResponseType::Success);
+        assert!(videos["a"].category().is_none());
+        assert!(database::get_videos(&db).unwrap()["a"].category().is_none());
+    }
+}
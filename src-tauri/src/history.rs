@@ -0,0 +1,153 @@
+use std::env;
+use std::path::{Path, PathBuf};
+use std::process;
+
+use serde::{Deserialize, Serialize};
+
+use crate::util;
+
+// Events or videos returned when the views don't ask for a number
+pub const WATCH_HISTORY_LIMIT: usize = 500;
+
+#[derive(Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum WatchEventKind {
+    // The video was opened in a player
+    Played,
+    Watched,
+    Unwatched,
+}
+
+impl WatchEventKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            WatchEventKind::Played => "played",
+            WatchEventKind::Watched => "watched",
+            WatchEventKind::Unwatched => "unwatched",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Self {
+        match name {
+            "watched" => WatchEventKind::Watched,
+            "unwatched" => WatchEventKind::Unwatched,
+            _ => WatchEventKind::Played,
+        }
+    }
+
+    pub fn from_watched(watched: bool) -> Self {
+        if watched {
+            WatchEventKind::Watched
+        } else {
+            WatchEventKind::Unwatched
+        }
+    }
+}
+
+// One run of the app, so the history shows who watched what in which sitting
+#[derive(Serialize, Clone, Debug)]
+pub struct WatchSession {
+    id: String,
+    user: Option<String>,
+}
+
+impl WatchSession {
+    pub fn start() -> Self {
+        Self {
+            id: format!("{:x}-{:x}", util::now_millis(), process::id()),
+            user: env::var("USER").or_else(|_| env::var("USERNAME")).ok(),
+        }
+    }
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct WatchEvent {
+    video: String,
+    kind: WatchEventKind,
+    // Milliseconds since the unix epoch
+    time: u64,
+    path: Option<PathBuf>,
+    session: String,
+    user: Option<String>,
+}
+
+impl WatchEvent {
+    pub fn new<P: AsRef<Path>>(
+        video: &str,
+        kind: WatchEventKind,
+        path: Option<P>,
+        session: &WatchSession,
+    ) -> Self {
+        Self::load(
+            video.to_string(),
+            kind,
+            util::now_millis(),
+            path.map(|p| p.as_ref().to_path_buf()),
+            session.id.clone(),
+            session.user.clone(),
+        )
+    }
+
+    pub fn load(
+        video: String,
+        kind: WatchEventKind,
+        time: u64,
+        path: Option<PathBuf>,
+        session: String,
+        user: Option<String>,
+    ) -> Self {
+        Self {
+            video,
+            kind,
+            time,
+            path,
+            session,
+            user,
+        }
+    }
+
+    pub fn video(&self) -> &str {
+        &self.video
+    }
+    pub fn kind(&self) -> WatchEventKind {
+        self.kind
+    }
+    pub fn time(&self) -> u64 {
+        self.time
+    }
+    pub fn path(&self) -> Option<&PathBuf> {
+        self.path.as_ref()
+    }
+    pub fn session(&self) -> &str {
+        &self.session
+    }
+    pub fn user(&self) -> Option<&String> {
+        self.user.as_ref()
+    }
+}
+
+// Summary of the history of one video. Both played and watched events count
+// as watching.
+#[derive(Serialize, Clone, Debug)]
+pub struct WatchStats {
+    video: String,
+    play_count: usize,
+    first_watched: Option<u64>,
+    last_watched: Option<u64>,
+}
+
+impl WatchStats {
+    pub fn new(
+        video: String,
+        play_count: usize,
+        first_watched: Option<u64>,
+        last_watched: Option<u64>,
+    ) -> Self {
+        Self {
+            video,
+            play_count,
+            first_watched,
+            last_watched,
+        }
+    }
+}
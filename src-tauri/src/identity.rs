@@ -0,0 +1,217 @@
+use std::fs::File;
+use std::hash::Hasher;
+use std::io::{BufReader, Read, Seek, SeekFrom};
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+use xxhash_rust::xxh3::Xxh3;
+
+const CHUNK_SIZE: u64 = 1024 * 1024;
+const SAMPLE_SIZE: u64 = 64 * 1024;
+const SAMPLE_COUNT: u64 = 16;
+
+#[derive(Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Debug, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum IdentityStrategy {
+    // First and last chunk of the file
+    #[default]
+    Partial,
+    // Whole file content
+    Full,
+    // File size and evenly spaced samples from the middle of the file
+    Sampled,
+}
+
+impl IdentityStrategy {
+    pub fn name(&self) -> &'static str {
+        match self {
+            IdentityStrategy::Partial => "partial",
+            IdentityStrategy::Full => "full",
+            IdentityStrategy::Sampled => "sampled",
+        }
+    }
+
+    pub fn from_name(name: Option<&str>) -> Self {
+        match name {
+            Some("full") => IdentityStrategy::Full,
+            Some("sampled") => IdentityStrategy::Sampled,
+            _ => IdentityStrategy::Partial,
+        }
+    }
+
+    pub fn hash_file<P: AsRef<Path>>(&self, path: P, file_size: u64) -> std::io::Result<String> {
+        let mut reader = BufReader::new(File::open(path)?);
+        let mut hasher = Xxh3::new();
+        match self {
+            IdentityStrategy::Partial => hash_partial(&mut reader, &mut hasher, file_size)?,
+            IdentityStrategy::Full => hash_full(&mut reader, &mut hasher)?,
+            IdentityStrategy::Sampled => hash_sampled(&mut reader, &mut hasher, file_size)?,
+        }
+        Ok(format!("{:x}", hasher.finish()))
+    }
+}
+
+fn hash_partial(
+    reader: &mut BufReader<File>,
+    hasher: &mut Xxh3,
+    file_size: u64,
+) -> std::io::Result<()> {
+    // The buffer is not cleared between chunks so ids stay compatible with
+    // the ones already stored in existing libraries
+    let mut buffer = Vec::new();
+    if file_size > CHUNK_SIZE {
+        reader.by_ref().take(CHUNK_SIZE).read_to_end(&mut buffer)?;
+        hasher.update(&buffer);
+        reader.seek(SeekFrom::End(-(CHUNK_SIZE as i64)))?;
+        reader.read_to_end(&mut buffer)?;
+        hasher.update(&buffer);
+    } else {
+        reader.read_to_end(&mut buffer)?;
+        hasher.update(&buffer);
+    }
+    Ok(())
+}
+
+fn hash_full(reader: &mut BufReader<File>, hasher: &mut Xxh3) -> std::io::Result<()> {
+    let mut buffer = vec![0; CHUNK_SIZE as usize];
+    loop {
+        let read = reader.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read]);
+    }
+    Ok(())
+}
+
+fn hash_sampled(
+    reader: &mut BufReader<File>,
+    hasher: &mut Xxh3,
+    file_size: u64,
+) -> std::io::Result<()> {
+    hasher.update(&file_size.to_le_bytes());
+    if file_size <= SAMPLE_SIZE * SAMPLE_COUNT * 2 {
+        return hash_full(reader, hasher);
+    }
+    let mut buffer = vec![0; SAMPLE_SIZE as usize];
+    for i in 1..=SAMPLE_COUNT {
+        let offset = file_size / (SAMPLE_COUNT + 1) * i;
+        reader.seek(SeekFrom::Start(offset))?;
+        reader.read_exact(&mut buffer)?;
+        hasher.update(&buffer);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use xxhash_rust::xxh3::xxh3_64;
+
+    use super::*;
+
+    const SMALL: usize = 1000;
+    const LARGE: usize = 3 * CHUNK_SIZE as usize;
+
+    fn content(len: usize) -> Vec<u8> {
+        (0..len).map(|i| (i % 251) as u8).collect()
+    }
+
+    fn file(name: &str, content: &[u8]) -> PathBuf {
+        let path =
+            std::env::temp_dir().join(format!("vidlib-identity-{}-{}", name, std::process::id()));
+        std::fs::write(&path, content).unwrap();
+        path
+    }
+
+    fn hash(identity: IdentityStrategy, name: &str, content: &[u8]) -> String {
+        identity
+            .hash_file(file(name, content), content.len() as u64)
+            .unwrap()
+    }
+
+    fn expected(parts: &[&[u8]]) -> String {
+        format!("{:x}", xxh3_64(&parts.concat()))
+    }
+
+    #[test]
+    fn empty_files_hash_to_the_empty_input() {
+        assert_eq!(
+            hash(IdentityStrategy::Partial, "empty", &[]),
+            "2d06800538d394c2"
+        );
+        assert_eq!(
+            hash(IdentityStrategy::Full, "empty", &[]),
+            "2d06800538d394c2"
+        );
+    }
+
+    #[test]
+    fn partial_hashes_small_files_whole() {
+        let content = content(SMALL);
+        assert_eq!(
+            hash(IdentityStrategy::Partial, "partial-small", &content),
+            expected(&[&content[..]])
+        );
+    }
+
+    // The first chunk is hashed twice, ids of existing libraries depend on it
+    #[test]
+    fn partial_hashes_the_first_and_last_chunk() {
+        let content = content(LARGE);
+        let chunk = CHUNK_SIZE as usize;
+        let (first, last) = (&content[..chunk], &content[LARGE - chunk..]);
+        assert_eq!(
+            hash(IdentityStrategy::Partial, "partial-large", &content),
+            expected(&[first, first, last])
+        );
+    }
+
+    #[test]
+    fn full_hashes_the_whole_file() {
+        for (name, len) in [("full-small", SMALL), ("full-large", LARGE)] {
+            let content = content(len);
+            assert_eq!(
+                hash(IdentityStrategy::Full, name, &content),
+                expected(&[&content[..]])
+            );
+        }
+    }
+
+    #[test]
+    fn sampled_hashes_small_files_whole() {
+        let content = content(SMALL);
+        let size = (SMALL as u64).to_le_bytes();
+        assert_eq!(
+            hash(IdentityStrategy::Sampled, "sampled-small", &content),
+            expected(&[&size[..], &content[..]])
+        );
+    }
+
+    #[test]
+    fn sampled_hashes_the_size_and_samples() {
+        let content = content(LARGE);
+        let size = (LARGE as u64).to_le_bytes();
+        let mut parts: Vec<&[u8]> = vec![&size[..]];
+        for i in 1..=SAMPLE_COUNT as usize {
+            let offset = LARGE / (SAMPLE_COUNT as usize + 1) * i;
+            parts.push(&content[offset..offset + SAMPLE_SIZE as usize]);
+        }
+        assert_eq!(
+            hash(IdentityStrategy::Sampled, "sampled-large", &content),
+            expected(&parts)
+        );
+    }
+
+    #[test]
+    fn strategies_give_different_ids_to_large_files() {
+        let content = content(LARGE);
+        let partial = hash(IdentityStrategy::Partial, "different", &content);
+        assert_ne!(partial, hash(IdentityStrategy::Full, "different", &content));
+        assert_ne!(
+            partial,
+            hash(IdentityStrategy::Sampled, "different", &content)
+        );
+    }
+}
@@ -0,0 +1,361 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use serde::{Deserialize, Serialize};
+use tauri::{AppHandle, Manager};
+
+use crate::service::{wrap_failure, Response, ResponseType};
+use crate::state::{self, AppState, EmitTotalProgress, VideoCache};
+use crate::{database, EmitProgress};
+
+// Number of probed files between two checkpoints of a running scan
+const CHECKPOINT_INTERVAL: usize = 200;
+
+#[derive(Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum JobKind {
+    FileScan,
+    AddFolder,
+    GetFolders,
+    Rekey,
+}
+
+impl JobKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            JobKind::FileScan => "file_scan",
+            JobKind::AddFolder => "add_folder",
+            JobKind::GetFolders => "get_folders",
+            JobKind::Rekey => "rekey",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "file_scan" => Some(JobKind::FileScan),
+            "add_folder" => Some(JobKind::AddFolder),
+            "get_folders" => Some(JobKind::GetFolders),
+            "rekey" => Some(JobKind::Rekey),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Serialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum JobStatus {
+    Running,
+    Finished,
+    Cancelled,
+    Failed,
+}
+
+impl JobStatus {
+    pub fn name(&self) -> &'static str {
+        match self {
+            JobStatus::Running => "running",
+            JobStatus::Finished => "finished",
+            JobStatus::Cancelled => "cancelled",
+            JobStatus::Failed => "failed",
+        }
+    }
+}
+
+#[derive(Serialize, Clone)]
+pub struct Job {
+    id: i64,
+    kind: JobKind,
+    path: Option<String>,
+    #[serde(skip)]
+    cancelled: Arc<AtomicBool>,
+    // Last file of the scan order saved at a checkpoint, the files up to it
+    // are in the cache
+    #[serde(skip)]
+    position: Option<PathBuf>,
+}
+
+impl Job {
+    pub fn new(id: i64, kind: JobKind, path: Option<String>) -> Self {
+        Self {
+            id,
+            kind,
+            path,
+            cancelled: Arc::new(AtomicBool::new(false)),
+            position: None,
+        }
+    }
+
+    pub fn with_position(mut self, position: Option<PathBuf>) -> Self {
+        self.position = position;
+        self
+    }
+
+    pub fn id(&self) -> i64 {
+        self.id
+    }
+    pub fn kind(&self) -> JobKind {
+        self.kind
+    }
+    pub fn path(&self) -> Option<&String> {
+        self.path.as_ref()
+    }
+    pub fn position(&self) -> Option<&Path> {
+        self.position.as_deref()
+    }
+
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+
+    // Emits the running total of the job, and the legacy add_progress event
+    // the scan views listen to
+    pub fn emitter<'a>(&'a self, app: &'a AppHandle) -> impl Fn(EmitProgress) + 'a {
+        let total = RefCell::new(EmitTotalProgress::new());
+        move |progress: EmitProgress| {
+            total.borrow_mut().process(progress);
+            let progress = total.borrow().clone();
+            let _ = app.emit_all("add_progress", &progress);
+            let _ = app.emit_all(
+                "job_progress",
+                JobProgressEmitEvent {
+                    id: self.id,
+                    progress,
+                },
+            );
+        }
+    }
+}
+
+// Saves what a scan has probed so far and how far it got in its file order,
+// so an interrupted scan continues after the last saved file. The database
+// and the shared cache are only locked while the probed files are merged.
+pub struct Checkpoint<'a> {
+    job: &'a Job,
+    app: &'a AppHandle,
+    probed: usize,
+}
+
+impl<'a> Checkpoint<'a> {
+    pub fn new(job: &'a Job, app: &'a AppHandle) -> Self {
+        Self {
+            job,
+            app,
+            probed: 0,
+        }
+    }
+
+    pub fn job(&self) -> &Job {
+        self.job
+    }
+
+    // position is the last file in scan order that every file before it was
+    // probed with
+    pub fn processed(&mut self, cache: &mut VideoCache, position: Option<&Path>) {
+        self.probed += 1;
+        if self.probed % CHECKPOINT_INTERVAL == 0 {
+            debug!("Job {} checkpoint at {} files", self.job.id, self.probed);
+            state::commit_video_cache(self.app, cache);
+            let state = self.app.state::<AppState>();
+            if let (Some(db), Some(position)) = (state.db.lock().unwrap().as_ref(), position) {
+                if let Err(e) = database::update_job_position(db, self.job.id, position) {
+                    error!("Job {} position can't be saved: {}", self.job.id, e);
+                }
+            }
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct JobRegistry {
+    jobs: HashMap<i64, Job>,
+}
+
+impl JobRegistry {
+    pub fn register(&mut self, job: &Job) {
+        self.jobs.insert(job.id, job.clone());
+    }
+
+    pub fn remove(&mut self, id: i64) {
+        self.jobs.remove(&id);
+    }
+
+    pub fn cancel(&self, id: i64) -> bool {
+        match self.jobs.get(&id) {
+            Some(job) => {
+                job.cancel();
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn jobs(&self) -> Vec<Job> {
+        let mut jobs: Vec<Job> = self.jobs.values().cloned().collect();
+        jobs.sort_by_key(|j| j.id);
+        jobs
+    }
+}
+
+// Events
+#[derive(Clone, Serialize)]
+pub struct JobProgressEmitEvent {
+    id: i64,
+    progress: EmitTotalProgress,
+}
+
+#[derive(Clone, Serialize)]
+pub struct JobFinishedEmitEvent {
+    id: i64,
+    status: JobStatus,
+    error: Option<String>,
+}
+
+pub fn start_job(app: &AppHandle, kind: JobKind, path: Option<String>) -> Result<Job, String> {
+    let state = app.state::<AppState>();
+    let id = {
+        let db_guard = state.db.lock().unwrap();
+        let db = db_guard.as_ref().ok_or("Database is not loaded")?;
+        database::add_job(db, kind, path.as_deref()).map_err(|e| e.to_string())?
+    };
+    let job = Job::new(id, kind, path);
+    register_job(app, &job);
+    Ok(job)
+}
+
+fn register_job(app: &AppHandle, job: &Job) {
+    debug!("Job {} started: {}", job.id, job.kind.name());
+    app.state::<AppState>().jobs.lock().unwrap().register(job);
+    let _ = app.emit_all("job_started", job);
+}
+
+// Runs the job on a blocking thread. The job row stays running until the
+// work returns, so a scan cut short by closing the app is resumed on the
+// next start.
+pub async fn run_job<T, F>(app: AppHandle, job: Job, work: F) -> Response<T>
+where
+    T: Send + 'static,
+    F: FnOnce(&AppHandle, &Job) -> Response<T> + Send + 'static,
+{
+    let id = job.id;
+    let handle = app.clone();
+    let result = tauri::async_runtime::spawn_blocking(move || {
+        let response = work(&handle, &job);
+        let status = if job.is_cancelled() {
+            JobStatus::Cancelled
+        } else if response.result == ResponseType::Failure {
+            JobStatus::Failed
+        } else {
+            JobStatus::Finished
+        };
+        (response, status)
+    })
+    .await;
+    let (response, status) = match result {
+        Ok(result) => result,
+        Err(e) => {
+            error!("Job {} failed: {}", id, e);
+            (wrap_failure(e.to_string()), JobStatus::Failed)
+        }
+    };
+    finish_job(&app, id, status, response.error.clone());
+    response
+}
+
+fn finish_job(app: &AppHandle, id: i64, status: JobStatus, error: Option<String>) {
+    debug!("Job {} {}", id, status.name());
+    let state = app.state::<AppState>();
+    state.jobs.lock().unwrap().remove(id);
+    if let Some(db) = state.db.lock().unwrap().as_ref() {
+        if let Err(e) = database::update_job_status(db, id, status) {
+            error!("Job {} status can't be saved: {}", id, e);
+        }
+    }
+    let _ = app.emit_all("job_finished", JobFinishedEmitEvent { id, status, error });
+}
+
+// Jobs that were still running when the app was closed. Jobs of paths that
+// were removed from the library since are cancelled instead, and old jobs
+// that are done are dropped.
+pub fn get_interrupted_jobs(app: &AppHandle) -> Vec<Job> {
+    let state = app.state::<AppState>();
+    let db_guard = state.db.lock().unwrap();
+    let Some(db) = db_guard.as_ref() else {
+        return Vec::new();
+    };
+    if let Err(e) = database::prune_jobs(db) {
+        error!("Old jobs can't be deleted: {}", e);
+    }
+    let jobs = match database::get_running_jobs(db) {
+        Ok(jobs) => jobs,
+        Err(e) => {
+            error!("Interrupted jobs can't be loaded: {}", e);
+            Vec::new()
+        }
+    };
+    let paths = database::get_paths(db).unwrap_or_default();
+    let (jobs, removed): (Vec<Job>, Vec<Job>) = jobs
+        .into_iter()
+        .partition(|job| job.path().map_or(true, |p| paths.contains(p)));
+    for job in removed {
+        debug!("Job {} path is no longer in the library", job.id);
+        if let Err(e) = database::update_job_status(db, job.id, JobStatus::Cancelled) {
+            error!("Job {} status can't be saved: {}", job.id, e);
+        }
+    }
+    jobs.iter().for_each(|job| register_job(app, job));
+    jobs
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn registry_cancels_running_jobs() {
+        let mut registry = JobRegistry::default();
+        let job = Job::new(2, JobKind::FileScan, Some("/v".into()));
+        registry.register(&job);
+        registry.register(&Job::new(1, JobKind::GetFolders, None));
+
+        assert_eq!(
+            registry.jobs().iter().map(Job::id).collect::<Vec<_>>(),
+            [1, 2]
+        );
+        assert!(registry.cancel(2));
+        assert!(job.is_cancelled());
+        assert!(!registry.jobs()[0].is_cancelled());
+    }
+
+    #[test]
+    fn registry_ignores_unknown_and_finished_jobs() {
+        let mut registry = JobRegistry::default();
+        let job = Job::new(1, JobKind::AddFolder, Some("/v".into()));
+        registry.register(&job);
+        registry.remove(1);
+
+        assert!(!registry.cancel(1));
+        assert!(!registry.cancel(7));
+        assert!(!job.is_cancelled());
+        assert!(registry.jobs().is_empty());
+    }
+
+    #[test]
+    fn job_kinds_round_trip_by_name() {
+        for kind in [
+            JobKind::FileScan,
+            JobKind::AddFolder,
+            JobKind::GetFolders,
+            JobKind::Rekey,
+        ] {
+            assert_eq!(JobKind::from_name(kind.name()), Some(kind));
+        }
+        assert_eq!(JobKind::from_name("unknown"), None);
+    }
+}
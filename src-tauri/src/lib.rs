@@ -0,0 +1,1603 @@
+#[macro_use]
+extern crate derive_builder;
+#[macro_use]
+extern crate log;
+
+use std::collections::{HashMap, HashSet};
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use rusqlite::Connection;
+use serde::Serialize;
+use tauri::{AppHandle, Error, Manager, State};
+
+use crate::database::{get_videos, load_database};
+use crate::filescan::{folder_id, FolderInfo, VideoFile};
+use crate::folderscan::{FolderPage, FolderSummary, FolderTree, VideoFilter};
+use crate::gui::{TagCount, VideoUpdate};
+use crate::history::{WatchEvent, WatchEventKind, WatchSession, WatchStats, WATCH_HISTORY_LIMIT};
+use crate::identity::IdentityStrategy;
+use crate::jobs::{Job, JobKind};
+use crate::mediainfo::{
+    MediaInfoQuery, MediaStream, VideoMediaInfo, VideoMediaInfoChannelMessage,
+    VideoMediaInfoEmitEvent,
+};
+use crate::player::{PlayerProfile, PlayerSettings, ResumePosition};
+use crate::scanreport::ScanReport;
+use crate::scanrules::ScanRule;
+use crate::service::{wrap_failure, wrap_success, Response, ResponseType};
+use crate::state::AppState;
+use crate::thumbnail::ThumbnailChannelMessage;
+use crate::video::{
+    FolderEntry, VideoCategory, VideoChange, VideoEntry, VideoLocation, VideoSelection, VideoTag,
+};
+
+mod database;
+pub mod filescan;
+pub mod folderscan;
+mod gui;
+mod history;
+pub mod identity;
+mod jobs;
+mod mediainfo;
+mod player;
+pub mod scanreport;
+pub mod scanrules;
+mod service;
+pub mod state;
+mod thumbnail;
+mod util;
+pub mod video;
+mod watcher;
+
+#[tauri::command]
+async fn file_scan(app: AppHandle, path: String) -> Result<Response<FolderInfo>, ()> {
+    debug!("File Scan Start");
+    let response = match jobs::start_job(&app, JobKind::FileScan, Some(path.clone())) {
+        Ok(job) => jobs::run_job(app, job, move |app, job| scan_path(app, job, path)).await,
+        Err(e) => wrap_failure(e),
+    };
+    debug!("File Scan End");
+    Ok(response)
+}
+
+#[tauri::command]
+fn select_folder() -> Result<Response<PathBuf>, ()> {
+    debug!("Select Folder Start");
+    gui::select_folder()
+}
+
+#[tauri::command]
+async fn get_folders(app: AppHandle) -> Result<Response<Vec<FolderInfo>>, Error> {
+    debug!("Get Folders Start");
+    let response = match jobs::start_job(&app, JobKind::GetFolders, None) {
+        Ok(job) => jobs::run_job(app, job, scan_folders).await,
+        Err(e) => wrap_failure(e),
+    };
+    debug!("Get Folders End");
+    Ok(response)
+}
+
+#[tauri::command]
+async fn add_folder(app: AppHandle, path: String) -> Result<Response<FolderInfo>, ()> {
+    debug!("Add Folder Start");
+    {
+        let state = app.state::<AppState>();
+        let db_guard = state.db.lock().unwrap();
+        database::add_path(db_guard.as_ref().unwrap(), &path).expect("Paths not found");
+    }
+    let response = match jobs::start_job(&app, JobKind::AddFolder, Some(path.clone())) {
+        Ok(job) => jobs::run_job(app, job, move |app, job| scan_path(app, job, path)).await,
+        Err(e) => wrap_failure(e),
+    };
+    debug!("Add Folder End");
+    Ok(response)
+}
+
+// Scans a single library root as the work of a job. The scan works on a copy
+// of the cache, the app state is only locked at checkpoints and once the
+// result is merged.
+fn scan_path(app: &AppHandle, job: &Job, path: String) -> Response<FolderInfo> {
+    let state = app.state::<AppState>();
+    let settings = database::get_scan_settings(state.db.lock().unwrap().as_ref().unwrap(), &path);
+    let mut cache = state.video_cache.lock().unwrap().clone().unwrap();
+    let response = gui::file_scan(app, path, settings, &mut cache, job, job.emitter(app));
+    state::commit_video_cache(app, &mut cache);
+    if let Some(folder_info) = response.response.as_ref() {
+        let mut folders_guard = state.folders.lock().unwrap();
+        folders_guard.as_mut().unwrap().insert(folder_info, &cache);
+    }
+    response
+}
+
+// Scans every library root as the work of a job
+fn scan_folders(app: &AppHandle, job: &Job) -> Response<Vec<FolderInfo>> {
+    let state = app.state::<AppState>();
+    let folders = database::get_paths_with_settings(state.db.lock().unwrap().as_ref().unwrap())
+        .expect("Paths not found");
+    let mut cache = state.video_cache.lock().unwrap().clone().unwrap();
+    let response = gui::get_folders(app, &folders, &mut cache, job, job.emitter(app));
+    state::commit_video_cache(app, &mut cache);
+    if let Some(folder_infos) = response.response.as_ref() {
+        let mut folders_guard = state.folders.lock().unwrap();
+        let folders = folders_guard.as_mut().unwrap();
+        folder_infos.iter().for_each(|f| folders.insert(f, &cache));
+        folders.set_indexed();
+    }
+    response
+}
+
+// Picks an interrupted job up again. The folders are walked again, the files
+// up to the position of the last checkpoint are taken from the cache and the
+// scan continues after them.
+async fn resume_job(app: AppHandle, job: Job) {
+    debug!("Resuming job {}", job.id());
+    match (job.kind(), job.path().cloned()) {
+        (JobKind::GetFolders, _) => {
+            jobs::run_job(app, job, scan_folders).await;
+        }
+        (JobKind::Rekey, Some(path)) => {
+            jobs::run_job(app, job, move |app, job| rekey_path(app, job, path)).await;
+        }
+        (_, Some(path)) => {
+            jobs::run_job(app, job, move |app, job| scan_path(app, job, path)).await;
+        }
+        (_, None) => {
+            jobs::run_job::<(), _>(app, job, |_, _| wrap_failure("Job has no path".into())).await;
+        }
+    }
+}
+
+// Library roots without their contents. The libraries are scanned first if
+// they were not all scanned since the app started. With a category or tag only
+// roots holding videos of that category or tag are listed.
+#[tauri::command]
+async fn get_folder_roots(
+    app: AppHandle,
+    category: Option<usize>,
+    tag: Option<usize>,
+) -> Result<Response<Vec<FolderSummary>>, ()> {
+    debug!("Get Folder Roots Start");
+    let indexed = {
+        let state = app.state::<AppState>();
+        let folders_guard = state.folders.lock().unwrap();
+        folders_guard.as_ref().unwrap().is_indexed()
+    };
+    if !indexed {
+        let response = match jobs::start_job(&app, JobKind::GetFolders, None) {
+            Ok(job) => jobs::run_job(app.clone(), job, scan_folders).await,
+            Err(e) => wrap_failure(e),
+        };
+        if response.result != ResponseType::Success {
+            return Ok(Response {
+                result: response.result,
+                response: None,
+                error: response.error,
+            });
+        }
+    }
+    let state = app.state::<AppState>();
+    let tags = tag.map(|t| tag_filter(state.db.lock().unwrap().as_ref().unwrap(), t));
+    let videos_guard = state.videos.lock().unwrap();
+    let folders_guard = state.folders.lock().unwrap();
+    let filter = video_filter(videos_guard.as_ref().unwrap(), category, tags);
+    Ok(wrap_success(
+        folders_guard.as_ref().unwrap().roots(filter.as_deref()),
+    ))
+}
+
+// The tag and the tags below it
+fn tag_filter(db: &Connection, tag: usize) -> HashSet<usize> {
+    match database::get_tags(db) {
+        Ok(tags) => gui::tag_with_children(&tags, tag),
+        Err(e) => {
+            error!("Tags can't be loaded: {}", e);
+            HashSet::from([tag])
+        }
+    }
+}
+
+fn video_filter(
+    videos: &HashMap<String, VideoEntry>,
+    category: Option<usize>,
+    tags: Option<HashSet<usize>>,
+) -> Option<Box<VideoFilter<'_>>> {
+    if category.is_none() && tags.is_none() {
+        return None;
+    }
+    Some(Box::new(move |video: &VideoFile| {
+        let Some(entry) = videos.get(&video.id) else {
+            return false;
+        };
+        category.map_or(true, |c| entry.category().map_or(false, |e| e.id() == c))
+            && tags
+                .as_ref()
+                .map_or(true, |t| entry.tags().iter().any(|e| t.contains(&e.id())))
+    }))
+}
+
+#[tauri::command]
+fn get_folder_children(
+    state: State<AppState>,
+    folder_id: String,
+    offset: usize,
+    limit: usize,
+    category: Option<usize>,
+    tag: Option<usize>,
+) -> Result<Response<FolderPage>, ()> {
+    debug!("Get Folder Children Start");
+    let tags = tag.map(|t| tag_filter(state.db.lock().unwrap().as_ref().unwrap(), t));
+    let videos_guard = state.videos.lock().unwrap();
+    let folders_guard = state.folders.lock().unwrap();
+    let filter = video_filter(videos_guard.as_ref().unwrap(), category, tags);
+    match folders_guard
+        .as_ref()
+        .unwrap()
+        .get_children(&folder_id, offset, limit, filter.as_deref())
+    {
+        Some(page) => Ok(wrap_success(page)),
+        None => Ok(wrap_failure("Folder not found".to_string())),
+    }
+}
+
+#[tauri::command]
+fn get_folder(state: State<AppState>, folder_id: String) -> Result<Response<FolderSummary>, ()> {
+    debug!("Get Folder Start");
+    let folders_guard = state.folders.lock().unwrap();
+    match folders_guard.as_ref().unwrap().get(&folder_id) {
+        Some(folder) => Ok(wrap_success(folder)),
+        None => Ok(wrap_failure("Folder not found".to_string())),
+    }
+}
+
+#[tauri::command]
+fn get_folder_parent(
+    state: State<AppState>,
+    folder_id: String,
+) -> Result<Response<FolderSummary>, ()> {
+    debug!("Get Folder Parent Start");
+    let folders_guard = state.folders.lock().unwrap();
+    match folders_guard.as_ref().unwrap().get_parent(&folder_id) {
+        Some(folder) => Ok(wrap_success(folder)),
+        None => Ok(wrap_failure("Folder has no parent".to_string())),
+    }
+}
+
+#[tauri::command]
+fn get_folder_by_path(state: State<AppState>, path: String) -> Result<Response<FolderSummary>, ()> {
+    debug!("Get Folder By Path Start");
+    let folders_guard = state.folders.lock().unwrap();
+    match folders_guard.as_ref().unwrap().get_by_path(&path) {
+        Some(folder) => Ok(wrap_success(folder)),
+        None => Ok(wrap_failure("Folder not found".to_string())),
+    }
+}
+
+#[tauri::command]
+fn cancel_job(state: State<AppState>, id: i64) -> Result<Response<bool>, ()> {
+    debug!("Cancel Job Start");
+    if state.jobs.lock().unwrap().cancel(id) {
+        Ok(wrap_success(true))
+    } else {
+        Ok(wrap_failure("Job not found".to_string()))
+    }
+}
+
+#[tauri::command]
+fn get_jobs(state: State<AppState>) -> Result<Response<Vec<Job>>, ()> {
+    debug!("Get Jobs Start");
+    Ok(wrap_success(state.jobs.lock().unwrap().jobs()))
+}
+
+#[tauri::command]
+fn get_video(
+    app: AppHandle,
+    state: State<AppState>,
+    mut video: VideoFile,
+) -> Result<Response<VideoFile>, ()> {
+    debug!("Get Video Start");
+    let connection_guard = state.db.lock().unwrap();
+    let connection = connection_guard.as_ref().unwrap();
+    let mut videos_guard = state.videos.lock().unwrap();
+    let videos = videos_guard.as_mut().unwrap();
+    let created = !videos.contains_key(&video.id);
+    let response = gui::get_video(&mut video, videos, connection);
+    // The embedded metadata of a new video is imported once its media info
+    // was read, which happens outside of the locks
+    if created && mediainfo::import_tags_enabled(connection) {
+        let message =
+            VideoMediaInfoChannelMessage::new(video.id.clone(), video.path().clone(), None);
+        tauri::async_runtime::spawn(async move {
+            let state = app.state::<AppState>();
+            if let Err(e) = state.mediainfo_channel.lock().await.send(message).await {
+                error!("Sending message to media info channel failed {}", e);
+            }
+        });
+    }
+    response
+}
+
+#[tauri::command]
+async fn get_thumbnail(
+    state: State<'_, AppState>,
+    id: String,
+    path: &str,
+) -> Result<Response<Option<Vec<PathBuf>>>, ()> {
+    debug!("Get Thumbnails Start");
+    let thumbnail = thumbnail::find_thumbnail_path_in_cache(&state, &id).await;
+    if let Some(t) = thumbnail {
+        debug!(
+            "Thumbnail found at location {}. Returning",
+            t.get(0)
+                .map(|p| p.display().to_string())
+                .unwrap_or("".into())
+        );
+        Ok(wrap_success(Some(t)))
+    } else {
+        debug!("Thumbnail not found, it will be created");
+        let pathbuf = PathBuf::from(path);
+        if pathbuf.is_file() {
+            debug!("Sending message to Thumbnail Channel");
+            let message = ThumbnailChannelMessage::new(pathbuf, id);
+            match state.thumbnail_channel.lock().await.send(message).await {
+                Ok(_) => Ok(wrap_success(None)),
+                Err(e) => {
+                    debug!("Sending message to thumbnail channel failed {}", e);
+                    Ok(wrap_failure(e.to_string()))
+                }
+            }
+        } else {
+            error!("Given path is not a file.");
+            Ok(wrap_failure("Given path is not a file.".into()))
+        }
+    }
+}
+
+#[tauri::command]
+fn set_video_rating(
+    state: State<AppState>,
+    file: VideoFile,
+    rating: usize,
+) -> Result<Response<usize>, ()> {
+    debug!("Set Video Rating Start");
+    let connection_guard = state.db.lock().unwrap();
+    let connection = connection_guard.as_ref().unwrap();
+    let mut videos_guard = state.videos.lock().unwrap();
+    let videos = videos_guard.as_mut().unwrap();
+    gui::update_rating(connection, videos, file, rating)
+}
+
+fn emit_folder_watched(app: &AppHandle, path: &Path, watched: bool) {
+    if let Some(parent) = path.parent() {
+        let event_name = format!("update_watch_{}", folder_id(parent));
+        let _ = app.emit_all(event_name.as_str(), EmitWatched { watched });
+    }
+}
+
+#[tauri::command]
+fn set_watched(app: AppHandle, file: VideoFile, watched: bool) -> Result<Response<bool>, ()> {
+    debug!("Set Watched Start");
+    update_watched(&app, file, watched)
+}
+
+// Also used by the player integration when a video is watched far enough
+fn update_watched(app: &AppHandle, file: VideoFile, watched: bool) -> Result<Response<bool>, ()> {
+    let state = app.state::<AppState>();
+    let connection_guard = state.db.lock().unwrap();
+    let connection = connection_guard.as_ref().unwrap();
+    let mut videos_guard = state.videos.lock().unwrap();
+    let videos = videos_guard.as_mut().unwrap();
+    let _ = app.emit_all(
+        format!("update_watch_{}", file.id.clone()).as_str(),
+        EmitWatched { watched },
+    );
+    let mut paths = database::get_locations(connection, &file.id)
+        .map(|l| l.into_iter().map(|(p, _)| p).collect::<Vec<_>>())
+        .unwrap_or_default();
+    if !paths.contains(file.path()) {
+        paths.push(file.path().clone());
+    }
+    paths
+        .iter()
+        .for_each(|p| emit_folder_watched(app, p, watched));
+    if let Some(folders) = state.folders.lock().unwrap().as_mut() {
+        folders.set_watched(&file.id, watched);
+    }
+    // Only an existing entry whose state flips counts as a toggle
+    if videos
+        .get(&file.id)
+        .map_or(false, |v| v.watched() != watched)
+    {
+        gui::add_watch_event(
+            connection,
+            WatchEvent::new(
+                &file.id,
+                WatchEventKind::from_watched(watched),
+                Some(file.path()),
+                &state.session,
+            ),
+        );
+    }
+    gui::update_watched(connection, videos, file, watched)
+}
+
+#[tauri::command]
+fn set_video_name(
+    state: State<AppState>,
+    file: VideoFile,
+    name: String,
+) -> Result<Response<String>, ()> {
+    debug!("Set Video Name Start");
+    gui::update_name(
+        state.db.lock().unwrap().as_ref().unwrap(),
+        state.videos.lock().unwrap().as_mut().unwrap(),
+        &file,
+        &name,
+    )
+}
+
+#[tauri::command]
+fn set_video_notes(
+    state: State<AppState>,
+    file: VideoFile,
+    notes: String,
+) -> Result<Response<String>, ()> {
+    debug!("Set Video Notes Start");
+    gui::update_notes(
+        state.db.lock().unwrap().as_ref().unwrap(),
+        state.videos.lock().unwrap().as_mut().unwrap(),
+        &file,
+        &notes,
+    )
+}
+
+// Applies one change, e.g. watched, rating or a tag, to many videos at once
+// and sends one videos_updated event instead of an event per video
+#[tauri::command]
+fn update_videos(
+    app: AppHandle,
+    state: State<AppState>,
+    selection: VideoSelection,
+    change: VideoChange,
+) -> Result<Response<usize>, ()> {
+    debug!("Update Videos Start");
+    let db_guard = state.db.lock().unwrap();
+    let cache_guard = state.video_cache.lock().unwrap();
+    let mut videos_guard = state.videos.lock().unwrap();
+    let mut folders_guard = state.folders.lock().unwrap();
+    let response = gui::update_videos(
+        db_guard.as_ref().unwrap(),
+        cache_guard.as_ref().unwrap(),
+        videos_guard.as_mut().unwrap(),
+        folders_guard.as_mut().unwrap(),
+        &state.session,
+        &selection,
+        &change,
+    );
+    match response.response {
+        Some(videos) => {
+            debug!("Updated {} videos", videos.len());
+            let count = videos.len();
+            let _ = app.emit_all("videos_updated", EmitVideosUpdated { videos, change });
+            Ok(wrap_success(count))
+        }
+        None => Ok(Response {
+            result: response.result,
+            response: None,
+            error: response.error,
+        }),
+    }
+}
+
+#[tauri::command]
+fn get_categories(state: State<AppState>) -> Result<Response<Vec<VideoCategory>>, ()> {
+    debug!("Get Categories Start");
+    match database::get_categories(state.db.lock().unwrap().as_ref().unwrap()) {
+        Ok(categories) => Ok(wrap_success(categories)),
+        Err(e) => Ok(wrap_failure(e.to_string())),
+    }
+}
+
+#[tauri::command]
+fn add_category(
+    state: State<AppState>,
+    name: String,
+    icon: String,
+) -> Result<Response<VideoCategory>, ()> {
+    debug!("Add Category Start");
+    Ok(gui::add_category(
+        state.db.lock().unwrap().as_ref().unwrap(),
+        &name,
+        &icon,
+    ))
+}
+
+#[tauri::command]
+fn update_category(
+    state: State<AppState>,
+    category: VideoCategory,
+) -> Result<Response<VideoCategory>, ()> {
+    debug!("Update Category Start");
+    Ok(gui::save_category(
+        state.db.lock().unwrap().as_ref().unwrap(),
+        state.videos.lock().unwrap().as_mut().unwrap(),
+        category,
+    ))
+}
+
+#[tauri::command]
+fn delete_category(state: State<AppState>, id: usize) -> Result<Response<bool>, ()> {
+    debug!("Delete Category Start");
+    Ok(gui::delete_category(
+        state.db.lock().unwrap().as_ref().unwrap(),
+        state.videos.lock().unwrap().as_mut().unwrap(),
+        id,
+    ))
+}
+
+#[tauri::command]
+fn set_video_category(
+    state: State<AppState>,
+    file: VideoFile,
+    category: Option<usize>,
+) -> Result<Response<Option<VideoCategory>>, ()> {
+    debug!("Set Video Category Start");
+    Ok(gui::update_category(
+        state.db.lock().unwrap().as_ref().unwrap(),
+        state.videos.lock().unwrap().as_mut().unwrap(),
+        &file,
+        category,
+    ))
+}
+
+#[tauri::command]
+fn get_tags(state: State<AppState>) -> Result<Response<Vec<TagCount>>, ()> {
+    debug!("Get Tags Start");
+    Ok(gui::get_tags(
+        state.db.lock().unwrap().as_ref().unwrap(),
+        state.videos.lock().unwrap().as_ref().unwrap(),
+    ))
+}
+
+// Nested tags are added with their path, e.g. genre/comedy
+#[tauri::command]
+fn add_tag(
+    state: State<AppState>,
+    path: String,
+    color: Option<String>,
+    icon: Option<String>,
+) -> Result<Response<VideoTag>, ()> {
+    debug!("Add Tag Start");
+    Ok(gui::add_tag(
+        state.db.lock().unwrap().as_ref().unwrap(),
+        &path,
+        color,
+        icon,
+    ))
+}
+
+#[tauri::command]
+fn update_tag(
+    state: State<AppState>,
+    id: usize,
+    name: String,
+    color: Option<String>,
+    icon: Option<String>,
+) -> Result<Response<VideoTag>, ()> {
+    debug!("Update Tag Start");
+    Ok(gui::update_tag(
+        state.db.lock().unwrap().as_ref().unwrap(),
+        state.videos.lock().unwrap().as_mut().unwrap(),
+        id,
+        &name,
+        color,
+        icon,
+    ))
+}
+
+#[tauri::command]
+fn merge_tags(state: State<AppState>, from: usize, into: usize) -> Result<Response<VideoTag>, ()> {
+    debug!("Merge Tags Start");
+    Ok(gui::merge_tags(
+        state.db.lock().unwrap().as_ref().unwrap(),
+        state.videos.lock().unwrap().as_mut().unwrap(),
+        from,
+        into,
+    ))
+}
+
+#[tauri::command]
+fn delete_tag(state: State<AppState>, id: usize) -> Result<Response<bool>, ()> {
+    debug!("Delete Tag Start");
+    Ok(gui::delete_tag(
+        state.db.lock().unwrap().as_ref().unwrap(),
+        state.videos.lock().unwrap().as_mut().unwrap(),
+        id,
+    ))
+}
+
+#[tauri::command]
+fn get_tagged_videos(state: State<AppState>, tag: usize) -> Result<Response<Vec<String>>, ()> {
+    debug!("Get Tagged Videos Start");
+    Ok(gui::get_tagged_videos(
+        state.db.lock().unwrap().as_ref().unwrap(),
+        tag,
+    ))
+}
+
+fn update_folder_entry(
+    state: State<AppState>,
+    folder_id: &str,
+    update: impl FnOnce(&mut FolderEntry),
+) -> Result<Response<FolderSummary>, ()> {
+    let db_guard = state.db.lock().unwrap();
+    let mut folders_guard = state.folders.lock().unwrap();
+    Ok(gui::update_folder_entry(
+        db_guard.as_ref().unwrap(),
+        folders_guard.as_mut().unwrap(),
+        folder_id,
+        update,
+    ))
+}
+
+// An empty name shows the folder name again
+#[tauri::command]
+fn set_folder_name(
+    state: State<AppState>,
+    folder_id: String,
+    name: String,
+) -> Result<Response<FolderSummary>, ()> {
+    debug!("Set Folder Name Start");
+    let name = Some(name).filter(|n| !n.trim().is_empty());
+    update_folder_entry(state, &folder_id, |e| e.set_name(name))
+}
+
+#[tauri::command]
+fn set_folder_notes(
+    state: State<AppState>,
+    folder_id: String,
+    notes: String,
+) -> Result<Response<FolderSummary>, ()> {
+    debug!("Set Folder Notes Start");
+    update_folder_entry(state, &folder_id, |e| e.set_notes(notes))
+}
+
+#[tauri::command]
+fn set_folder_rating(
+    state: State<AppState>,
+    folder_id: String,
+    rating: usize,
+) -> Result<Response<FolderSummary>, ()> {
+    debug!("Set Folder Rating Start");
+    update_folder_entry(state, &folder_id, |e| e.set_rating(rating))
+}
+
+// The cover is any image file, or one of the thumbnails of the folder videos
+#[tauri::command]
+fn set_folder_cover(
+    state: State<AppState>,
+    folder_id: String,
+    cover: Option<PathBuf>,
+) -> Result<Response<FolderSummary>, ()> {
+    debug!("Set Folder Cover Start");
+    if cover.as_ref().map_or(false, |c| !c.is_file()) {
+        return Ok(wrap_failure("Given path is not a file.".into()));
+    }
+    update_folder_entry(state, &folder_id, |e| e.set_cover(cover))
+}
+
+// Opens the video in the player profile given, or the one set for its
+// category or library, or the default profile. Without any it opens in the
+// default application of the OS.
+#[tauri::command]
+fn open_video(
+    app: AppHandle,
+    state: State<AppState>,
+    video: VideoFile,
+    location: Option<String>,
+    profile: Option<usize>,
+    chapter: Option<usize>,
+) -> Result<Response<()>, ()> {
+    debug!("Open Video Start");
+    let start = match chapter_start(&state, &video.id, chapter) {
+        Ok(start) => start,
+        Err(e) => return Ok(wrap_failure(e)),
+    };
+    let profile = {
+        let db_guard = state.db.lock().unwrap();
+        let videos_guard = state.videos.lock().unwrap();
+        player::resolve_profile(
+            db_guard.as_ref().unwrap(),
+            videos_guard.as_ref().unwrap(),
+            &video,
+            profile,
+        )
+    };
+    let profile = match profile {
+        Ok(Some(profile)) => profile,
+        Ok(None) if start.is_some() => {
+            return Ok(wrap_failure(
+                "Starting at a chapter needs a player profile".into(),
+            ))
+        }
+        Ok(None) => {
+            return gui::open_video(
+                state.db.lock().unwrap().as_ref().unwrap(),
+                &state.session,
+                video,
+                location,
+            )
+        }
+        Err(e) => return Ok(wrap_failure(e)),
+    };
+    let result = video_location(&state, &video, location)
+        .and_then(|p| player::launch(&app, &profile, video, p, start));
+    match result {
+        Ok(_) => Ok(wrap_success(())),
+        Err(e) => {
+            error!("{}", e);
+            Ok(wrap_failure(e))
+        }
+    }
+}
+
+// Start in seconds of the chapter from the stored media info
+fn chapter_start(
+    state: &State<AppState>,
+    id: &str,
+    chapter: Option<usize>,
+) -> Result<Option<f64>, String> {
+    let Some(chapter) = chapter else {
+        return Ok(None);
+    };
+    stored_media_info(state, id)?
+        .and_then(|info| info.chapters().get(chapter).map(|c| c.start()))
+        .map(Some)
+        .ok_or_else(|| "Chapter not found".to_string())
+}
+
+fn video_location(
+    state: &State<AppState>,
+    video: &VideoFile,
+    location: Option<String>,
+) -> Result<PathBuf, String> {
+    let location = {
+        let db_guard = state.db.lock().unwrap();
+        gui::resolve_location(db_guard.as_ref().unwrap(), video, location)
+    };
+    location.and_then(|p| {
+        if p.is_file() {
+            Ok(p)
+        } else {
+            Err("File not found".into())
+        }
+    })
+}
+
+// Plays the video in mpv, starting where it was left the last time
+#[tauri::command]
+fn play_video(
+    app: AppHandle,
+    state: State<AppState>,
+    video: VideoFile,
+    location: Option<String>,
+    chapter: Option<usize>,
+) -> Result<Response<()>, ()> {
+    debug!("Play Video Start");
+    let result = chapter_start(&state, &video.id, chapter).and_then(|start| {
+        video_location(&state, &video, location).and_then(|p| player::play(&app, video, p, start))
+    });
+    match result {
+        Ok(_) => Ok(wrap_success(())),
+        Err(e) => {
+            error!("{}", e);
+            Ok(wrap_failure(e))
+        }
+    }
+}
+
+#[tauri::command]
+fn get_resume_position(
+    state: State<AppState>,
+    id: String,
+) -> Result<Response<Option<ResumePosition>>, ()> {
+    debug!("Get Resume Position Start");
+    match database::get_resume(state.db.lock().unwrap().as_ref().unwrap(), &id) {
+        Ok(resume) => Ok(wrap_success(resume)),
+        Err(e) => Ok(wrap_failure(e.to_string())),
+    }
+}
+
+#[tauri::command]
+fn clear_resume_position(state: State<AppState>, id: String) -> Result<Response<bool>, ()> {
+    debug!("Clear Resume Position Start");
+    match database::delete_resume(state.db.lock().unwrap().as_ref().unwrap(), &id) {
+        Ok(_) => Ok(wrap_success(true)),
+        Err(e) => Ok(wrap_failure(e.to_string())),
+    }
+}
+
+#[tauri::command]
+fn get_player_settings(state: State<AppState>) -> Result<Response<PlayerSettings>, ()> {
+    debug!("Get Player Settings Start");
+    Ok(wrap_success(PlayerSettings::load(
+        state.db.lock().unwrap().as_ref().unwrap(),
+    )))
+}
+
+#[tauri::command]
+fn set_mpv_path(state: State<AppState>, path: String) -> Result<Response<PlayerSettings>, ()> {
+    debug!("Set Mpv Path Start");
+    let db_guard = state.db.lock().unwrap();
+    let db = db_guard.as_ref().unwrap();
+    match player::set_mpv_path(db, &path) {
+        Ok(_) => Ok(wrap_success(PlayerSettings::load(db))),
+        Err(e) => Ok(wrap_failure(e)),
+    }
+}
+
+#[tauri::command]
+fn set_watched_threshold(
+    state: State<AppState>,
+    threshold: f64,
+) -> Result<Response<PlayerSettings>, ()> {
+    debug!("Set Watched Threshold Start");
+    let db_guard = state.db.lock().unwrap();
+    let db = db_guard.as_ref().unwrap();
+    match player::set_watched_threshold(db, threshold) {
+        Ok(_) => Ok(wrap_success(PlayerSettings::load(db))),
+        Err(e) => Ok(wrap_failure(e)),
+    }
+}
+
+#[tauri::command]
+fn get_player_profiles(state: State<AppState>) -> Result<Response<Vec<PlayerProfile>>, ()> {
+    debug!("Get Player Profiles Start");
+    match database::get_player_profiles(state.db.lock().unwrap().as_ref().unwrap()) {
+        Ok(profiles) => Ok(wrap_success(profiles)),
+        Err(e) => Ok(wrap_failure(e.to_string())),
+    }
+}
+
+// Adds the profile when it has no id yet, otherwise updates it
+#[tauri::command]
+fn save_player_profile(
+    state: State<AppState>,
+    profile: PlayerProfile,
+) -> Result<Response<PlayerProfile>, ()> {
+    debug!("Save Player Profile Start");
+    if profile.name().trim().is_empty() || profile.executable().trim().is_empty() {
+        return Ok(wrap_failure(
+            "Player profile needs a name and an executable".into(),
+        ));
+    }
+    let mut profile = profile;
+    match database::save_player_profile(state.db.lock().unwrap().as_ref().unwrap(), &profile) {
+        Ok(id) => {
+            profile.set_id(id);
+            Ok(wrap_success(profile))
+        }
+        Err(e) => Ok(wrap_failure(e.to_string())),
+    }
+}
+
+#[tauri::command]
+fn delete_player_profile(state: State<AppState>, id: usize) -> Result<Response<bool>, ()> {
+    debug!("Delete Player Profile Start");
+    let db_guard = state.db.lock().unwrap();
+    let db = db_guard.as_ref().unwrap();
+    if PlayerSettings::load(db).default_player() == Some(id) {
+        if let Err(e) = player::set_default_player(db, None) {
+            return Ok(wrap_failure(e));
+        }
+    }
+    match database::delete_player_profile(db, id) {
+        Ok(_) => Ok(wrap_success(true)),
+        Err(e) => Ok(wrap_failure(e.to_string())),
+    }
+}
+
+#[derive(Serialize, Clone)]
+struct PlayerOverrides {
+    default: Option<usize>,
+    libraries: HashMap<String, usize>,
+    categories: HashMap<usize, usize>,
+}
+
+#[tauri::command]
+fn get_player_overrides(state: State<AppState>) -> Result<Response<PlayerOverrides>, ()> {
+    debug!("Get Player Overrides Start");
+    let db_guard = state.db.lock().unwrap();
+    let db = db_guard.as_ref().unwrap();
+    let overrides = database::get_path_players(db).and_then(|libraries| {
+        database::get_category_players(db).map(|categories| PlayerOverrides {
+            default: PlayerSettings::load(db).default_player(),
+            libraries,
+            categories,
+        })
+    });
+    match overrides {
+        Ok(overrides) => Ok(wrap_success(overrides)),
+        Err(e) => Ok(wrap_failure(e.to_string())),
+    }
+}
+
+fn check_player_profile(db: &Connection, player: Option<usize>) -> Result<(), String> {
+    match player.map(|id| database::get_player_profile(db, id)) {
+        Some(Ok(None)) => Err("Player profile not found".into()),
+        Some(Err(e)) => Err(e.to_string()),
+        _ => Ok(()),
+    }
+}
+
+#[tauri::command]
+fn set_default_player(
+    state: State<AppState>,
+    player: Option<usize>,
+) -> Result<Response<PlayerSettings>, ()> {
+    debug!("Set Default Player Start");
+    let db_guard = state.db.lock().unwrap();
+    let db = db_guard.as_ref().unwrap();
+    match check_player_profile(db, player).and_then(|_| player::set_default_player(db, player)) {
+        Ok(_) => Ok(wrap_success(PlayerSettings::load(db))),
+        Err(e) => Ok(wrap_failure(e)),
+    }
+}
+
+#[tauri::command]
+fn set_library_player(
+    state: State<AppState>,
+    path: String,
+    player: Option<usize>,
+) -> Result<Response<bool>, ()> {
+    debug!("Set Library Player Start");
+    let db_guard = state.db.lock().unwrap();
+    let db = db_guard.as_ref().unwrap();
+    let result = check_player_profile(db, player)
+        .and_then(|_| database::set_path_player(db, &path, player).map_err(|e| e.to_string()));
+    match result {
+        Ok(_) => Ok(wrap_success(true)),
+        Err(e) => Ok(wrap_failure(e)),
+    }
+}
+
+#[tauri::command]
+fn set_category_player(
+    state: State<AppState>,
+    category: usize,
+    player: Option<usize>,
+) -> Result<Response<bool>, ()> {
+    debug!("Set Category Player Start");
+    let db_guard = state.db.lock().unwrap();
+    let db = db_guard.as_ref().unwrap();
+    let result = check_player_profile(db, player).and_then(|_| {
+        database::set_category_player(db, category, player).map_err(|e| e.to_string())
+    });
+    match result {
+        Ok(_) => Ok(wrap_success(true)),
+        Err(e) => Ok(wrap_failure(e)),
+    }
+}
+
+// Every stream of the video as stored with its media info, None until the info
+// was read by get_media_info
+#[tauri::command]
+fn get_media_streams(
+    state: State<AppState>,
+    id: String,
+) -> Result<Response<Option<Vec<MediaStream>>>, ()> {
+    debug!("Get Media Streams Start");
+    match stored_media_info(&state, &id) {
+        Ok(info) => Ok(wrap_success(info.map(|i| i.streams().to_vec()))),
+        Err(e) => Ok(wrap_failure(e)),
+    }
+}
+
+// Paths of the chapter thumbnails in chapter order, None for the ones still
+// being created. Those are sent with the update_chapter_thumbnail event.
+#[tauri::command]
+async fn get_chapter_thumbnails(
+    app: AppHandle,
+    state: State<'_, AppState>,
+    id: String,
+    path: &str,
+) -> Result<Response<Vec<Option<PathBuf>>>, ()> {
+    debug!("Get Chapter Thumbnails Start");
+    let chapters: Vec<(f64, f64)> = match stored_media_info(&state, &id) {
+        Ok(info) => info
+            .map(|i| i.chapters().iter().map(|c| (c.start(), c.end())).collect())
+            .unwrap_or_default(),
+        Err(e) => return Ok(wrap_failure(e)),
+    };
+    let save_location = thumbnail::get_thumbnail_save_location(&app);
+    let video_path = PathBuf::from(path);
+    let mut thumbnails = Vec::new();
+    for (index, (start, end)) in chapters.into_iter().enumerate() {
+        let thumbnail = thumbnail::get_chapter_thumbnail_path(&save_location, &id, index);
+        if thumbnail.is_file() {
+            thumbnails.push(Some(thumbnail));
+            continue;
+        }
+        thumbnails.push(None);
+        if !video_path.is_file() {
+            continue;
+        }
+        // Requests already on their way are not sent again
+        let requested = match state.thumbnail_cache.lock().await.as_mut() {
+            Some(cache) => cache.request_chapter(&thumbnail),
+            None => true,
+        };
+        if !requested {
+            continue;
+        }
+        let message =
+            ThumbnailChannelMessage::chapter(video_path.clone(), id.clone(), index, start, end);
+        if let Err(e) = state.thumbnail_channel.lock().await.send(message).await {
+            debug!("Sending message to thumbnail channel failed {}", e);
+            if let Some(cache) = state.thumbnail_cache.lock().await.as_mut() {
+                cache.finish_chapter(&thumbnail);
+            }
+            return Ok(wrap_failure(e.to_string()));
+        }
+    }
+    Ok(wrap_success(thumbnails))
+}
+
+#[tauri::command]
+fn get_metadata_import(state: State<AppState>) -> Result<Response<bool>, ()> {
+    debug!("Get Metadata Import Start");
+    Ok(wrap_success(mediainfo::import_tags_enabled(
+        state.db.lock().unwrap().as_ref().unwrap(),
+    )))
+}
+
+// When enabled, videos seen for the first time take their name, notes and
+// category from the embedded metadata
+#[tauri::command]
+fn set_metadata_import(state: State<AppState>, enabled: bool) -> Result<Response<bool>, ()> {
+    debug!("Set Metadata Import Start");
+    match mediainfo::set_import_tags(state.db.lock().unwrap().as_ref().unwrap(), enabled) {
+        Ok(_) => Ok(wrap_success(enabled)),
+        Err(e) => Ok(wrap_failure(e)),
+    }
+}
+
+// Ids of the videos with stored media info matching the query, nothing is
+// read from the files
+#[tauri::command]
+fn find_videos_by_media_info(
+    state: State<AppState>,
+    query: MediaInfoQuery,
+) -> Result<Response<Vec<String>>, ()> {
+    debug!("Find Videos By Media Info Start");
+    match database::find_media_info(state.db.lock().unwrap().as_ref().unwrap(), &query) {
+        Ok(ids) => Ok(wrap_success(ids)),
+        Err(e) => Ok(wrap_failure(e.to_string())),
+    }
+}
+
+// Newest first, of one video or of every video when no id is given
+#[tauri::command]
+fn get_watch_history(
+    state: State<AppState>,
+    id: Option<String>,
+    limit: Option<usize>,
+) -> Result<Response<Vec<WatchEvent>>, ()> {
+    debug!("Get Watch History Start");
+    Ok(gui::get_watch_history(
+        state.db.lock().unwrap().as_ref().unwrap(),
+        id.as_deref(),
+        limit.unwrap_or(WATCH_HISTORY_LIMIT),
+    ))
+}
+
+// Play count, first and last watch per video, the most recently watched
+// video first
+#[tauri::command]
+fn get_watch_stats(
+    state: State<AppState>,
+    id: Option<String>,
+    limit: Option<usize>,
+) -> Result<Response<Vec<WatchStats>>, ()> {
+    debug!("Get Watch Stats Start");
+    Ok(gui::get_watch_stats(
+        state.db.lock().unwrap().as_ref().unwrap(),
+        id.as_deref(),
+        limit.unwrap_or(WATCH_HISTORY_LIMIT),
+    ))
+}
+
+#[tauri::command]
+fn get_video_locations(
+    state: State<AppState>,
+    id: String,
+) -> Result<Response<Vec<VideoLocation>>, ()> {
+    debug!("Get Video Locations Start");
+    let locations = database::get_locations(state.db.lock().unwrap().as_ref().unwrap(), &id);
+    gui::get_locations(locations)
+}
+
+#[tauri::command]
+fn set_preferred_location(
+    state: State<AppState>,
+    id: String,
+    path: String,
+) -> Result<Response<String>, ()> {
+    debug!("Set Preferred Location Start");
+    gui::set_preferred_location(state.db.lock().unwrap().as_ref().unwrap(), &id, &path)
+}
+
+// The stored info while the file it was read from is unchanged, None when it
+// still has to be read
+fn stored_media_info(state: &AppState, id: &str) -> Result<Option<VideoMediaInfo>, String> {
+    let db_guard = state.db.lock().unwrap();
+    let cache_guard = state.video_cache.lock().unwrap();
+    let (Some(db), Some(cache)) = (db_guard.as_ref(), cache_guard.as_ref()) else {
+        return Ok(None);
+    };
+    let stored = database::get_media_info(db, id).map_err(|e| e.to_string())?;
+    Ok(stored
+        .filter(|(path, size, mtime, _)| {
+            cache
+                .get_video(path)
+                .map_or(false, |i| i.id() == id && i.is_current(*size, *mtime))
+        })
+        .map(|(_, _, _, info)| info))
+}
+
+// Answers with the stored info while the file is unchanged, otherwise the info
+// is read again and sent with the update_mediainfo event
+#[tauri::command]
+async fn get_media_info(
+    app: AppHandle,
+    state: State<'_, AppState>,
+    id: String,
+    path: &str,
+) -> Result<Response<Option<VideoMediaInfo>>, ()> {
+    debug!("Get Metadata Start");
+    let path = PathBuf::from(path);
+    let stored = stored_media_info(&state, &id).ok().flatten();
+    if let Some(info) = stored {
+        debug!("Stored media info found");
+        let _ = app.emit_all(
+            &format!("update_mediainfo_{}", id),
+            VideoMediaInfoEmitEvent::new(info.clone()),
+        );
+        Ok(wrap_success(Some(info)))
+    } else if !path.is_file() {
+        error!("File does not exist");
+        Ok(wrap_failure("File does not exist".to_string()))
+    } else {
+        debug!("Sending media info message to channel");
+        match state
+            .mediainfo_channel
+            .lock()
+            .await
+            .send(VideoMediaInfoChannelMessage::new(id, path, None))
+            .await
+        {
+            Ok(_) => Ok(wrap_success(None)),
+            Err(e) => {
+                error!("Sending message to media info channel failed {}", e);
+                Ok(wrap_failure(e.to_string()))
+            }
+        }
+    }
+}
+
+#[tauri::command]
+fn delete_path(app: AppHandle, state: State<AppState>, path: &str) -> Result<Response<bool>, ()> {
+    debug!("Delete Path Start");
+    let mut db_guard = state.db.lock().unwrap();
+    let db = db_guard.as_mut().unwrap();
+    if let Err(e) = gui::validate_path(&db, &path) {
+        Ok(e)
+    } else {
+        let mut cache_guard = state.video_cache.lock().unwrap();
+        let cache = cache_guard.as_mut().unwrap();
+        let response = gui::delete_path(db, cache, &path);
+        if response.result == ResponseType::Success {
+            if let Some(folders) = state.folders.lock().unwrap().as_mut() {
+                folders.remove(path);
+            }
+            let _ = app.emit_all(
+                "path_deleted",
+                EmitPathDeleted {
+                    path: path.to_string(),
+                },
+            );
+        }
+        Ok(response)
+    }
+}
+
+#[tauri::command]
+async fn set_identity_strategy(
+    app: AppHandle,
+    path: String,
+    identity: IdentityStrategy,
+) -> Result<Response<usize>, ()> {
+    debug!("Set Identity Strategy Start");
+    {
+        let state = app.state::<AppState>();
+        let db_guard = state.db.lock().unwrap();
+        let db = db_guard.as_ref().unwrap();
+        match gui::validate_path(db, &path) {
+            Ok(true) => {}
+            Ok(false) => return Ok(wrap_failure("Path not found".to_string())),
+            Err(e) => return Ok(e),
+        }
+        // Saved first so an interrupted rekey picks the strategy up again
+        if let Err(e) = database::set_identity(db, &path, identity) {
+            return Ok(wrap_failure(e.to_string()));
+        }
+    }
+    let response = match jobs::start_job(&app, JobKind::Rekey, Some(path.clone())) {
+        Ok(job) => jobs::run_job(app, job, move |app, job| rekey_path(app, job, path)).await,
+        Err(e) => wrap_failure(e),
+    };
+    debug!("Set Identity Strategy End");
+    Ok(response)
+}
+
+// Hashes the videos of a library root again with its identity strategy as the
+// work of a job. Files are hashed on a copy of the cache, the app state is
+// only locked to apply the new ids.
+fn rekey_path(app: &AppHandle, job: &Job, path: String) -> Response<usize> {
+    let state = app.state::<AppState>();
+    let identity =
+        database::get_scan_settings(state.db.lock().unwrap().as_ref().unwrap(), &path).identity;
+    let cache = state.video_cache.lock().unwrap().clone().unwrap();
+    let rekey = match gui::hash_videos(&cache, &path, identity, job, job.emitter(app)) {
+        Some(rekey) => rekey,
+        None => return wrap_failure("Rekey cancelled".to_string()),
+    };
+    let mut db_guard = state.db.lock().unwrap();
+    let mut cache_guard = state.video_cache.lock().unwrap();
+    let mut videos_guard = state.videos.lock().unwrap();
+    gui::set_identity_strategy(
+        db_guard.as_mut().unwrap(),
+        cache_guard.as_mut().unwrap(),
+        videos_guard.as_mut().unwrap(),
+        rekey,
+        &thumbnail::get_thumbnail_save_location(app),
+    )
+}
+
+#[tauri::command]
+fn get_scan_rules(state: State<AppState>, path: String) -> Result<Response<Vec<ScanRule>>, ()> {
+    debug!("Get Scan Rules Start");
+    let db_guard = state.db.lock().unwrap();
+    let db = db_guard.as_ref().unwrap();
+    match gui::validate_path(db, &path) {
+        Ok(true) => Ok(gui::get_scan_rules(db, &path)),
+        Ok(false) => Ok(wrap_failure("Path not found".to_string())),
+        Err(e) => Ok(e),
+    }
+}
+
+#[tauri::command]
+fn set_scan_rules(
+    state: State<AppState>,
+    path: String,
+    rules: Vec<ScanRule>,
+) -> Result<Response<Vec<ScanRule>>, ()> {
+    debug!("Set Scan Rules Start");
+    let mut db_guard = state.db.lock().unwrap();
+    let db = db_guard.as_mut().unwrap();
+    match gui::validate_path(db, &path) {
+        Ok(true) => Ok(gui::set_scan_rules(db, &path, rules)),
+        Ok(false) => Ok(wrap_failure("Path not found".to_string())),
+        Err(e) => Ok(e),
+    }
+}
+
+#[tauri::command]
+fn set_follow_symlinks(
+    state: State<AppState>,
+    path: String,
+    follow: bool,
+) -> Result<Response<bool>, ()> {
+    debug!("Set Follow Symlinks Start");
+    let db_guard = state.db.lock().unwrap();
+    let db = db_guard.as_ref().unwrap();
+    match gui::validate_path(db, &path) {
+        Ok(true) => Ok(gui::set_follow_symlinks(db, &path, follow)),
+        Ok(false) => Ok(wrap_failure("Path not found".to_string())),
+        Err(e) => Ok(e),
+    }
+}
+
+#[tauri::command]
+fn get_scan_reports(state: State<AppState>, path: String) -> Result<Response<Vec<ScanReport>>, ()> {
+    debug!("Get Scan Reports Start");
+    let db_guard = state.db.lock().unwrap();
+    let db = db_guard.as_ref().unwrap();
+    match gui::validate_path(db, &path) {
+        Ok(true) => Ok(gui::get_scan_reports(db, &path)),
+        Ok(false) => Ok(wrap_failure("Path not found".to_string())),
+        Err(e) => Ok(e),
+    }
+}
+
+#[tauri::command]
+fn open_path(path: &str, parent: bool) {
+    debug!("Open Path Start");
+    let path_buf = PathBuf::from(path);
+    let path = if parent {
+        let parent_path = path_buf.parent().unwrap();
+        parent_path.to_str().unwrap()
+    } else {
+        path
+    };
+    opener::open(path).unwrap();
+}
+
+#[tauri::command]
+async fn folder_scan(state: State<'_, AppState>) -> Result<Response<()>, Error> {
+    let paths = {
+        let db_guard = state.db.lock().unwrap();
+        database::get_paths(db_guard.as_ref().unwrap()).unwrap_or_default()
+    };
+    let channel = state.folder_channel.lock().await;
+    for path in paths {
+        if let Err(e) = channel.send(PathBuf::from(path)).await {
+            error!("Sending message to folder channel failed {}", e);
+            return Ok(wrap_failure(e.to_string()));
+        }
+    }
+    Ok(wrap_success(()))
+}
+
+#[derive(Clone, Serialize)]
+struct EmitWatched {
+    watched: bool,
+}
+
+#[derive(Clone, Serialize)]
+struct EmitVideosUpdated {
+    videos: Vec<VideoUpdate>,
+    change: VideoChange,
+}
+
+#[derive(Clone, Serialize)]
+struct EmitPathDeleted {
+    path: String,
+}
+
+#[derive(Clone, Serialize)]
+pub struct EmitProgress {
+    total: Option<usize>,
+    name: Option<String>,
+    folder: bool,
+}
+
+pub fn run() {
+    let (thumbnail_input_tx, thumbnail_input_rx) = tokio::sync::mpsc::channel(1);
+    let (thumbnail_output_tx, thumbnail_output_rx) = tokio::sync::mpsc::channel(1);
+    let (mediainfo_input_tx, mediainfo_input_rx) = tokio::sync::mpsc::channel(1);
+    let (mediainfo_output_tx, mediainfo_output_rx) = tokio::sync::mpsc::channel(1);
+    let (folder_output_tx, folder_output_rx) = tokio::sync::mpsc::channel(20);
+    let (watcher_tx, watcher_rx) = std::sync::mpsc::sync_channel(20);
+
+    tauri::Builder::default()
+        .manage(AppState {
+            db: Default::default(),
+            videos: Default::default(),
+            thumbnail_cache: Default::default(),
+            video_cache: Default::default(),
+            thumbnail_channel: tokio::sync::Mutex::new(thumbnail_input_tx),
+            mediainfo_channel: tokio::sync::Mutex::new(mediainfo_input_tx),
+            folder_channel: tokio::sync::Mutex::new(folder_output_tx),
+            folders: Mutex::new(Some(FolderTree::default())),
+            jobs: Default::default(),
+            session: WatchSession::start(),
+        })
+        .plugin(
+            tauri_plugin_log::Builder::default()
+                .targets(util::get_log_targets())
+                .level(util::get_log_level())
+                .build(),
+        )
+        .invoke_handler(tauri::generate_handler![
+            file_scan,
+            select_folder,
+            get_folders,
+            add_folder,
+            get_video,
+            get_thumbnail,
+            set_video_rating,
+            set_watched,
+            open_video,
+            set_video_name,
+            get_media_info,
+            find_videos_by_media_info,
+            get_media_streams,
+            get_chapter_thumbnails,
+            get_metadata_import,
+            set_metadata_import,
+            set_video_notes,
+            delete_path,
+            open_path,
+            folder_scan,
+            set_identity_strategy,
+            get_video_locations,
+            set_preferred_location,
+            get_scan_rules,
+            set_scan_rules,
+            set_follow_symlinks,
+            cancel_job,
+            get_jobs,
+            get_scan_reports,
+            get_folder_roots,
+            get_folder_children,
+            get_folder,
+            get_folder_parent,
+            get_folder_by_path,
+            set_folder_name,
+            set_folder_notes,
+            set_folder_rating,
+            set_folder_cover,
+            update_videos,
+            get_categories,
+            add_category,
+            update_category,
+            delete_category,
+            set_video_category,
+            get_tags,
+            add_tag,
+            update_tag,
+            merge_tags,
+            delete_tag,
+            get_tagged_videos,
+            get_watch_history,
+            get_watch_stats,
+            play_video,
+            get_resume_position,
+            clear_resume_position,
+            get_player_settings,
+            set_mpv_path,
+            set_watched_threshold,
+            get_player_profiles,
+            save_player_profile,
+            delete_player_profile,
+            get_player_overrides,
+            set_default_player,
+            set_library_player,
+            set_category_player
+        ])
+        .setup(|app| {
+            let handle = app.handle();
+            let handle = Arc::new(handle);
+            let state = handle.state::<AppState>();
+            let db = load_database(&handle).expect("Load database failed");
+            let videos = get_videos(&db).expect("Load videos failed");
+            let thumbnail_location = thumbnail::get_thumbnail_save_location(&handle);
+            let thumbnail_cache = thumbnail::create_thumbnail_cache(&thumbnail_location);
+            let video_cache = state::get_video_cache(&db);
+            let folder_entries =
+                database::get_folder_entries(&db).expect("Load folder entries failed");
+            let durations = database::get_media_durations(&db).expect("Load durations failed");
+            *state.folders.lock().unwrap() = Some(FolderTree::new(folder_entries, durations));
+            *state.videos.lock().unwrap() = Some(videos);
+            *state.db.lock().unwrap() = Some(db);
+            *state.video_cache.lock().unwrap() = Some(video_cache);
+            // Thumbnail mutex async task
+            {
+                let handle = Arc::clone(&handle);
+                tauri::async_runtime::spawn(async move {
+                    let state = handle.state::<AppState>();
+                    let mut lock = state.thumbnail_cache.lock().await;
+                    *lock = Some(thumbnail_cache);
+                });
+            }
+            // Thumbnail input async task
+            {
+                let handle = Arc::clone(&handle);
+                tauri::async_runtime::spawn(async move {
+                    let state = handle.state::<AppState>();
+                    thumbnail::process_thumbnail_input_channels(
+                        &state.thumbnail_cache,
+                        &thumbnail_location,
+                        thumbnail_input_rx,
+                        thumbnail_output_tx,
+                    )
+                    .await
+                    .expect("Thumbnail input channels failed");
+                });
+            }
+            // Thumbnail output async task
+            {
+                let handle = Arc::clone(&handle);
+                tauri::async_runtime::spawn(async move {
+                    match thumbnail::process_thumbnail_output_channels(&handle, thumbnail_output_rx)
+                        .await
+                    {
+                        Ok(_) => Ok(()),
+                        Err(e) => {
+                            error!("Thumbnail output channels failed {}", e.to_string());
+                            Err(e)
+                        }
+                    }
+                });
+            }
+            // Mediainfo input async task
+            {
+                tauri::async_runtime::spawn(async move {
+                    match mediainfo::process_mediainfo_input_channels(
+                        mediainfo_input_rx,
+                        mediainfo_output_tx,
+                    )
+                    .await
+                    {
+                        Ok(_) => Ok(()),
+                        Err(e) => {
+                            error!("Mediainfo input channels failed {}", e.to_string());
+                            Err(e)
+                        }
+                    }
+                });
+            }
+            // Mediainfo output async task
+            {
+                let handle = Arc::clone(&handle);
+                tauri::async_runtime::spawn(async move {
+                    match mediainfo::process_mediainfo_output_channels(&handle, mediainfo_output_rx)
+                        .await
+                    {
+                        Ok(_) => Ok(()),
+                        Err(e) => {
+                            error!("Mediainfo input channels failed {}", e.to_string());
+                            Err(e)
+                        }
+                    }
+                });
+            }
+            // Folder input async task
+            {
+                let handle = Arc::clone(&handle);
+                tauri::async_runtime::spawn(async move {
+                    match folderscan::process_folder_output_channels(&handle, folder_output_rx)
+                        .await
+                    {
+                        Ok(_) => Ok(()),
+                        Err(e) => {
+                            error!("Folder input channels failed {}", e.to_string());
+                            Err(e)
+                        }
+                    }
+                });
+            }
+            // Interrupted jobs async task
+            {
+                let handle = Arc::clone(&handle);
+                tauri::async_runtime::spawn(async move {
+                    for job in jobs::get_interrupted_jobs(&handle) {
+                        resume_job(handle.deref().clone(), job).await;
+                    }
+                });
+            }
+            // Watcher thread
+            {
+                let handle = Arc::clone(&handle);
+                let spawned = std::thread::Builder::new()
+                    .name("watcher".into())
+                    .spawn(move || {
+                        match watcher::process_watcher_channels(&handle, watcher_tx, watcher_rx) {
+                            Ok(_) => Ok(()),
+                            Err(e) => {
+                                error!("Watcher channels failed {}", e.to_string());
+                                Err(e)
+                            }
+                        }
+                    });
+                if let Err(e) = spawned {
+                    error!("Watcher thread can't be started {}", e);
+                }
+            }
+            Ok(())
+        })
+        .run(tauri::generate_context!())
+        .expect("error while running tauri application");
+}
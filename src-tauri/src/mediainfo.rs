@@ -1,17 +1,36 @@
+use std::ffi::{c_char, CStr, CString};
 use std::path::{Path, PathBuf};
+use std::{mem, ptr, slice};
 
 use anyhow::Error;
-use rsmpeg::avcodec::{AVCodecContext, AVCodecParametersRef, AVCodecRef};
+use rsmpeg::avcodec::{AVCodec, AVCodecContext, AVCodecParametersRef, AVCodecRef};
+use rsmpeg::avformat::{AVFormatContextInput, AVStreamRef};
 use rsmpeg::avutil::av_q2d;
 use rsmpeg::ffi::{
-    av_get_bits_per_sample, AVMediaType_AVMEDIA_TYPE_AUDIO, AVMediaType_AVMEDIA_TYPE_VIDEO,
-    AV_TIME_BASE,
+    self, av_channel_layout_describe, av_chroma_location_name, av_color_primaries_name,
+    av_color_range_name, av_color_space_name, av_color_transfer_name, av_dict_get,
+    av_display_rotation_get, av_get_bits_per_sample, av_get_pix_fmt_name, av_pix_fmt_desc_get,
+    av_reduce, av_stream_get_side_data, avcodec_descriptor_get, AVChannelLayout,
+    AVColorTransferCharacteristic_AVCOL_TRC_ARIB_STD_B67,
+    AVColorTransferCharacteristic_AVCOL_TRC_SMPTE2084, AVContentLightMetadata,
+    AVDOVIDecoderConfigurationRecord, AVMasteringDisplayMetadata, AVMediaType,
+    AVMediaType_AVMEDIA_TYPE_ATTACHMENT, AVMediaType_AVMEDIA_TYPE_AUDIO,
+    AVMediaType_AVMEDIA_TYPE_DATA, AVMediaType_AVMEDIA_TYPE_SUBTITLE,
+    AVMediaType_AVMEDIA_TYPE_VIDEO, AVPacketSideDataType,
+    AVPacketSideDataType_AV_PKT_DATA_CONTENT_LIGHT_LEVEL,
+    AVPacketSideDataType_AV_PKT_DATA_DISPLAYMATRIX, AVPacketSideDataType_AV_PKT_DATA_DOVI_CONF,
+    AVPacketSideDataType_AV_PKT_DATA_MASTERING_DISPLAY_METADATA, AVRational,
+    AV_CODEC_PROP_BITMAP_SUB, AV_CODEC_PROP_TEXT_SUB, AV_DISPOSITION_DEFAULT,
+    AV_DISPOSITION_FORCED, AV_TIME_BASE,
 };
+use rusqlite::Connection;
 use serde::{Deserialize, Serialize};
 use tauri::{AppHandle, Manager};
 use tokio::sync::mpsc::{Receiver, Sender};
 
-use crate::{thumbnail, util};
+use crate::state::AppState;
+use crate::video::VideoEntry;
+use crate::{database, thumbnail, util};
 
 #[derive(Debug, Clone, Builder, Serialize, Deserialize)]
 pub struct VideoMediaInfo {
@@ -26,6 +45,9 @@ pub struct VideoMediaInfo {
     #[builder(default = "None")]
     bitrate: Option<String>,
     length: String,
+    // Length in seconds, used for the folder totals
+    #[builder(default = "None")]
+    duration: Option<f64>,
     #[builder(default = "None")]
     codec: Option<String>,
     #[builder(default = "None")]
@@ -34,7 +56,320 @@ pub struct VideoMediaInfo {
     acodec: Option<String>,
     #[builder(default = "None")]
     asample: Option<String>,
+    // Raw values next to the formatted ones, stored for sorting and filtering
+    #[builder(default = "None")]
+    #[serde(default)]
+    bit_rate: Option<i64>,
+    #[builder(default = "None")]
+    #[serde(default)]
+    codec_id: Option<i64>,
+    #[builder(default = "None")]
+    #[serde(default)]
+    abit_rate: Option<i64>,
+    #[builder(default = "None")]
+    #[serde(default)]
+    acodec_id: Option<i64>,
+    #[builder(default = "None")]
+    #[serde(default)]
+    sample_rate: Option<i32>,
+    // Every stream of the container, in file order
+    #[builder(default = "Vec::new()")]
+    #[serde(default)]
+    streams: Vec<MediaStream>,
+    #[builder(default = "Vec::new()")]
+    #[serde(default)]
+    chapters: Vec<Chapter>,
+    #[builder(default = "ContainerTags::default()")]
+    #[serde(default)]
+    tags: ContainerTags,
+    #[builder(default = "None")]
+    #[serde(default)]
+    color: Option<ColorInfo>,
+    // Width to height as shown, like 16:9, with the sample aspect ratio applied
+    #[builder(default = "None")]
+    #[serde(default)]
+    aspect_ratio: Option<String>,
+    // Degrees the video is turned counterclockwise when shown
+    #[builder(default = "None")]
+    #[serde(default)]
+    rotation: Option<f64>,
+}
+
+impl VideoMediaInfo {
+    pub fn duration(&self) -> Option<f64> {
+        self.duration
+    }
+    pub fn width(&self) -> Option<i32> {
+        self.width
+    }
+    pub fn height(&self) -> Option<i32> {
+        self.height
+    }
+    pub fn framerate(&self) -> Option<f64> {
+        self.framerate
+    }
+    pub fn bit_rate(&self) -> Option<i64> {
+        self.bit_rate
+    }
+    pub fn codec_id(&self) -> Option<i64> {
+        self.codec_id
+    }
+    pub fn abit_rate(&self) -> Option<i64> {
+        self.abit_rate
+    }
+    pub fn acodec_id(&self) -> Option<i64> {
+        self.acodec_id
+    }
+    pub fn sample_rate(&self) -> Option<i32> {
+        self.sample_rate
+    }
+    pub fn streams(&self) -> &[MediaStream] {
+        &self.streams
+    }
+    pub fn chapters(&self) -> &[Chapter] {
+        &self.chapters
+    }
+    pub fn tags(&self) -> &ContainerTags {
+        &self.tags
+    }
+}
+
+// Embedded metadata as written by the muxer, the format level wins over the
+// streams. The title is only taken from the format, a stream title names the
+// track.
+#[derive(Deserialize, Serialize, Clone, Debug, Default)]
+pub struct ContainerTags {
+    title: Option<String>,
+    date: Option<String>,
+    comment: Option<String>,
+    description: Option<String>,
+    genre: Option<String>,
+    encoder: Option<String>,
+    show: Option<String>,
+    episode_id: Option<String>,
+    season_number: Option<String>,
+}
+
+impl ContainerTags {
+    pub fn title(&self) -> Option<&String> {
+        self.title.as_ref()
+    }
+    pub fn description(&self) -> Option<&String> {
+        self.description.as_ref().or(self.comment.as_ref())
+    }
+    pub fn genre(&self) -> Option<&String> {
+        self.genre.as_ref()
+    }
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum HdrFormat {
+    // PQ transfer
+    Hdr10,
+    Hlg,
+    DolbyVision,
+}
+
+// Names are the ones ffmpeg uses, like bt2020 or smpte2084
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct ColorInfo {
+    pixel_format: Option<String>,
+    bit_depth: Option<i32>,
+    primaries: Option<String>,
+    transfer: Option<String>,
+    range: Option<String>,
+    matrix: Option<String>,
+    chroma_location: Option<String>,
+    hdr: Option<HdrFormat>,
+    mastering_display: Option<MasteringDisplay>,
+    content_light_level: Option<ContentLightLevel>,
+    dolby_vision: Option<DolbyVisionConfig>,
+}
+
+// Chromaticity coordinates of the red, green and blue primaries and the white
+// point, luminance in cd/m²
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct MasteringDisplay {
+    primaries: Option<[[f64; 2]; 3]>,
+    white_point: Option<[f64; 2]>,
+    min_luminance: Option<f64>,
+    max_luminance: Option<f64>,
+}
+
+// In cd/m²
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct ContentLightLevel {
+    max_cll: u32,
+    max_fall: u32,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct DolbyVisionConfig {
+    version: String,
+    profile: u8,
+    level: u8,
+    rpu: bool,
+    enhancement_layer: bool,
+    base_layer: bool,
+    // Which non Dolby Vision players can show the base layer, 0 for none
+    compatibility_id: u8,
+}
+
+// Stored with the info, bumped when reading it fills in more fields so info
+// read before is read again
+pub const MEDIA_INFO_VERSION: u32 = 1;
+
+const IMPORT_TAGS_KEY: &str = "import_embedded_metadata";
+
+// Whether new videos get their name, notes and category from the embedded
+// metadata
+pub fn import_tags_enabled(db: &Connection) -> bool {
+    database::get_setting(db, IMPORT_TAGS_KEY)
+        .ok()
+        .flatten()
+        .map_or(false, |v| v == "true")
 }
+
+pub fn set_import_tags(db: &Connection, enabled: bool) -> Result<(), String> {
+    database::set_setting(db, IMPORT_TAGS_KEY, &enabled.to_string()).map_err(|e| e.to_string())
+}
+
+// Fills in the name, notes and category of a new video from the tags of its
+// file. Only fields still at their defaults are filled, the name defaults to
+// the file name. The genre is only used when a category of that name exists.
+fn import_tags(db: &Connection, tags: &ContainerTags, file_name: &str, entry: &mut VideoEntry) {
+    if let Some(title) = tags.title().filter(|_| entry.name() == file_name) {
+        entry.set_name(title.clone());
+    }
+    if let Some(description) = tags.description().filter(|_| entry.notes().is_empty()) {
+        entry.set_notes(description.clone());
+    }
+    if let Some(genre) = tags.genre().filter(|_| entry.category().is_none()) {
+        let category = database::get_categories(db)
+            .unwrap_or_default()
+            .into_iter()
+            .find(|c| c.name().eq_ignore_ascii_case(genre));
+        if category.is_some() {
+            entry.set_category(category);
+        }
+    }
+}
+
+// Start and end in seconds
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct Chapter {
+    start: f64,
+    end: f64,
+    title: Option<String>,
+}
+
+impl Chapter {
+    pub fn start(&self) -> f64 {
+        self.start
+    }
+    pub fn end(&self) -> f64 {
+        self.end
+    }
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum MediaStreamKind {
+    Video,
+    Audio,
+    Subtitle,
+    Attachment,
+    Data,
+    Unknown,
+}
+
+impl MediaStreamKind {
+    fn from_media_type(media_type: AVMediaType) -> Self {
+        match media_type {
+            AVMediaType_AVMEDIA_TYPE_VIDEO => MediaStreamKind::Video,
+            AVMediaType_AVMEDIA_TYPE_AUDIO => MediaStreamKind::Audio,
+            AVMediaType_AVMEDIA_TYPE_SUBTITLE => MediaStreamKind::Subtitle,
+            AVMediaType_AVMEDIA_TYPE_ATTACHMENT => MediaStreamKind::Attachment,
+            AVMediaType_AVMEDIA_TYPE_DATA => MediaStreamKind::Data,
+            _ => MediaStreamKind::Unknown,
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum SubtitleFormat {
+    Text,
+    Bitmap,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct MediaStream {
+    index: i32,
+    kind: MediaStreamKind,
+    codec: Option<String>,
+    codec_id: i64,
+    language: Option<String>,
+    // Attachments without a title have their file name here
+    title: Option<String>,
+    default: bool,
+    forced: bool,
+    channels: Option<i32>,
+    channel_layout: Option<String>,
+    subtitle_format: Option<SubtitleFormat>,
+}
+
+// Stored media info columns the views can sort and filter on
+#[derive(Deserialize, Clone, Copy, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum MediaInfoField {
+    Duration,
+    Bitrate,
+    Width,
+    Height,
+    Fps,
+    AudioBitrate,
+    SampleRate,
+    Filesize,
+}
+
+impl MediaInfoField {
+    pub fn column(&self) -> &'static str {
+        match self {
+            MediaInfoField::Duration => "duration",
+            MediaInfoField::Bitrate => "bitrate",
+            MediaInfoField::Width => "width",
+            MediaInfoField::Height => "height",
+            MediaInfoField::Fps => "fps",
+            MediaInfoField::AudioBitrate => "abitrate",
+            MediaInfoField::SampleRate => "sample_rate",
+            MediaInfoField::Filesize => "size",
+        }
+    }
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct MediaInfoRange {
+    pub field: MediaInfoField,
+    pub min: Option<f64>,
+    pub max: Option<f64>,
+}
+
+// Videos with stored media info matching every range and codec given, in the
+// order of the sort field
+#[derive(Deserialize, Clone, Debug, Default)]
+pub struct MediaInfoQuery {
+    #[serde(default)]
+    pub ranges: Vec<MediaInfoRange>,
+    pub codec: Option<i64>,
+    pub acodec: Option<i64>,
+    pub sort: Option<MediaInfoField>,
+    #[serde(default)]
+    pub descending: bool,
+    pub limit: Option<usize>,
+}
+
 // Events
 #[derive(Clone, Serialize)]
 pub struct VideoMediaInfoEmitEvent {
@@ -47,6 +382,11 @@ impl VideoMediaInfoEmitEvent {
     }
 }
 
+#[derive(Clone, Serialize)]
+pub struct VideoEmitEvent {
+    video: VideoEntry,
+}
+
 // Channels
 pub struct VideoMediaInfoChannelMessage {
     id: String,
@@ -60,6 +400,8 @@ impl VideoMediaInfoChannelMessage {
     }
 }
 
+// Files without media info are passed on too, so their pending work is
+// dropped
 pub async fn process_mediainfo_input_channels(
     mut mediainfo_input_rx: Receiver<VideoMediaInfoChannelMessage>,
     mediainfo_output_tx: Sender<VideoMediaInfoChannelMessage>,
@@ -67,19 +409,23 @@ pub async fn process_mediainfo_input_channels(
     debug!("Media info input channel started");
     while let Some(input) = mediainfo_input_rx.recv().await {
         debug!("Media info input message received");
-        let media_info = create_media_info(&input.path);
-        if let Ok(m) = media_info {
-            debug!("Media info created: {:?}", m);
-            if let Err(e) = mediainfo_output_tx
-                .send(VideoMediaInfoChannelMessage::new(
-                    input.id,
-                    input.path,
-                    Some(m),
-                ))
-                .await
-            {
-                error!("Failed to send media output: {}", e);
+        let media_info = match create_media_info(&input.path) {
+            Ok(m) => {
+                debug!("Media info created: {:?}", m);
+                Some(m)
             }
+            Err(e) => {
+                error!("Media info creation failed for {}: {}", input.id, e);
+                None
+            }
+        };
+        if let Err(e) = mediainfo_output_tx
+            .send(VideoMediaInfoChannelMessage::new(
+                input.id, input.path, media_info,
+            ))
+            .await
+        {
+            error!("Failed to send media output: {}", e);
         }
     }
 
@@ -93,14 +439,106 @@ pub async fn process_mediainfo_output_channels(
     debug!("Media info output channel started");
     while let Some(input) = mediainfo_output_rx.recv().await {
         debug!("Media info output message received");
-        let emit_message = VideoMediaInfoEmitEvent::new(input.info.unwrap());
-        let _ = app.emit_all(&format!("update_mediainfo_{}", input.id), emit_message);
-        debug!("Media info output message send for: {}", input.id);
+        let handle = app.clone();
+        // The database work blocks, it stays off the async workers
+        if let Err(e) =
+            tauri::async_runtime::spawn_blocking(move || store_media_info(&handle, input)).await
+        {
+            error!("Media info output failed: {}", e);
+        }
     }
 
     Ok(())
 }
 
+// Locks db, then video_cache, then videos and folders, the order used
+// everywhere else
+fn store_media_info(app: &AppHandle, input: VideoMediaInfoChannelMessage) {
+    let state = app.state::<AppState>();
+    let Some(info) = input.info else {
+        // The tags of a file that can't be read won't come, the import is
+        // dropped instead of waiting for them
+        if let Some(db) = state.db.lock().unwrap().as_ref() {
+            if let Err(e) = database::take_metadata_import(db, &input.id) {
+                error!("Metadata import can't be dropped for {}: {}", input.id, e);
+            }
+        }
+        return;
+    };
+    {
+        let db_guard = state.db.lock().unwrap();
+        let cache_guard = state.video_cache.lock().unwrap();
+        let item = cache_guard.as_ref().and_then(|c| c.get_video(&input.path));
+        if let (Some(db), Some(item)) = (db_guard.as_ref(), item) {
+            // Thumbnails of the chapters in the info this one replaces
+            thumbnail::delete_chapter_thumbnails(
+                thumbnail::get_thumbnail_save_location(app),
+                &[input.id.clone()],
+            );
+            if let Err(e) = database::save_media_info(
+                db,
+                &input.id,
+                &input.path,
+                item.filesize(),
+                item.mtime(),
+                &info,
+            ) {
+                error!("Saving media info failed for {}: {}", input.id, e);
+            }
+        }
+        if let Some(db) = db_guard.as_ref() {
+            import_video_tags(app, db, &input.id, &input.path, info.tags());
+        }
+    }
+    if let Some(duration) = info.duration() {
+        if let Some(folders) = state.folders.lock().unwrap().as_mut() {
+            folders.set_duration(&input.id, duration);
+        }
+    }
+    let emit_message = VideoMediaInfoEmitEvent::new(info);
+    let _ = app.emit_all(&format!("update_mediainfo_{}", input.id), emit_message);
+    debug!("Media info output message send for: {}", input.id);
+}
+
+// Imports the tags into the entry of a video created while the import was on.
+// Called with the db lock held, it takes the videos lock itself.
+fn import_video_tags(
+    app: &AppHandle,
+    db: &Connection,
+    id: &String,
+    path: &Path,
+    tags: &ContainerTags,
+) {
+    match database::take_metadata_import(db, id) {
+        Ok(true) => {}
+        Ok(false) => return,
+        Err(e) => {
+            error!("Metadata import can't be checked for {}: {}", id, e);
+            return;
+        }
+    }
+    let state = app.state::<AppState>();
+    let mut videos_guard = state.videos.lock().unwrap();
+    let Some(entry) = videos_guard.as_mut().and_then(|v| v.get_mut(id)) else {
+        return;
+    };
+    let file_name = path
+        .file_name()
+        .map(|n| n.to_string_lossy().to_string())
+        .unwrap_or_default();
+    import_tags(db, tags, &file_name, entry);
+    if let Err(e) = database::save_videos(db, &[(id, &*entry)]) {
+        error!("Imported metadata can't be saved for {}: {}", id, e);
+        return;
+    }
+    let _ = app.emit_all(
+        &format!("update_video_{}", id),
+        VideoEmitEvent {
+            video: entry.clone(),
+        },
+    );
+}
+
 // Creator
 fn create_media_info<P: AsRef<Path>>(video_path: P) -> Result<VideoMediaInfo, Error> {
     let mut builder = VideoMediaInfoBuilder::create_empty();
@@ -112,14 +550,19 @@ fn create_media_info<P: AsRef<Path>>(video_path: P) -> Result<VideoMediaInfo, Er
     let input_context = thumbnail::create_input_context(video_path)?;
     debug!("Input context created");
     builder.length(format_duration(input_context.duration));
+    if input_context.duration > 0 {
+        builder.duration(Some(input_context.duration as f64 / AV_TIME_BASE as f64));
+    }
     if input_context.bit_rate > 0 {
         builder.bitrate(Some(format!("{} kb/s", input_context.bit_rate / 1000)));
+        builder.bit_rate(Some(input_context.bit_rate));
     }
     if let Ok(Some((index, codec))) = input_context.find_best_stream(AVMediaType_AVMEDIA_TYPE_VIDEO)
     {
         if let Some(video_stream) = input_context.streams().get(index) {
             debug!("Video stream found video information will be created");
             builder.codec(get_codec_name(&codec));
+            builder.codec_id(Some(i64::from(codec.id)));
             let params = video_stream.codecpar();
             if params.width > 0 {
                 builder.width(Some(params.width));
@@ -127,6 +570,7 @@ fn create_media_info<P: AsRef<Path>>(video_path: P) -> Result<VideoMediaInfo, Er
             }
             if params.bit_rate > 0 {
                 builder.bitrate(Some(format!("{} kb/s", params.bit_rate / 1000)));
+                builder.bit_rate(Some(params.bit_rate));
             } else {
                 calculate_bit_rate(&mut builder, &codec, &params);
             }
@@ -134,6 +578,9 @@ fn create_media_info<P: AsRef<Path>>(video_path: P) -> Result<VideoMediaInfo, Er
             if fps > 0.0 {
                 builder.framerate(Some((fps * 100.0).round() / 100.0));
             }
+            builder.color(Some(read_color_info(video_stream, &params)));
+            builder.aspect_ratio(display_aspect_ratio(video_stream, &params));
+            builder.rotation(read_rotation(video_stream));
             debug!("Video info creation done");
         }
     }
@@ -142,9 +589,11 @@ fn create_media_info<P: AsRef<Path>>(video_path: P) -> Result<VideoMediaInfo, Er
         if let Some(audio_stream) = input_context.streams().get(index) {
             debug!("Audio stream found video information will be created");
             builder.acodec(get_codec_name(&codec));
+            builder.acodec_id(Some(i64::from(codec.id)));
             let params = audio_stream.codecpar();
             if params.sample_rate > 0 {
                 builder.asample(Some(format!("{} Hz", params.sample_rate)));
+                builder.sample_rate(Some(params.sample_rate));
             }
 
             let mut bit_rate;
@@ -164,15 +613,287 @@ fn create_media_info<P: AsRef<Path>>(video_path: P) -> Result<VideoMediaInfo, Er
             }
             if bit_rate > 0 {
                 builder.abitrate(Some(format!("{} kb/s", bit_rate / 1000)));
+                builder.abit_rate(Some(bit_rate));
             } else {
                 calculate_bit_rate(&mut builder, &codec, &params);
             }
             debug!("Audio info creation done");
         }
     }
+    builder.streams(
+        input_context
+            .streams()
+            .iter()
+            .map(create_media_stream)
+            .collect(),
+    );
+    builder.chapters(read_chapters(&input_context));
+    builder.tags(read_container_tags(&input_context));
     Ok(builder.build()?)
 }
 
+fn create_media_stream(stream: &AVStreamRef) -> MediaStream {
+    let params = stream.codecpar();
+    let kind = MediaStreamKind::from_media_type(params.codec_type);
+    // The metadata of the stream lives as long as the stream
+    let tag = |key: &str| unsafe { dictionary_value(stream.metadata, key) };
+    let descriptor = unsafe { avcodec_descriptor_get(params.codec_id).as_ref() };
+    let codec = AVCodec::find_decoder(params.codec_id)
+        .and_then(|c| get_codec_name(&c))
+        .or_else(|| {
+            descriptor.map(|d| {
+                unsafe { CStr::from_ptr(d.name) }
+                    .to_string_lossy()
+                    .to_string()
+            })
+        });
+    let subtitle_format = descriptor
+        .filter(|_| kind == MediaStreamKind::Subtitle)
+        .and_then(|d| {
+            if d.props & AV_CODEC_PROP_BITMAP_SUB as i32 != 0 {
+                Some(SubtitleFormat::Bitmap)
+            } else if d.props & AV_CODEC_PROP_TEXT_SUB as i32 != 0 {
+                Some(SubtitleFormat::Text)
+            } else {
+                None
+            }
+        });
+    let (channels, channel_layout) = if kind == MediaStreamKind::Audio {
+        (
+            Some(params.ch_layout.nb_channels).filter(|c| *c > 0),
+            describe_channel_layout(&params.ch_layout),
+        )
+    } else {
+        (None, None)
+    };
+    MediaStream {
+        index: stream.index,
+        kind,
+        codec,
+        codec_id: i64::from(params.codec_id),
+        language: tag("language"),
+        title: tag("title").or_else(|| {
+            if kind == MediaStreamKind::Attachment {
+                tag("filename")
+            } else {
+                None
+            }
+        }),
+        default: stream.disposition & AV_DISPOSITION_DEFAULT as i32 != 0,
+        forced: stream.disposition & AV_DISPOSITION_FORCED as i32 != 0,
+        channels,
+        channel_layout,
+        subtitle_format,
+    }
+}
+
+fn read_container_tags(input_context: &AVFormatContextInput) -> ContainerTags {
+    let streams: Vec<*const ffi::AVDictionary> = input_context
+        .streams()
+        .iter()
+        .map(|s| s.metadata as *const ffi::AVDictionary)
+        .collect();
+    let format = input_context.metadata as *const ffi::AVDictionary;
+    // The dictionaries belong to the input context, which is borrowed for the
+    // whole function
+    let format_tag = |key: &str| unsafe { dictionary_value(format, key) };
+    let tag = |key: &str| {
+        format_tag(key).or_else(|| {
+            streams
+                .iter()
+                .find_map(|s| unsafe { dictionary_value(*s, key) })
+        })
+    };
+    ContainerTags {
+        title: format_tag("title"),
+        date: tag("date").or_else(|| tag("creation_time")),
+        comment: tag("comment"),
+        description: tag("description").or_else(|| tag("synopsis")),
+        genre: tag("genre"),
+        encoder: tag("encoder"),
+        show: tag("show"),
+        episode_id: tag("episode_id"),
+        season_number: tag("season_number"),
+    }
+}
+
+fn read_chapters(input_context: &AVFormatContextInput) -> Vec<Chapter> {
+    if input_context.chapters.is_null() {
+        return Vec::new();
+    }
+    let chapters = unsafe {
+        slice::from_raw_parts(input_context.chapters, input_context.nb_chapters as usize)
+    };
+    chapters
+        .iter()
+        .filter_map(|c| unsafe { c.as_ref() })
+        .map(|chapter| {
+            let time_base = av_q2d(chapter.time_base);
+            Chapter {
+                start: chapter.start as f64 * time_base,
+                end: chapter.end as f64 * time_base,
+                // The chapter and its metadata belong to the input context
+                title: unsafe { dictionary_value(chapter.metadata, "title") },
+            }
+        })
+        .collect()
+}
+
+fn read_color_info(stream: &AVStreamRef, params: &AVCodecParametersRef) -> ColorInfo {
+    let pixel_format = unsafe { av_get_pix_fmt_name(params.format) };
+    let bit_depth = unsafe { av_pix_fmt_desc_get(params.format).as_ref() }
+        .map(|d| d.comp[0].depth)
+        .or(Some(params.bits_per_raw_sample))
+        .filter(|d| *d > 0);
+    let mastering_display = side_data::<AVMasteringDisplayMetadata>(
+        stream,
+        AVPacketSideDataType_AV_PKT_DATA_MASTERING_DISPLAY_METADATA,
+    )
+    .map(|m| MasteringDisplay {
+        primaries: (m.has_primaries != 0)
+            .then(|| m.display_primaries.map(|p| [av_q2d(p[0]), av_q2d(p[1])])),
+        white_point: (m.has_primaries != 0)
+            .then(|| [av_q2d(m.white_point[0]), av_q2d(m.white_point[1])]),
+        min_luminance: (m.has_luminance != 0).then(|| av_q2d(m.min_luminance)),
+        max_luminance: (m.has_luminance != 0).then(|| av_q2d(m.max_luminance)),
+    });
+    let content_light_level = side_data::<AVContentLightMetadata>(
+        stream,
+        AVPacketSideDataType_AV_PKT_DATA_CONTENT_LIGHT_LEVEL,
+    )
+    .map(|c| ContentLightLevel {
+        max_cll: c.MaxCLL,
+        max_fall: c.MaxFALL,
+    });
+    let dolby_vision = side_data::<AVDOVIDecoderConfigurationRecord>(
+        stream,
+        AVPacketSideDataType_AV_PKT_DATA_DOVI_CONF,
+    )
+    .map(|d| DolbyVisionConfig {
+        version: format!("{}.{}", d.dv_version_major, d.dv_version_minor),
+        profile: d.dv_profile,
+        level: d.dv_level,
+        rpu: d.rpu_present_flag != 0,
+        enhancement_layer: d.el_present_flag != 0,
+        base_layer: d.bl_present_flag != 0,
+        compatibility_id: d.dv_bl_signal_compatibility_id,
+    });
+    let hdr = if dolby_vision.is_some() {
+        Some(HdrFormat::DolbyVision)
+    } else {
+        match params.color_trc {
+            AVColorTransferCharacteristic_AVCOL_TRC_SMPTE2084 => Some(HdrFormat::Hdr10),
+            AVColorTransferCharacteristic_AVCOL_TRC_ARIB_STD_B67 => Some(HdrFormat::Hlg),
+            _ => None,
+        }
+    };
+    ColorInfo {
+        pixel_format: color_name(pixel_format),
+        bit_depth,
+        primaries: color_name(unsafe { av_color_primaries_name(params.color_primaries) }),
+        transfer: color_name(unsafe { av_color_transfer_name(params.color_trc) }),
+        range: color_name(unsafe { av_color_range_name(params.color_range) }),
+        matrix: color_name(unsafe { av_color_space_name(params.color_space) }),
+        chroma_location: color_name(unsafe { av_chroma_location_name(params.chroma_location) }),
+        hdr,
+        mastering_display,
+        content_light_level,
+        dolby_vision,
+    }
+}
+
+// Unset values are left out instead of showing up as unknown
+fn color_name(name: *const c_char) -> Option<String> {
+    if name.is_null() {
+        return None;
+    }
+    let name = unsafe { CStr::from_ptr(name) }
+        .to_string_lossy()
+        .to_string();
+    Some(name).filter(|n| n != "unknown" && n != "unspecified")
+}
+
+// The stream side data of the given type, when it is big enough for T
+fn side_data<T: Copy>(stream: &AVStreamRef, data_type: AVPacketSideDataType) -> Option<T> {
+    let stream: *const ffi::AVStream = &**stream;
+    let mut size = 0;
+    let data = unsafe { av_stream_get_side_data(stream, data_type, &mut size) };
+    if data.is_null() || size < mem::size_of::<T>() {
+        return None;
+    }
+    Some(unsafe { ptr::read_unaligned(data as *const T) })
+}
+
+fn display_aspect_ratio(stream: &AVStreamRef, params: &AVCodecParametersRef) -> Option<String> {
+    aspect_ratio(
+        params.width,
+        params.height,
+        [params.sample_aspect_ratio, stream.sample_aspect_ratio],
+    )
+}
+
+// The first valid sample aspect ratio is used, the codec one comes before the
+// container one. Without any the pixels are square.
+fn aspect_ratio(width: i32, height: i32, sample_aspect_ratios: [AVRational; 2]) -> Option<String> {
+    if width <= 0 || height <= 0 {
+        return None;
+    }
+    let sample_aspect_ratio = sample_aspect_ratios
+        .into_iter()
+        .find(|r| r.num > 0 && r.den > 0)
+        .unwrap_or(AVRational { num: 1, den: 1 });
+    let (mut num, mut den) = (0, 0);
+    unsafe {
+        av_reduce(
+            &mut num,
+            &mut den,
+            width as i64 * sample_aspect_ratio.num as i64,
+            height as i64 * sample_aspect_ratio.den as i64,
+            1024 * 1024,
+        )
+    };
+    Some(format!("{}:{}", num, den))
+}
+
+fn read_rotation(stream: &AVStreamRef) -> Option<f64> {
+    matrix_rotation(side_data(
+        stream,
+        AVPacketSideDataType_AV_PKT_DATA_DISPLAYMATRIX,
+    ))
+}
+
+// Degrees counterclockwise, None without a display matrix
+fn matrix_rotation(matrix: Option<[i32; 9]>) -> Option<f64> {
+    let rotation = unsafe { av_display_rotation_get(matrix?.as_ptr()) };
+    // NaN for a matrix that is not a rotation, + 0.0 turns -0 into 0
+    Some(rotation + 0.0).filter(|r| r.is_finite())
+}
+
+// Keys are matched ignoring case, empty values count as missing.
+//
+// Safety: the dictionary has to be null or point to a valid AVDictionary that
+// is not changed or freed during the call.
+unsafe fn dictionary_value(dictionary: *const ffi::AVDictionary, key: &str) -> Option<String> {
+    let key = CString::new(key).ok()?;
+    let value = unsafe {
+        av_dict_get(dictionary, key.as_ptr(), ptr::null(), 0)
+            .as_ref()
+            .map(|e| CStr::from_ptr(e.value).to_string_lossy().trim().to_string())
+    };
+    value.filter(|v| !v.is_empty())
+}
+
+// Names like 5.1(side) or stereo, as ffprobe shows them
+fn describe_channel_layout(layout: &AVChannelLayout) -> Option<String> {
+    let mut buffer = [0 as c_char; 64];
+    let length = unsafe { av_channel_layout_describe(layout, buffer.as_mut_ptr(), buffer.len()) };
+    if length <= 0 {
+        return None;
+    }
+    let description = unsafe { CStr::from_ptr(buffer.as_ptr()) };
+    Some(description.to_string_lossy().to_string())
+}
+
 fn calculate_bit_rate(
     builder: &mut VideoMediaInfoBuilder,
     codec: &AVCodecRef,
@@ -184,6 +905,7 @@ fn calculate_bit_rate(
         && codec_context.rc_max_rate > 0
     {
         builder.bitrate(Some(format!("{} kb/s", codec_context.rc_max_rate / 1000)));
+        builder.bit_rate(Some(codec_context.rc_max_rate));
     }
 }
 
@@ -214,3 +936,119 @@ fn format_duration(duration: i64) -> String {
         hours, minutes, seconds, milliseconds
     )
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::open_in_memory;
+
+    fn tags() -> ContainerTags {
+        ContainerTags {
+            title: Some("The Title".into()),
+            comment: Some("A comment".into()),
+            genre: Some("drama".into()),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn tags_fill_new_entries() {
+        let db = open_in_memory();
+        database::add_category(&db, "Drama", "mask").unwrap();
+        let mut entry = VideoEntry::new("a.mkv".into(), 0, "".into(), false);
+
+        import_tags(&db, &tags(), "a.mkv", &mut entry);
+
+        assert_eq!(entry.name(), "The Title");
+        assert_eq!(entry.notes(), "A comment");
+        assert_eq!(entry.category().unwrap().name(), "Drama");
+    }
+
+    #[test]
+    fn tags_keep_changed_fields() {
+        let db = open_in_memory();
+        database::add_category(&db, "Drama", "mask").unwrap();
+        let movies = database::add_category(&db, "Movies", "film").unwrap();
+        let mut entry = VideoEntry::new("Renamed".into(), 0, "Own notes".into(), false);
+        entry.set_category(database::get_category(&db, movies).unwrap());
+
+        import_tags(&db, &tags(), "a.mkv", &mut entry);
+
+        assert_eq!(entry.name(), "Renamed");
+        assert_eq!(entry.notes(), "Own notes");
+        assert_eq!(entry.category().unwrap().id(), movies);
+    }
+
+    #[test]
+    fn unknown_genres_leave_the_category_empty() {
+        let db = open_in_memory();
+        let mut entry = VideoEntry::new("a.mkv".into(), 0, "".into(), false);
+
+        import_tags(&db, &tags(), "a.mkv", &mut entry);
+
+        assert!(entry.category().is_none());
+    }
+
+    fn ratio(num: i32, den: i32) -> AVRational {
+        AVRational { num, den }
+    }
+
+    #[test]
+    fn aspect_ratios_use_the_sample_aspect_ratio() {
+        let unset = ratio(0, 1);
+        assert_eq!(
+            aspect_ratio(1920, 1080, [ratio(1, 1), unset]).unwrap(),
+            "16:9"
+        );
+        // PAL DVD, 4:3 and anamorphic 16:9
+        assert_eq!(
+            aspect_ratio(720, 576, [ratio(16, 15), unset]).unwrap(),
+            "4:3"
+        );
+        assert_eq!(
+            aspect_ratio(720, 576, [ratio(64, 45), ratio(16, 15)]).unwrap(),
+            "16:9"
+        );
+    }
+
+    #[test]
+    fn aspect_ratios_fall_back_to_the_container_and_square_pixels() {
+        let unset = ratio(0, 1);
+        assert_eq!(
+            aspect_ratio(720, 576, [unset, ratio(64, 45)]).unwrap(),
+            "16:9"
+        );
+        assert_eq!(aspect_ratio(720, 576, [unset, unset]).unwrap(), "5:4");
+        assert_eq!(
+            aspect_ratio(720, 576, [ratio(1, 0), ratio(-1, 1)]).unwrap(),
+            "5:4"
+        );
+    }
+
+    #[test]
+    fn aspect_ratios_need_a_size() {
+        assert!(aspect_ratio(0, 1080, [ratio(1, 1); 2]).is_none());
+        assert!(aspect_ratio(1920, 0, [ratio(1, 1); 2]).is_none());
+    }
+
+    // Rotation matrices as av_display_rotation_set writes them, 16.16 fixed
+    // point with the last column in 2.30
+    const ONE: i32 = 1 << 16;
+    const W: i32 = 1 << 30;
+
+    #[test]
+    fn rotations_are_read_from_the_display_matrix() {
+        let identity = [ONE, 0, 0, 0, ONE, 0, 0, 0, W];
+        let left = [0, -ONE, 0, ONE, 0, 0, 0, 0, W];
+        let right = [0, ONE, 0, -ONE, 0, 0, 0, 0, W];
+        assert_eq!(matrix_rotation(Some(identity)), Some(0.0));
+        assert_eq!(matrix_rotation(Some(left)), Some(90.0));
+        assert_eq!(matrix_rotation(Some(right)), Some(-90.0));
+    }
+
+    #[test]
+    fn rotations_need_a_display_matrix() {
+        assert_eq!(matrix_rotation(None), None);
+        assert_eq!(matrix_rotation(Some([0; 9])), None);
+    }
+}
@@ -0,0 +1,772 @@
+use std::collections::HashMap;
+#[cfg(windows)]
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Read, Write};
+#[cfg(unix)]
+use std::os::unix::net::UnixStream;
+use std::path::{Path, PathBuf};
+use std::process::{self, Child, Command};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use anyhow::{bail, Error};
+use rusqlite::Connection;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tauri::{AppHandle, Manager};
+
+use crate::filescan::VideoFile;
+use crate::gui;
+use crate::history::{WatchEvent, WatchEventKind};
+use crate::state::AppState;
+use crate::video::VideoEntry;
+use crate::{database, util};
+
+const MPV_PATH_KEY: &str = "mpv_path";
+const WATCHED_THRESHOLD_KEY: &str = "watched_threshold";
+const DEFAULT_PLAYER_KEY: &str = "default_player";
+const DEFAULT_MPV_PATH: &str = "mpv";
+const DEFAULT_WATCHED_THRESHOLD: f64 = 0.9;
+const POLL_INTERVAL: Duration = Duration::from_secs(2);
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+const READ_TIMEOUT: Duration = Duration::from_secs(5);
+// Polls between two writes of the resume position
+const SAVE_INTERVAL: usize = 5;
+// Positions this close to the start are not worth resuming, in seconds
+const MIN_RESUME_POSITION: f64 = 5.0;
+
+#[derive(Serialize, Clone, Debug)]
+pub struct ResumePosition {
+    video: String,
+    // Seconds
+    position: f64,
+    duration: Option<f64>,
+    updated: u64,
+}
+
+impl ResumePosition {
+    pub fn new(video: &str, position: f64, duration: Option<f64>) -> Self {
+        Self::load(video.to_string(), position, duration, util::now_millis())
+    }
+
+    pub fn load(video: String, position: f64, duration: Option<f64>, updated: u64) -> Self {
+        Self {
+            video,
+            position,
+            duration,
+            updated,
+        }
+    }
+
+    pub fn video(&self) -> &str {
+        &self.video
+    }
+    pub fn position(&self) -> f64 {
+        self.position
+    }
+    pub fn duration(&self) -> Option<f64> {
+        self.duration
+    }
+    pub fn updated(&self) -> u64 {
+        self.updated
+    }
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct PlayerSettings {
+    mpv_path: String,
+    // Share of the duration after which a video counts as watched
+    watched_threshold: f64,
+    // Profile used when neither the category nor the library has one
+    default_player: Option<usize>,
+}
+
+impl PlayerSettings {
+    pub fn load(db: &Connection) -> Self {
+        let setting = |key| database::get_setting(db, key).ok().flatten();
+        Self {
+            mpv_path: setting(MPV_PATH_KEY).unwrap_or_else(|| DEFAULT_MPV_PATH.to_string()),
+            watched_threshold: setting(WATCHED_THRESHOLD_KEY)
+                .and_then(|t| t.parse::<f64>().ok())
+                .filter(|t| valid_threshold(*t))
+                .unwrap_or(DEFAULT_WATCHED_THRESHOLD),
+            default_player: setting(DEFAULT_PLAYER_KEY).and_then(|p| p.parse::<usize>().ok()),
+        }
+    }
+
+    pub fn default_player(&self) -> Option<usize> {
+        self.default_player
+    }
+}
+
+fn valid_threshold(threshold: f64) -> bool {
+    threshold > 0.0 && threshold <= 1.0
+}
+
+pub fn set_mpv_path(db: &Connection, path: &str) -> Result<(), String> {
+    let path = path.trim();
+    let path = if path.is_empty() {
+        DEFAULT_MPV_PATH
+    } else {
+        path
+    };
+    database::set_setting(db, MPV_PATH_KEY, path).map_err(|e| e.to_string())
+}
+
+pub fn set_default_player(db: &Connection, player: Option<usize>) -> Result<(), String> {
+    let value = player.map(|p| p.to_string()).unwrap_or_default();
+    database::set_setting(db, DEFAULT_PLAYER_KEY, &value).map_err(|e| e.to_string())
+}
+
+pub fn set_watched_threshold(db: &Connection, threshold: f64) -> Result<(), String> {
+    if !valid_threshold(threshold) {
+        return Err("Threshold must be above 0 and at most 1".to_string());
+    }
+    database::set_setting(db, WATCHED_THRESHOLD_KEY, &threshold.to_string())
+        .map_err(|e| e.to_string())
+}
+
+// Talks to mpv over its JSON IPC, one request at a time
+pub struct MpvClient<R: Read, W: Write> {
+    reader: BufReader<R>,
+    writer: W,
+    request_id: u64,
+}
+
+impl<R: Read, W: Write> MpvClient<R, W> {
+    pub fn new(reader: R, writer: W) -> Self {
+        Self {
+            reader: BufReader::new(reader),
+            writer,
+            request_id: 0,
+        }
+    }
+
+    // None while mpv has no value for the property, e.g. before the file is
+    // loaded
+    pub fn get_property(&mut self, name: &str) -> Result<Option<f64>, Error> {
+        self.request_id += 1;
+        let request = json!({"command": ["get_property", name], "request_id": self.request_id});
+        writeln!(self.writer, "{}", request)?;
+        self.writer.flush()?;
+        let mut line = String::new();
+        loop {
+            line.clear();
+            if self.reader.read_line(&mut line)? == 0 {
+                bail!("mpv closed the connection");
+            }
+            let Ok(response) = serde_json::from_str::<Value>(&line) else {
+                continue;
+            };
+            // Events are sent over the same connection
+            if response.get("request_id").and_then(Value::as_u64) != Some(self.request_id) {
+                continue;
+            }
+            if response.get("error").and_then(Value::as_str) != Some("success") {
+                return Ok(None);
+            }
+            return Ok(response.get("data").and_then(Value::as_f64));
+        }
+    }
+}
+
+#[cfg(unix)]
+fn ipc_path(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("{}.sock", name))
+}
+
+#[cfg(windows)]
+fn ipc_path(name: &str) -> PathBuf {
+    PathBuf::from(format!(r"\\.\pipe\{}", name))
+}
+
+#[cfg(unix)]
+fn connect(path: &Path) -> Result<MpvClient<impl Read, impl Write>, Error> {
+    let stream = UnixStream::connect(path)?;
+    stream.set_read_timeout(Some(READ_TIMEOUT))?;
+    Ok(MpvClient::new(stream.try_clone()?, stream))
+}
+
+#[cfg(windows)]
+fn connect(path: &Path) -> Result<MpvClient<impl Read, impl Write>, Error> {
+    let pipe: File = OpenOptions::new().read(true).write(true).open(path)?;
+    Ok(MpvClient::new(pipe.try_clone()?, pipe))
+}
+
+// An external player. The arguments are a template, {path}, {start},
+// {subtitle} and {title} are filled in per launch. An argument whose
+// placeholder has no value, e.g. --start={start} without a resume position, is
+// left out. The path is added last when the template has no {path}.
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct PlayerProfile {
+    #[serde(default)]
+    id: usize,
+    name: String,
+    executable: String,
+    arguments: String,
+    working_dir: Option<PathBuf>,
+    // mpv only, follows the playback for resume positions and watched marks
+    #[serde(default)]
+    mpv_ipc: bool,
+}
+
+impl PlayerProfile {
+    pub fn new(
+        id: usize,
+        name: String,
+        executable: String,
+        arguments: String,
+        working_dir: Option<PathBuf>,
+        mpv_ipc: bool,
+    ) -> Self {
+        Self {
+            id,
+            name,
+            executable,
+            arguments,
+            working_dir,
+            mpv_ipc,
+        }
+    }
+
+    // The profile play_video uses, built from the mpv settings
+    fn mpv(settings: &PlayerSettings) -> Self {
+        Self::new(
+            0,
+            "mpv".to_string(),
+            settings.mpv_path.clone(),
+            "--start={start} --sub-file={subtitle} --force-media-title={title} {path}".to_string(),
+            None,
+            true,
+        )
+    }
+
+    pub fn id(&self) -> usize {
+        self.id
+    }
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+    pub fn executable(&self) -> &str {
+        &self.executable
+    }
+    pub fn arguments(&self) -> &str {
+        &self.arguments
+    }
+    pub fn working_dir(&self) -> Option<&PathBuf> {
+        self.working_dir.as_ref()
+    }
+    pub fn mpv_ipc(&self) -> bool {
+        self.mpv_ipc
+    }
+    pub fn set_id(&mut self, id: usize) {
+        self.id = id;
+    }
+
+    fn expand(&self, launch: &Launch) -> Vec<String> {
+        let values = [
+            ("{path}", Some(launch.path.display().to_string())),
+            ("{start}", launch.start.map(|s| format!("{:.3}", s))),
+            (
+                "{subtitle}",
+                launch.subtitle.as_ref().map(|s| s.display().to_string()),
+            ),
+            ("{title}", Some(launch.title.clone())),
+        ];
+        let mut arguments: Vec<String> = split_arguments(&self.arguments)
+            .iter()
+            .filter_map(|argument| expand_argument(argument, &values))
+            .collect();
+        if !self.arguments.contains("{path}") {
+            arguments.push(launch.path.display().to_string());
+        }
+        arguments
+    }
+}
+
+// Fills in the placeholders in one pass over the template, so a value that
+// contains a placeholder itself, e.g. a title with {path}, is kept as is. None
+// when a placeholder of the argument has no value.
+fn expand_argument(argument: &str, values: &[(&str, Option<String>)]) -> Option<String> {
+    let mut expanded = String::new();
+    let mut rest = argument;
+    while !rest.is_empty() {
+        match values.iter().find(|(key, _)| rest.starts_with(key)) {
+            Some((key, value)) => {
+                expanded.push_str(value.as_ref()?);
+                rest = &rest[key.len()..];
+            }
+            None => {
+                let c = rest.chars().next()?;
+                expanded.push(c);
+                rest = &rest[c.len_utf8()..];
+            }
+        }
+    }
+    Some(expanded)
+}
+
+// Splits on whitespace, double quotes keep an argument together
+fn split_arguments(template: &str) -> Vec<String> {
+    let mut arguments = Vec::new();
+    let mut current = String::new();
+    let mut quoted = false;
+    for c in template.chars() {
+        match c {
+            '"' => quoted = !quoted,
+            c if c.is_whitespace() && !quoted => {
+                if !current.is_empty() {
+                    arguments.push(std::mem::take(&mut current));
+                }
+            }
+            c => current.push(c),
+        }
+    }
+    if !current.is_empty() {
+        arguments.push(current);
+    }
+    arguments
+}
+
+const SUBTITLE_EXTENSIONS: &[&str] = &["srt", "ass", "ssa", "vtt", "sub"];
+
+// A subtitle next to the video with the same file stem
+fn find_subtitle(path: &Path) -> Option<PathBuf> {
+    SUBTITLE_EXTENSIONS
+        .iter()
+        .map(|e| path.with_extension(e))
+        .find(|p| p.is_file())
+}
+
+struct Launch {
+    path: PathBuf,
+    start: Option<f64>,
+    subtitle: Option<PathBuf>,
+    title: String,
+}
+
+// The profile picked at launch wins, then the one of the video category, the
+// one of its library and the default profile. None means the OS default
+// player.
+pub fn resolve_profile(
+    db: &Connection,
+    videos: &HashMap<String, VideoEntry>,
+    video: &VideoFile,
+    requested: Option<usize>,
+) -> Result<Option<PlayerProfile>, String> {
+    if let Some(id) = requested {
+        return match database::get_player_profile(db, id) {
+            Ok(Some(profile)) => Ok(Some(profile)),
+            Ok(None) => Err("Player profile not found".to_string()),
+            Err(e) => Err(e.to_string()),
+        };
+    }
+    let category = videos
+        .get(&video.id)
+        .and_then(|e| e.category())
+        .map(|c| c.id());
+    let by_category = category.and_then(|c| {
+        database::get_category_players(db)
+            .ok()
+            .and_then(|players| players.get(&c).copied())
+    });
+    let by_library = || {
+        database::get_path_players(db).ok().and_then(|players| {
+            players
+                .into_iter()
+                .filter(|(path, _)| video.path().starts_with(path))
+                .max_by_key(|(path, _)| path.len())
+                .map(|(_, player)| player)
+        })
+    };
+    let id = by_category
+        .or_else(by_library)
+        .or_else(|| PlayerSettings::load(db).default_player);
+    match id.map(|id| database::get_player_profile(db, id)) {
+        Some(Ok(profile)) => Ok(profile),
+        Some(Err(e)) => Err(e.to_string()),
+        None => Ok(None),
+    }
+}
+
+// Starts mpv at the saved resume position, or at the start given, and follows
+// the playback on its own thread
+pub fn play(
+    app: &AppHandle,
+    video: VideoFile,
+    path: PathBuf,
+    start: Option<f64>,
+) -> Result<(), String> {
+    let settings = {
+        let state = app.state::<AppState>();
+        let db_guard = state.db.lock().unwrap();
+        PlayerSettings::load(db_guard.as_ref().ok_or("Database is not loaded")?)
+    };
+    launch(app, &PlayerProfile::mpv(&settings), video, path, start)
+}
+
+// Runs the profile for the video. A start given, like a chapter start, wins
+// over the resume position. Profiles with mpv IPC are followed on their own
+// thread for resume positions and watched marks.
+pub fn launch(
+    app: &AppHandle,
+    profile: &PlayerProfile,
+    video: VideoFile,
+    path: PathBuf,
+    start: Option<f64>,
+) -> Result<(), String> {
+    let state = app.state::<AppState>();
+    let (settings, resume, title) = {
+        let db_guard = state.db.lock().unwrap();
+        let db = db_guard.as_ref().ok_or("Database is not loaded")?;
+        let resume = database::get_resume(db, &video.id).map_err(|e| e.to_string())?;
+        let videos_guard = state.videos.lock().unwrap();
+        let title = videos_guard
+            .as_ref()
+            .and_then(|v| v.get(&video.id))
+            .map(|e| e.name().to_string())
+            .unwrap_or_else(|| video.name().to_string());
+        (PlayerSettings::load(db), resume, title)
+    };
+    let launch = Launch {
+        start: start.or_else(|| resume.map(|r| r.position)),
+        subtitle: find_subtitle(&path),
+        title,
+        path,
+    };
+    let mut command = Command::new(&profile.executable);
+    if let Some(dir) = profile.working_dir.as_ref() {
+        command.current_dir(dir);
+    }
+    let ipc = profile.mpv_ipc.then(|| {
+        ipc_path(&format!(
+            "vidlib-mpv-{}-{}",
+            process::id(),
+            util::now_millis()
+        ))
+    });
+    if let Some(ipc) = ipc.as_ref() {
+        command.arg(format!("--input-ipc-server={}", ipc.display()));
+    }
+    let mut child = command
+        .args(profile.expand(&launch))
+        .spawn()
+        .map_err(|e| format!("{} can't be started: {}", profile.name, e))?;
+    debug!("{} started for {}", profile.name, launch.path.display());
+    if let Some(db) = state.db.lock().unwrap().as_ref() {
+        gui::add_watch_event(
+            db,
+            WatchEvent::new(
+                &video.id,
+                WatchEventKind::Played,
+                Some(&launch.path),
+                &state.session,
+            ),
+        );
+    }
+    let app = app.clone();
+    let threshold = settings.watched_threshold;
+    match ipc {
+        Some(ipc) => thread::spawn(move || follow(&app, child, &ipc, video, threshold)),
+        None => thread::spawn(move || {
+            let _ = child.wait();
+        }),
+    };
+    Ok(())
+}
+
+struct Playback {
+    position: Option<f64>,
+    duration: Option<f64>,
+    watched: bool,
+}
+
+impl Playback {
+    fn passed(&self, threshold: f64) -> bool {
+        match (self.position, self.duration) {
+            (Some(position), Some(duration)) if duration > 0.0 => position / duration >= threshold,
+            _ => false,
+        }
+    }
+
+    // A video played to the end starts from the beginning next time
+    fn resume_position(&self, video: &str, threshold: f64) -> Option<ResumePosition> {
+        self.position
+            .filter(|p| *p >= MIN_RESUME_POSITION && !self.passed(threshold))
+            .map(|p| ResumePosition::new(video, p, self.duration))
+    }
+}
+
+fn follow(app: &AppHandle, mut child: Child, ipc: &Path, video: VideoFile, threshold: f64) {
+    let mut playback = Playback {
+        position: None,
+        duration: None,
+        watched: false,
+    };
+    match wait_for_ipc(&mut child, ipc) {
+        Some(mut client) => {
+            let mut polls = 0;
+            loop {
+                thread::sleep(POLL_INTERVAL);
+                let (position, duration) = match (
+                    client.get_property("time-pos"),
+                    client.get_property("duration"),
+                ) {
+                    (Ok(position), Ok(duration)) => (position, duration),
+                    _ => break,
+                };
+                playback.position = position.or(playback.position);
+                playback.duration = duration.or(playback.duration);
+                if !playback.watched && playback.passed(threshold) {
+                    playback.watched = true;
+                    mark_watched(app, &video);
+                }
+                polls += 1;
+                if polls % SAVE_INTERVAL == 0 {
+                    save_resume(app, &video, &playback, threshold);
+                }
+            }
+        }
+        None => error!("mpv IPC can't be reached for {}", video.path().display()),
+    }
+    let _ = child.wait();
+    #[cfg(unix)]
+    let _ = std::fs::remove_file(ipc);
+    debug!("mpv closed for {}", video.path().display());
+    save_resume(app, &video, &playback, threshold);
+}
+
+fn wait_for_ipc(child: &mut Child, ipc: &Path) -> Option<MpvClient<impl Read, impl Write>> {
+    let start = Instant::now();
+    while start.elapsed() < CONNECT_TIMEOUT {
+        if let Ok(Some(_)) = child.try_wait() {
+            return None;
+        }
+        if let Ok(client) = connect(ipc) {
+            return Some(client);
+        }
+        thread::sleep(Duration::from_millis(200));
+    }
+    None
+}
+
+fn mark_watched(app: &AppHandle, video: &VideoFile) {
+    let watched = {
+        let state = app.state::<AppState>();
+        let videos_guard = state.videos.lock().unwrap();
+        videos_guard
+            .as_ref()
+            .and_then(|v| v.get(&video.id))
+            .map_or(false, |e| e.watched())
+    };
+    if !watched {
+        debug!("Watched threshold passed for {}", video.path().display());
+        let _ = crate::update_watched(app, video.clone(), true);
+    }
+}
+
+fn save_resume(app: &AppHandle, video: &VideoFile, playback: &Playback, threshold: f64) {
+    if playback.position.is_none() {
+        return;
+    }
+    let resume = playback.resume_position(&video.id, threshold);
+    let state = app.state::<AppState>();
+    let db_guard = state.db.lock().unwrap();
+    let Some(db) = db_guard.as_ref() else {
+        return;
+    };
+    let result = match resume.as_ref() {
+        Some(resume) => database::set_resume(db, resume),
+        None => database::delete_resume(db, &video.id),
+    };
+    if let Err(e) = result {
+        error!("Resume position can't be saved: {}", e);
+    }
+    let _ = app.emit_all(
+        &format!("update_resume_{}", video.id),
+        ResumeEmitEvent { resume },
+    );
+}
+
+// Events
+#[derive(Clone, Serialize)]
+pub struct ResumeEmitEvent {
+    resume: Option<ResumePosition>,
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+
+    fn client<'a>(
+        responses: &str,
+        requests: &'a mut Vec<u8>,
+    ) -> MpvClient<Cursor<Vec<u8>>, &'a mut Vec<u8>> {
+        MpvClient::new(Cursor::new(responses.as_bytes().to_vec()), requests)
+    }
+
+    fn launch(start: Option<f64>, subtitle: Option<&str>, title: &str) -> Launch {
+        Launch {
+            path: PathBuf::from("/videos/a b.mkv"),
+            start,
+            subtitle: subtitle.map(PathBuf::from),
+            title: title.to_string(),
+        }
+    }
+
+    fn profile(arguments: &str) -> PlayerProfile {
+        PlayerProfile::new(
+            1,
+            "player".to_string(),
+            "player".to_string(),
+            arguments.to_string(),
+            None,
+            false,
+        )
+    }
+
+    fn playback(position: Option<f64>, duration: Option<f64>) -> Playback {
+        Playback {
+            position,
+            duration,
+            watched: false,
+        }
+    }
+
+    #[test]
+    fn get_property_skips_interleaved_events() {
+        let responses = concat!(
+            "{\"event\":\"playback-restart\"}\n",
+            "not json\n",
+            "{\"event\":\"property-change\",\"name\":\"pause\",\"data\":false}\n",
+            "{\"data\":12.5,\"request_id\":1,\"error\":\"success\"}\n",
+        );
+        let mut requests = Vec::new();
+        let value = client(responses, &mut requests).get_property("time-pos");
+
+        assert_eq!(value.unwrap(), Some(12.5));
+        let request: Value = serde_json::from_slice(&requests).unwrap();
+        assert_eq!(request["command"], json!(["get_property", "time-pos"]));
+        assert_eq!(request["request_id"], json!(1));
+    }
+
+    #[test]
+    fn get_property_waits_for_its_own_request_id() {
+        let responses = concat!(
+            "{\"data\":1.0,\"request_id\":1,\"error\":\"success\"}\n",
+            "{\"data\":99.0,\"request_id\":7,\"error\":\"success\"}\n",
+            "{\"data\":2.0,\"request_id\":2,\"error\":\"success\"}\n",
+        );
+        let mut requests = Vec::new();
+        let mut client = client(responses, &mut requests);
+
+        assert_eq!(client.get_property("time-pos").unwrap(), Some(1.0));
+        assert_eq!(client.get_property("duration").unwrap(), Some(2.0));
+    }
+
+    #[test]
+    fn get_property_is_none_while_unavailable() {
+        let responses = "{\"request_id\":1,\"error\":\"property unavailable\"}\n";
+        let mut requests = Vec::new();
+
+        assert_eq!(
+            client(responses, &mut requests)
+                .get_property("duration")
+                .unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn get_property_fails_once_mpv_closes_the_connection() {
+        let responses = "{\"event\":\"shutdown\"}\n";
+        let mut requests = Vec::new();
+
+        assert!(client(responses, &mut requests)
+            .get_property("time-pos")
+            .is_err());
+    }
+
+    #[test]
+    fn playback_passes_the_threshold_with_a_known_duration() {
+        assert!(playback(Some(90.0), Some(100.0)).passed(0.9));
+        assert!(!playback(Some(89.0), Some(100.0)).passed(0.9));
+        assert!(!playback(Some(90.0), None).passed(0.9));
+        assert!(!playback(Some(90.0), Some(0.0)).passed(0.9));
+        assert!(!playback(None, Some(100.0)).passed(0.9));
+    }
+
+    #[test]
+    fn resume_position_skips_the_start_and_the_end() {
+        let resume = playback(Some(42.0), Some(100.0))
+            .resume_position("a", 0.9)
+            .unwrap();
+        assert_eq!(resume.video(), "a");
+        assert_eq!(resume.position(), 42.0);
+        assert_eq!(resume.duration(), Some(100.0));
+
+        assert!(playback(Some(MIN_RESUME_POSITION), Some(100.0))
+            .resume_position("a", 0.9)
+            .is_some());
+        assert!(playback(Some(4.0), Some(100.0))
+            .resume_position("a", 0.9)
+            .is_none());
+        assert!(playback(Some(95.0), Some(100.0))
+            .resume_position("a", 0.9)
+            .is_none());
+        assert!(playback(None, Some(100.0))
+            .resume_position("a", 0.9)
+            .is_none());
+    }
+
+    #[test]
+    fn expand_fills_in_the_placeholders() {
+        let arguments = profile("--start={start} --sub-file={subtitle} \"--title={title}\" {path}")
+            .expand(&launch(Some(61.5), Some("/videos/a b.srt"), "A B"));
+
+        assert_eq!(
+            arguments,
+            [
+                "--start=61.500",
+                "--sub-file=/videos/a b.srt",
+                "--title=A B",
+                "/videos/a b.mkv"
+            ]
+        );
+    }
+
+    #[test]
+    fn expand_leaves_out_arguments_without_a_value() {
+        let arguments = profile("--start={start} --sub-file={subtitle} --fullscreen {path}")
+            .expand(&launch(None, None, "a"));
+
+        assert_eq!(arguments, ["--fullscreen", "/videos/a b.mkv"]);
+    }
+
+    #[test]
+    fn expand_adds_the_path_without_a_placeholder() {
+        let arguments = profile("--fullscreen").expand(&launch(None, None, "a"));
+
+        assert_eq!(arguments, ["--fullscreen", "/videos/a b.mkv"]);
+    }
+
+    #[test]
+    fn expand_keeps_placeholders_inside_values() {
+        let arguments = profile("--title={title} --start={start}").expand(&launch(
+            Some(1.0),
+            None,
+            "{path} {start} {subtitle}",
+        ));
+
+        assert_eq!(
+            arguments,
+            [
+                "--title={path} {start} {subtitle}",
+                "--start=1.000",
+                "/videos/a b.mkv"
+            ]
+        );
+    }
+}
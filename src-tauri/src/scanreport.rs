@@ -0,0 +1,151 @@
+use std::io;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::util;
+
+#[derive(Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum ScanIssue {
+    // The file or folder could not be read
+    Unreadable,
+    PermissionDenied,
+    // ffmpeg could not find a video stream in the file
+    Rejected,
+    // Left out on purpose, the message says why
+    Skipped,
+}
+
+impl ScanIssue {
+    pub fn name(&self) -> &'static str {
+        match self {
+            ScanIssue::Unreadable => "unreadable",
+            ScanIssue::PermissionDenied => "permission_denied",
+            ScanIssue::Rejected => "rejected",
+            ScanIssue::Skipped => "skipped",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Self {
+        match name {
+            "permission_denied" => ScanIssue::PermissionDenied,
+            "rejected" => ScanIssue::Rejected,
+            "skipped" => ScanIssue::Skipped,
+            _ => ScanIssue::Unreadable,
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct ScanReportEntry {
+    path: PathBuf,
+    issue: ScanIssue,
+    message: String,
+}
+
+impl ScanReportEntry {
+    pub fn new<P: AsRef<Path>>(path: P, issue: ScanIssue, message: String) -> Self {
+        Self {
+            path: path.as_ref().to_path_buf(),
+            issue,
+            message,
+        }
+    }
+
+    pub fn from_io<P: AsRef<Path>>(path: P, error: &io::Error) -> Self {
+        let issue = match error.kind() {
+            io::ErrorKind::PermissionDenied => ScanIssue::PermissionDenied,
+            _ => ScanIssue::Unreadable,
+        };
+        Self::new(path, issue, error.to_string())
+    }
+
+    pub fn path(&self) -> &PathBuf {
+        &self.path
+    }
+    pub fn issue(&self) -> ScanIssue {
+        self.issue
+    }
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+}
+
+// Everything a scan of a library root left out or failed on. Files without a
+// video extension are not listed, they would drown out the rest.
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct ScanReport {
+    path: PathBuf,
+    job: Option<i64>,
+    created: u64,
+    files: usize,
+    videos: usize,
+    entries: Vec<ScanReportEntry>,
+}
+
+impl ScanReport {
+    pub fn new<P: AsRef<Path>>(path: P, job: Option<i64>) -> Self {
+        Self::load(path, job, util::now_millis(), 0, 0, Vec::new())
+    }
+
+    pub fn load<P: AsRef<Path>>(
+        path: P,
+        job: Option<i64>,
+        created: u64,
+        files: usize,
+        videos: usize,
+        entries: Vec<ScanReportEntry>,
+    ) -> Self {
+        Self {
+            path: path.as_ref().to_path_buf(),
+            job,
+            created,
+            files,
+            videos,
+            entries,
+        }
+    }
+
+    pub fn add(&mut self, entry: ScanReportEntry) {
+        debug!(
+            "Scan {} {}: {}",
+            entry.issue.name(),
+            entry.path.display(),
+            entry.message
+        );
+        self.entries.push(entry);
+    }
+
+    pub fn extend(&mut self, entries: Vec<ScanReportEntry>) {
+        entries.into_iter().for_each(|e| self.add(e));
+    }
+
+    pub fn set_counts(&mut self, files: usize, videos: usize) {
+        self.files = files;
+        self.videos = videos;
+    }
+
+    pub fn sort(&mut self) {
+        self.entries.sort_by(|a, b| a.path.cmp(&b.path));
+    }
+
+    pub fn path(&self) -> &PathBuf {
+        &self.path
+    }
+    pub fn job(&self) -> Option<i64> {
+        self.job
+    }
+    pub fn created(&self) -> u64 {
+        self.created
+    }
+    pub fn files(&self) -> usize {
+        self.files
+    }
+    pub fn videos(&self) -> usize {
+        self.videos
+    }
+    pub fn entries(&self) -> &[ScanReportEntry] {
+        &self.entries
+    }
+}
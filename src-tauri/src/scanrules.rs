@@ -0,0 +1,148 @@
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use ignore::gitignore::Gitignore;
+use serde::{Deserialize, Serialize};
+
+pub const IGNORE_FILE_NAME: &str = ".vidlibignore";
+
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct ScanRule {
+    pattern: String,
+    include: bool,
+}
+
+impl ScanRule {
+    pub fn new(pattern: String, include: bool) -> Self {
+        Self { pattern, include }
+    }
+
+    pub fn pattern(&self) -> &str {
+        &self.pattern
+    }
+    pub fn include(&self) -> bool {
+        self.include
+    }
+}
+
+// Root globs plus the .vidlibignore files found on the way down to the
+// directory currently being read. Entering a directory shares the root globs
+// and only adds a layer when the directory has its own ignore file.
+#[derive(Clone)]
+pub struct ScanRules {
+    globs: Arc<RootGlobs>,
+    ignore: Option<Arc<IgnoreLayer>>,
+}
+
+struct RootGlobs {
+    root: PathBuf,
+    include: Option<GlobSet>,
+    exclude: GlobSet,
+}
+
+// An ignore file and the ones of the directories above it
+struct IgnoreLayer {
+    gitignore: Gitignore,
+    parent: Option<Arc<IgnoreLayer>>,
+}
+
+impl ScanRules {
+    pub fn new<P: AsRef<Path>>(root: P, rules: &[ScanRule]) -> Self {
+        let include = rules.iter().filter(|r| r.include()).collect::<Vec<_>>();
+        let exclude = rules.iter().filter(|r| !r.include()).collect::<Vec<_>>();
+        let rules = Self {
+            globs: Arc::new(RootGlobs {
+                root: root.as_ref().to_path_buf(),
+                include: if include.is_empty() {
+                    None
+                } else {
+                    Some(build_glob_set(&include))
+                },
+                exclude: build_glob_set(&exclude),
+            }),
+            ignore: None,
+        };
+        rules.enter(root)
+    }
+
+    pub fn enter<P: AsRef<Path>>(&self, dir: P) -> Self {
+        let ignore_file = dir.as_ref().join(IGNORE_FILE_NAME);
+        if !ignore_file.is_file() {
+            return self.clone();
+        }
+        let (gitignore, error) = Gitignore::new(&ignore_file);
+        if let Some(e) = error {
+            error!("Ignore file {} has errors: {}", ignore_file.display(), e);
+        }
+        Self {
+            globs: Arc::clone(&self.globs),
+            ignore: Some(Arc::new(IgnoreLayer {
+                gitignore,
+                parent: self.ignore.clone(),
+            })),
+        }
+    }
+
+    pub fn is_ignored<P: AsRef<Path>>(&self, path: P, is_dir: bool) -> bool {
+        let path = path.as_ref();
+        let mut layer = self.ignore.as_deref();
+        while let Some(current) = layer {
+            let matched = current.gitignore.matched(path, is_dir);
+            if matched.is_ignore() {
+                return true;
+            } else if matched.is_whitelist() {
+                break;
+            }
+            layer = current.parent.as_deref();
+        }
+        let relative = path.strip_prefix(&self.globs.root).unwrap_or(path);
+        if self.globs.exclude.is_match(relative) {
+            return true;
+        }
+        !is_dir
+            && self
+                .globs
+                .include
+                .as_ref()
+                .map_or(false, |include| !include.is_match(relative))
+    }
+
+    // Checks every directory between the root and the path, for paths that
+    // are not reached through a directory walk
+    pub fn is_ignored_in_tree<P: AsRef<Path>>(&self, path: P, is_dir: bool) -> bool {
+        let path = path.as_ref();
+        let relative = match path.strip_prefix(&self.globs.root) {
+            Ok(relative) => relative,
+            Err(_) => return true,
+        };
+        let mut rules = self.clone();
+        let mut dir = self.globs.root.clone();
+        if let Some(parent) = relative.parent() {
+            for component in parent.components() {
+                dir.push(component);
+                if rules.is_ignored(&dir, true) {
+                    return true;
+                }
+                rules = rules.enter(&dir);
+            }
+        }
+        rules.is_ignored(path, is_dir)
+    }
+}
+
+fn build_glob_set(rules: &[&ScanRule]) -> GlobSet {
+    let mut builder = GlobSetBuilder::new();
+    for rule in rules {
+        match Glob::new(rule.pattern()) {
+            Ok(glob) => {
+                builder.add(glob);
+            }
+            Err(e) => error!("Invalid scan rule {}: {}", rule.pattern(), e),
+        }
+    }
+    builder.build().unwrap_or_else(|e| {
+        error!("Scan rules can't be built: {}", e);
+        GlobSet::empty()
+    })
+}
@@ -4,14 +4,17 @@ use std::sync::Mutex;
 
 use rusqlite::Connection;
 use serde::Serialize;
-use slab_tree::Tree;
+use tauri::{AppHandle, Manager};
 
-use crate::folderscan::Folder;
+use crate::folderscan::FolderTree;
+use crate::history::WatchSession;
+use crate::jobs::JobRegistry;
 use crate::mediainfo::VideoMediaInfoChannelMessage;
 use crate::thumbnail::ThumbnailChannelMessage;
 use crate::video::VideoEntry;
 use crate::{database, thumbnail, EmitProgress};
 
+#[derive(Clone)]
 pub struct VideoCache {
     items: HashMap<String, VideoCacheItem>,
     add: HashMap<String, VideoCacheItem>,
@@ -27,49 +30,146 @@ impl VideoCache {
         }
     }
 
-    pub fn get_video(&self, p: &PathBuf) -> Option<&VideoCacheItem> {
-        let p = p.display().to_string();
-        self.items.get(&p)
+    pub fn get_video<P: AsRef<Path>>(&self, p: P) -> Option<&VideoCacheItem> {
+        let p = p.as_ref().display().to_string();
+        self.add.get(&p).or_else(|| self.items.get(&p))
+    }
+
+    pub fn get_videos_in<P: AsRef<Path>>(&self, p: P) -> Vec<(PathBuf, VideoCacheItem)> {
+        self.items
+            .iter()
+            .map(|(k, v)| (PathBuf::from(k), v))
+            .filter(|(k, _)| k.starts_with(p.as_ref()))
+            .map(|(k, v)| (k, v.clone()))
+            .collect()
+    }
+
+    pub fn get_videos_by_id(&self, id: &str) -> Vec<(PathBuf, VideoCacheItem)> {
+        self.items
+            .iter()
+            .filter(|(_, v)| v.is_video() && v.id() == id)
+            .map(|(k, v)| (PathBuf::from(k), v.clone()))
+            .collect()
+    }
+
+    // Every path of each video, for lookups of many ids at once
+    pub fn paths_by_id(&self) -> HashMap<String, Vec<PathBuf>> {
+        let mut paths: HashMap<String, Vec<PathBuf>> = HashMap::new();
+        self.items
+            .iter()
+            .filter(|(_, v)| v.is_video())
+            .for_each(|(k, v)| paths.entry(v.id().to_string()).or_default().push(k.into()));
+        paths
+    }
+
+    pub fn location_counts(&self) -> HashMap<String, usize> {
+        let mut counts = HashMap::new();
+        self.items
+            .values()
+            .filter(|v| v.is_video())
+            .for_each(|v| *counts.entry(v.id().to_string()).or_insert(0) += 1);
+        counts
     }
 
     pub fn add_video<P: AsRef<Path>>(&mut self, p: P, v: VideoCacheItem) {
-        let _ = &self.add.insert(p.as_ref().display().to_string(), v);
+        let p = p.as_ref().display().to_string();
+        let _ = &self.delete.retain(|d| d != &p);
+        let _ = &self.add.insert(p, v);
     }
 
     pub fn delete_video<P: AsRef<Path>>(&mut self, p: P) {
-        let _ = &self.delete.push(p.as_ref().display().to_string());
+        let p = p.as_ref().display().to_string();
+        let _ = &self.add.remove(&p);
+        if !self.delete.contains(&p) {
+            let _ = &self.delete.push(p);
+        }
+    }
+
+    pub fn move_video<P: AsRef<Path>, Q: AsRef<Path>>(&mut self, from: P, to: Q) {
+        if let Some(v) = self.get_video(&from).cloned() {
+            self.delete_video(from);
+            self.add_video(to, v);
+        }
+    }
+
+    // Hands the pending changes over and applies them to this cache only
+    pub fn take_changes(&mut self) -> VideoCacheChanges {
+        let add = std::mem::take(&mut self.add);
+        let delete = std::mem::take(&mut self.delete);
+        delete.iter().for_each(|p| {
+            self.items.remove(p);
+        });
+        add.iter().for_each(|(p, v)| {
+            self.items.insert(p.clone(), v.clone());
+        });
+        VideoCacheChanges { add, delete }
     }
 
-    pub fn commit(&mut self, connection: &Connection) {
+    pub fn merge(&mut self, changes: VideoCacheChanges) {
+        changes.delete.iter().for_each(|p| self.delete_video(p));
+        changes
+            .add
+            .into_iter()
+            .for_each(|(p, v)| self.add_video(p, v));
+    }
+
+    // Returns the ids of the videos whose media info was dropped as stale
+    pub fn commit(&mut self, connection: &Connection) -> Vec<String> {
+        let mut dropped = Vec::new();
         for p in &self.delete {
             database::delete_video_cache(connection, p);
             let _ = &self.items.remove(p);
         }
         let _ = &self.delete.clear();
         for (p, v) in &self.add {
-            database::add_video_cache(connection, p, &v.filesize, &v.id);
+            dropped.extend(database::add_video_cache(connection, p, v));
             let _ = &self.items.insert(p.clone(), v.clone());
         }
         let _ = &self.add.clear();
+        dropped
     }
 }
 
+// Changes made to a copy of the cache, scans work on a copy so the shared
+// cache is only locked while their results are merged
+pub struct VideoCacheChanges {
+    add: HashMap<String, VideoCacheItem>,
+    delete: Vec<String>,
+}
+
 #[derive(Clone)]
 pub struct VideoCacheItem {
     filesize: u64,
+    mtime: Option<u64>,
     id: String,
+    video: bool,
 }
 
 impl VideoCacheItem {
-    pub fn new(filesize: u64, id: String) -> Self {
-        Self { filesize, id }
+    pub fn new(filesize: u64, mtime: Option<u64>, id: String, video: bool) -> Self {
+        Self {
+            filesize,
+            mtime,
+            id,
+            video,
+        }
     }
     pub fn filesize(&self) -> u64 {
         self.filesize
     }
+    pub fn mtime(&self) -> Option<u64> {
+        self.mtime
+    }
     pub fn id(&self) -> &str {
         &self.id
     }
+    pub fn is_video(&self) -> bool {
+        self.video
+    }
+    /// Rows written before mtime was tracked only have their size compared.
+    pub fn is_current(&self, filesize: u64, mtime: Option<u64>) -> bool {
+        self.filesize == filesize && (self.mtime.is_none() || self.mtime == mtime)
+    }
 }
 
 pub struct AppState {
@@ -81,7 +181,22 @@ pub struct AppState {
     pub mediainfo_channel:
         tokio::sync::Mutex<tokio::sync::mpsc::Sender<VideoMediaInfoChannelMessage>>,
     pub folder_channel: tokio::sync::Mutex<tokio::sync::mpsc::Sender<PathBuf>>,
-    pub folders: Mutex<Option<Tree<Folder>>>,
+    pub folders: Mutex<Option<FolderTree>>,
+    pub jobs: Mutex<JobRegistry>,
+    pub session: WatchSession,
+}
+
+// Merges what a scan changed in its copy into the shared cache and saves it.
+// The database and the shared cache are only locked for the merge.
+pub fn commit_video_cache(app: &AppHandle, cache: &mut VideoCache) {
+    let state = app.state::<AppState>();
+    let db_guard = state.db.lock().unwrap();
+    let mut cache_guard = state.video_cache.lock().unwrap();
+    if let (Some(db), Some(shared)) = (db_guard.as_ref(), cache_guard.as_mut()) {
+        shared.merge(cache.take_changes());
+        let dropped = shared.commit(db);
+        thumbnail::delete_chapter_thumbnails(thumbnail::get_thumbnail_save_location(app), &dropped);
+    }
 }
 
 pub fn get_video_cache(connection: &Connection) -> VideoCache {
@@ -21,17 +21,33 @@ use tokio::sync;
 
 use crate::{state, util};
 
+// Seconds after the chapter start the chapter thumbnail is taken at
+const CHAPTER_THUMBNAIL_OFFSET: f64 = 2.0;
+
 // Thumbnail Cache
 pub struct ThumbnailCache {
     thumbnails: collections::HashMap<String, ThumbnailEntry>,
+    // Chapter thumbnails sent to the thumbnail channel and not created yet.
+    // Failed ones are released as well, so the next request tries again.
+    pending_chapters: collections::HashSet<PathBuf>,
 }
 impl ThumbnailCache {
     pub fn new() -> Self {
         Self {
             thumbnails: collections::HashMap::new(),
+            pending_chapters: collections::HashSet::new(),
         }
     }
 
+    // False when the thumbnail was already requested
+    pub fn request_chapter(&mut self, path: &Path) -> bool {
+        self.pending_chapters.insert(path.to_path_buf())
+    }
+
+    pub fn finish_chapter(&mut self, path: &Path) {
+        self.pending_chapters.remove(path);
+    }
+
     pub fn add_thumbnail_entry(&mut self, id: &str, path: &PathBuf) {
         self.thumbnails
             .entry(id.to_string())
@@ -94,6 +110,39 @@ pub fn get_thumbnail_save_location(app: &AppHandle) -> PathBuf {
     thumbnail_path
 }
 
+// Chapter thumbnails live in their own folder so the cache of the video
+// thumbnails doesn't pick them up
+pub fn get_chapter_thumbnail_path<P: AsRef<Path>>(
+    save_location: P,
+    id: &str,
+    index: usize,
+) -> PathBuf {
+    save_location
+        .as_ref()
+        .join("chapters")
+        .join(format!("{}_{:03}.png", id, index))
+}
+
+// Removes the chapter thumbnails of the videos, their chapters may differ once
+// the media info is read again
+pub fn delete_chapter_thumbnails<P: AsRef<Path>>(save_location: P, ids: &[String]) {
+    if ids.is_empty() {
+        return;
+    }
+    let Ok(entries) = fs::read_dir(save_location.as_ref().join("chapters")) else {
+        return;
+    };
+    for entry in entries.flatten() {
+        let name = entry.file_name();
+        let name = name.to_string_lossy();
+        if ids.iter().any(|id| name.starts_with(&format!("{}_", id))) {
+            if let Err(e) = fs::remove_file(entry.path()) {
+                error!("Chapter thumbnail can't be deleted: {}", e);
+            }
+        }
+    }
+}
+
 pub async fn find_thumbnail_path_in_cache(
     state: &tauri::State<'_, state::AppState>,
     id: &String,
@@ -125,11 +174,25 @@ fn validate_thumbnail(v: &[PathBuf]) -> bool {
 pub struct ThumbnailChannelMessage {
     path: PathBuf,
     id: String,
+    // Index, start and end in seconds of the chapter the thumbnail is for
+    chapter: Option<(usize, f64, f64)>,
 }
 
 impl ThumbnailChannelMessage {
     pub fn new(path: PathBuf, id: String) -> Self {
-        Self { path, id }
+        Self {
+            path,
+            id,
+            chapter: None,
+        }
+    }
+
+    pub fn chapter(path: PathBuf, id: String, index: usize, start: f64, end: f64) -> Self {
+        Self {
+            path,
+            id,
+            chapter: Some((index, start, end)),
+        }
     }
 }
 
@@ -172,6 +235,31 @@ pub async fn process_thumbnail_input_channels(
 ) -> Result<(), Error> {
     while let Some(input) = thumbnail_input_rx.recv().await {
         debug!("Message received in thumbnail input {}", input);
+        if let Some((index, start, end)) = input.chapter {
+            let path = get_chapter_thumbnail_path(save_location, &input.id, index);
+            let result = path
+                .parent()
+                .map_or(Ok(()), fs::create_dir_all)
+                .map_err(Error::from)
+                .and_then(|_| create_chapter_thumbnail(&path, &input.path, start, end));
+            if let Some(c) = thumbnail_cache.lock().await.as_mut() {
+                c.finish_chapter(&path);
+            }
+            match result {
+                Ok(path) => {
+                    if let Err(e) = thumbnail_output_tx
+                        .send(ThumbnailChannelMessage::chapter(
+                            path, input.id, index, start, end,
+                        ))
+                        .await
+                    {
+                        error!("Failed to send thumbnail output: {}", e);
+                    }
+                }
+                Err(e) => error!("Chapter thumbnail creation failed: {}", e),
+            }
+            continue;
+        }
         let id = &input.id.clone();
         if let Ok(path) =
             create_and_send_thumbnail(save_location, input, &thumbnail_output_tx).await
@@ -196,6 +284,16 @@ pub async fn process_thumbnail_output_channels(
 ) -> Result<(), Error> {
     while let Some(output) = thumbnail_output_rx.recv().await {
         debug!("Message received in thumbnail output {}", output);
+        if let Some((index, _, _)) = output.chapter {
+            let _ = app.emit_all(
+                &format!("update_chapter_thumbnail_{}", output.id),
+                ChapterThumbnailEmitEvent {
+                    index,
+                    path: output.path,
+                },
+            );
+            continue;
+        }
         let _ = app.emit_all(
             &format!("update_thumbnail_{}", output.id),
             ThumbnailEmitEvent::new(output.path),
@@ -217,6 +315,12 @@ impl ThumbnailEmitEvent {
     }
 }
 
+#[derive(Clone, Serialize)]
+pub struct ChapterThumbnailEmitEvent {
+    index: usize,
+    path: PathBuf,
+}
+
 // Creator
 fn create_thumbnail<P: AsRef<Path>, R: AsRef<Path>>(
     save_location: P,
@@ -225,12 +329,27 @@ fn create_thumbnail<P: AsRef<Path>, R: AsRef<Path>>(
 ) -> Result<PathBuf, Error> {
     let file_name = format!("{}_01.png", id);
     let full_location = save_location.as_ref().join(file_name);
-    generate_thumbnail(full_location, video_location)
+    generate_thumbnail(full_location, video_location, None)
 }
 
+// Takes the frame a little after the chapter start, which is often black.
+// Chapters shorter than twice the offset use their middle frame instead.
+fn create_chapter_thumbnail<P: AsRef<Path>, R: AsRef<Path>>(
+    save_location: P,
+    video_location: R,
+    start: f64,
+    end: f64,
+) -> Result<PathBuf, Error> {
+    let seconds = (start + CHAPTER_THUMBNAIL_OFFSET).min((start + end) / 2.0);
+    let position = (seconds * ffi::AV_TIME_BASE as f64) as i64;
+    generate_thumbnail(save_location, video_location, Some(position))
+}
+
+// Without a position the frame is taken from the middle of the video
 fn generate_thumbnail<P: AsRef<Path>, R: AsRef<Path>>(
     save_location: P,
     video_location: R,
+    position: Option<i64>,
 ) -> Result<PathBuf, Error> {
     debug!(
         "Generate thumbnail started {}",
@@ -240,8 +359,11 @@ fn generate_thumbnail<P: AsRef<Path>, R: AsRef<Path>>(
     debug!("Input context created");
     let (video_index, mut decoder_context) = create_decoder_context(&mut input_context)?;
     debug!("Decoding context created");
-    seek_to_middle(&mut input_context);
-    debug!("Seeked to middle of the video");
+    match position {
+        Some(position) => seek_to(&mut input_context, position),
+        None => seek_to_middle(&mut input_context),
+    }
+    debug!("Seeked to the thumbnail position");
     let thumbnail_frame =
         get_thumbnail_frame(&mut input_context, video_index, &mut decoder_context)?;
     debug!("Found thumbnail frame");
@@ -354,11 +476,16 @@ fn seek_to_middle(input_context: &mut AVFormatContextInput) {
         } else {
             0
         });
+    seek_to(input_context, duration / 2);
+}
+
+// Position in AV_TIME_BASE units
+fn seek_to(input_context: &mut AVFormatContextInput, position: i64) {
     unsafe {
         av_seek_frame(
             input_context.as_mut_ptr(),
             -1,
-            duration / 2,
+            position,
             (AVSEEK_FLAG_BACKWARD | AVSEEK_FLAG_FRAME) as i32,
         )
     };
@@ -1,5 +1,7 @@
 use std::env;
 use std::path::PathBuf;
+use std::thread;
+use std::time::{SystemTime, UNIX_EPOCH};
 
 use log::LevelFilter;
 use tauri::AppHandle;
@@ -30,6 +32,22 @@ fn log_enabled() -> bool {
             .map_or(false, |n| n >= 1)
 }
 
+// Milliseconds since the unix epoch
+pub fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+pub fn get_scan_workers() -> usize {
+    env::var("VIDLIB_SCAN_WORKERS")
+        .ok()
+        .and_then(|v| v.parse::<usize>().ok())
+        .filter(|n| *n > 0)
+        .unwrap_or_else(|| thread::available_parallelism().map_or(1, |n| n.get()))
+}
+
 pub fn get_log_targets() -> Vec<LogTarget> {
     if log_enabled() {
         vec![LogTarget::LogDir, LogTarget::Stdout, LogTarget::Webview]
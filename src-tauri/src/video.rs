@@ -1,18 +1,87 @@
 use std::fs::File;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
 use rsmpeg::ffi::AVMediaType_AVMEDIA_TYPE_VIDEO;
 use serde::{Deserialize, Serialize};
 
 use crate::thumbnail;
 
-#[derive(Deserialize, Serialize, Clone)]
+#[derive(Deserialize, Serialize, Clone, Debug)]
 pub struct VideoCategory {
     id: usize,
     name: String,
     icon: String,
 }
 
+impl VideoCategory {
+    pub fn new(id: usize, name: String, icon: String) -> Self {
+        Self { id, name, icon }
+    }
+
+    pub fn id(&self) -> usize {
+        self.id
+    }
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+    pub fn icon(&self) -> &str {
+        &self.icon
+    }
+}
+
+// A tag can have a parent tag, the path joins the names of the tag and its
+// parents, e.g. genre/comedy
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct VideoTag {
+    id: usize,
+    name: String,
+    parent: Option<usize>,
+    path: String,
+    color: Option<String>,
+    icon: Option<String>,
+}
+
+impl VideoTag {
+    pub fn new(
+        id: usize,
+        name: String,
+        parent: Option<usize>,
+        color: Option<String>,
+        icon: Option<String>,
+    ) -> Self {
+        Self {
+            path: name.clone(),
+            id,
+            name,
+            parent,
+            color,
+            icon,
+        }
+    }
+
+    pub fn id(&self) -> usize {
+        self.id
+    }
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+    pub fn parent(&self) -> Option<usize> {
+        self.parent
+    }
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+    pub fn color(&self) -> Option<&String> {
+        self.color.as_ref()
+    }
+    pub fn icon(&self) -> Option<&String> {
+        self.icon.as_ref()
+    }
+    pub fn set_path(&mut self, path: String) {
+        self.path = path;
+    }
+}
+
 #[derive(Deserialize, Serialize, Clone)]
 pub struct VideoEntry {
     name: String,
@@ -20,6 +89,8 @@ pub struct VideoEntry {
     notes: String,
     watched: bool,
     category: Option<VideoCategory>,
+    #[serde(default)]
+    tags: Vec<VideoTag>,
 }
 
 impl VideoEntry {
@@ -30,6 +101,7 @@ impl VideoEntry {
             notes,
             watched,
             category: None,
+            tags: Vec::new(),
         }
     }
 
@@ -45,6 +117,27 @@ impl VideoEntry {
     pub fn watched(&self) -> bool {
         self.watched
     }
+    pub fn category(&self) -> Option<&VideoCategory> {
+        self.category.as_ref()
+    }
+    pub fn set_category(&mut self, category: Option<VideoCategory>) {
+        self.category = category;
+    }
+    pub fn tags(&self) -> &[VideoTag] {
+        &self.tags
+    }
+    pub fn set_tags(&mut self, tags: Vec<VideoTag>) {
+        self.tags = tags;
+    }
+    pub fn add_tag(&mut self, tag: VideoTag) {
+        if !self.tags.iter().any(|t| t.id == tag.id) {
+            self.tags.push(tag);
+            self.tags.sort_by(|a, b| a.path.cmp(&b.path));
+        }
+    }
+    pub fn remove_tag(&mut self, id: usize) {
+        self.tags.retain(|t| t.id != id);
+    }
     pub fn set_rating(&mut self, rating: usize) {
         self.rating = rating;
     }
@@ -59,6 +152,125 @@ impl VideoEntry {
     }
 }
 
+// One change applied to many videos at once by update_videos
+#[derive(Deserialize, Serialize, Clone, Debug)]
+#[serde(tag = "field", content = "value", rename_all = "snake_case")]
+pub enum VideoChange {
+    Watched(bool),
+    Rating(usize),
+    Notes(String),
+    // Category id, None clears the category
+    Category(Option<usize>),
+    AddTag(usize),
+    RemoveTag(usize),
+}
+
+impl VideoChange {
+    // The category and tag are the ones the change refers to, looked up by
+    // the caller
+    pub fn apply(
+        &self,
+        entry: &mut VideoEntry,
+        category: Option<&VideoCategory>,
+        tag: Option<&VideoTag>,
+    ) {
+        match self {
+            VideoChange::Watched(watched) => entry.set_watched(*watched),
+            VideoChange::Rating(rating) => entry.set_rating(*rating),
+            VideoChange::Notes(notes) => entry.set_notes(notes.clone()),
+            VideoChange::Category(_) => entry.set_category(category.cloned()),
+            VideoChange::AddTag(_) => {
+                if let Some(tag) = tag {
+                    entry.add_tag(tag.clone());
+                }
+            }
+            VideoChange::RemoveTag(id) => entry.remove_tag(*id),
+        }
+    }
+}
+
+// The videos a bulk change applies to, either by id or every video below a
+// folder of the index
+#[derive(Deserialize, Clone, Debug)]
+#[serde(tag = "kind", content = "value", rename_all = "snake_case")]
+pub enum VideoSelection {
+    Ids(Vec<String>),
+    Folder(String),
+}
+
+// What the user set on a folder, a folder usually stands for a series,
+// season or collection. The name replaces the folder name in the views.
+#[derive(Deserialize, Serialize, Clone, Default, Debug)]
+pub struct FolderEntry {
+    name: Option<String>,
+    rating: usize,
+    notes: String,
+    cover: Option<PathBuf>,
+}
+
+impl FolderEntry {
+    pub fn new(name: Option<String>, rating: usize, notes: String, cover: Option<PathBuf>) -> Self {
+        Self {
+            name,
+            rating,
+            notes,
+            cover,
+        }
+    }
+
+    pub fn name(&self) -> Option<&String> {
+        self.name.as_ref()
+    }
+    pub fn rating(&self) -> usize {
+        self.rating
+    }
+    pub fn notes(&self) -> &str {
+        &self.notes
+    }
+    pub fn cover(&self) -> Option<&PathBuf> {
+        self.cover.as_ref()
+    }
+    pub fn set_name(&mut self, name: Option<String>) {
+        self.name = name;
+    }
+    pub fn set_rating(&mut self, rating: usize) {
+        self.rating = rating;
+    }
+    pub fn set_notes(&mut self, notes: String) {
+        self.notes = notes;
+    }
+    pub fn set_cover(&mut self, cover: Option<PathBuf>) {
+        self.cover = cover;
+    }
+}
+
+#[derive(Deserialize, Serialize, Clone)]
+pub struct VideoLocation {
+    path: PathBuf,
+    preferred: bool,
+    available: bool,
+}
+
+impl VideoLocation {
+    pub fn new(path: PathBuf, preferred: bool, available: bool) -> Self {
+        Self {
+            path,
+            preferred,
+            available,
+        }
+    }
+
+    pub fn path(&self) -> &PathBuf {
+        &self.path
+    }
+    pub fn preferred(&self) -> bool {
+        self.preferred
+    }
+    pub fn available(&self) -> bool {
+        self.available
+    }
+}
+
 pub const VIDEO_FILE_EXTENSIONS: &[&str] = &[
     "3g2", "3gp", "3gp2", "3gpp", "amv", "asf", "avi", "bik", "dds", "divx", "dpg", "dv", "dvr-ms",
     "evo", "f4v", "flv", "hdmov", "k3g", "m1v", "m2t", "m2ts", "m2v", "m4b", "m4p", "m4v", "mk3d",
@@ -80,7 +292,7 @@ fn is_file_openable<P: AsRef<Path>>(path: P) -> bool {
     File::open(path).is_ok()
 }
 
-fn has_video_extension<P: AsRef<Path>>(path: P) -> bool {
+pub(crate) fn has_video_extension<P: AsRef<Path>>(path: P) -> bool {
     path.as_ref()
         .extension()
         .map(|e| VIDEO_FILE_EXTENSIONS.contains(&e.to_string_lossy().as_ref()))
@@ -0,0 +1,691 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{Receiver, RecvTimeoutError, SyncSender};
+use std::time::Duration;
+
+use anyhow::Error;
+use notify::event::{ModifyKind, RenameMode};
+use notify::{Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use notify_debouncer_full::{
+    new_debouncer, DebounceEventResult, DebouncedEvent, Debouncer, FileIdMap,
+};
+use serde::Serialize;
+use tauri::{AppHandle, Manager};
+
+use crate::filescan::{probe_cached, ScanSettings, Traversal, VideoFile};
+use crate::identity::IdentityStrategy;
+use crate::scanrules::ScanRules;
+use crate::state::{AppState, VideoCache};
+use crate::video::has_video_extension;
+use crate::{database, thumbnail};
+
+const DEBOUNCE_TIMEOUT: Duration = Duration::from_secs(2);
+const ROOT_CHECK_INTERVAL: Duration = Duration::from_secs(5);
+
+// Events
+#[derive(Clone, Serialize)]
+pub struct VideoAddedEmitEvent {
+    video: VideoFile,
+    folder: PathBuf,
+}
+
+#[derive(Clone, Serialize)]
+pub struct VideoRemovedEmitEvent {
+    id: String,
+    path: PathBuf,
+}
+
+#[derive(Clone, Serialize)]
+pub struct VideoMovedEmitEvent {
+    id: String,
+    from: PathBuf,
+    to: PathBuf,
+}
+
+#[derive(Clone, Serialize)]
+pub struct PathAvailabilityEmitEvent {
+    path: PathBuf,
+}
+
+// Channels
+pub enum WatcherChannelMessage {
+    Events(Vec<DebouncedEvent>),
+    Errors(Vec<notify::Error>),
+}
+
+// Runs on its own thread, events are handled without holding the state locks
+// while files are probed
+pub fn process_watcher_channels(
+    app: &AppHandle,
+    watcher_tx: SyncSender<WatcherChannelMessage>,
+    watcher_rx: Receiver<WatcherChannelMessage>,
+) -> Result<(), Error> {
+    debug!("Watcher channel started");
+    let mut debouncer = new_debouncer(
+        DEBOUNCE_TIMEOUT,
+        None,
+        move |result: DebounceEventResult| {
+            let message = match result {
+                Ok(events) => WatcherChannelMessage::Events(events),
+                Err(errors) => WatcherChannelMessage::Errors(errors),
+            };
+            if let Err(e) = watcher_tx.send(message) {
+                error!("Failed to send watcher events: {}", e);
+            }
+        },
+    )?;
+    // Root path to whether it is currently being watched
+    let mut roots: HashMap<PathBuf, bool> = HashMap::new();
+    loop {
+        sync_roots(app, &mut debouncer, &mut roots);
+        match watcher_rx.recv_timeout(ROOT_CHECK_INTERVAL) {
+            Ok(WatcherChannelMessage::Events(events)) => {
+                debug!("Watcher received {} events", events.len());
+                process_events(app, &roots, events);
+            }
+            Ok(WatcherChannelMessage::Errors(errors)) => {
+                errors
+                    .iter()
+                    .for_each(|e| error!("Watcher error received: {}", e));
+            }
+            Err(RecvTimeoutError::Disconnected) => break,
+            Err(RecvTimeoutError::Timeout) => {}
+        }
+    }
+
+    Ok(())
+}
+
+// Keeps watched roots in line with the library paths and re-watches roots
+// that come back after being unmounted
+fn sync_roots(
+    app: &AppHandle,
+    debouncer: &mut Debouncer<RecommendedWatcher, FileIdMap>,
+    roots: &mut HashMap<PathBuf, bool>,
+) {
+    let state = app.state::<AppState>();
+    let paths = match state.db.lock().unwrap().as_ref().map(database::get_paths) {
+        Some(Ok(paths)) => paths,
+        _ => return,
+    };
+    roots.retain(|root, watched| {
+        let keep = paths.iter().any(|p| Path::new(p) == root);
+        if !keep && *watched {
+            debug!("Path removed from library, unwatching {}", root.display());
+            let _ = debouncer.watcher().unwatch(root);
+            debouncer.cache().remove_root(root);
+        }
+        keep
+    });
+    for path in paths.iter().map(PathBuf::from) {
+        let watched = roots.entry(path.clone()).or_insert(false);
+        if *watched && !path.is_dir() {
+            debug!("Watched path is no longer available {}", path.display());
+            let _ = debouncer.watcher().unwatch(&path);
+            debouncer.cache().remove_root(&path);
+            *watched = false;
+            let _ = app.emit_all("path_unavailable", PathAvailabilityEmitEvent { path });
+        } else if !*watched && path.is_dir() {
+            match debouncer.watcher().watch(&path, RecursiveMode::Recursive) {
+                Ok(_) => {
+                    debug!("Watching path {}", path.display());
+                    debouncer.cache().add_root(&path, RecursiveMode::Recursive);
+                    *watched = true;
+                    let _ = app.emit_all("path_available", PathAvailabilityEmitEvent { path });
+                }
+                Err(e) => error!("Failed to watch path {}: {}", path.display(), e),
+            }
+        }
+    }
+}
+
+// The settings of every root are read and the cache is copied under short
+// locks, the events are then handled without holding any lock while files are
+// walked and hashed. The state is only locked to apply the result.
+fn process_events(app: &AppHandle, roots: &HashMap<PathBuf, bool>, events: Vec<DebouncedEvent>) {
+    let state = app.state::<AppState>();
+    let settings: HashMap<PathBuf, ScanSettings> = match state.db.lock().unwrap().as_ref() {
+        Some(db) => roots
+            .keys()
+            .map(|root| (root.clone(), database::get_scan_settings(db, root)))
+            .collect(),
+        None => return,
+    };
+    let mut cache = match state.video_cache.lock().unwrap().clone() {
+        Some(cache) => cache,
+        None => return,
+    };
+    let events: Vec<Event> = events.into_iter().map(|e| e.event).collect();
+    let changes = handle_events(&mut cache, &settings, &events);
+    apply_changes(app, &mut cache, changes);
+}
+
+// What a batch of events changes in the index, applied in order once the
+// state is locked
+enum WatchChange {
+    Added(VideoFile, u64),
+    Removed(String, PathBuf),
+    Moved(String, PathBuf, PathBuf),
+    FolderMoved(PathBuf, PathBuf),
+    FolderRemoved(PathBuf),
+}
+
+// Updates the copy of the cache for the events and returns the changes to
+// apply to the folder index
+fn handle_events(
+    cache: &mut VideoCache,
+    roots: &HashMap<PathBuf, ScanSettings>,
+    events: &[Event],
+) -> Vec<WatchChange> {
+    let mut changes = Vec::new();
+    for event in events {
+        match event.kind {
+            EventKind::Modify(ModifyKind::Name(RenameMode::Both)) if event.paths.len() == 2 => {
+                let (from, to) = (&event.paths[0], &event.paths[1]);
+                let moved = cache.get_videos_in(from);
+                let folder_moved = to.is_dir() && !roots.contains_key(from);
+                if folder_moved {
+                    changes.push(WatchChange::FolderMoved(from.clone(), to.clone()));
+                }
+                let mut scan = find_root(roots, to).map(|(root, s)| RootScan::new(root, s));
+                if moved.is_empty() {
+                    if let Some(scan) = scan.as_mut() {
+                        add_videos(cache, scan, to, &mut changes);
+                    }
+                }
+                for (old, item) in moved {
+                    let new = match old.strip_prefix(from) {
+                        Ok(rest) if !rest.as_os_str().is_empty() => to.join(rest),
+                        _ => to.clone(),
+                    };
+                    // Moving into an ignored folder or out of the library
+                    // removes the video
+                    let kept = item.is_video()
+                        && has_video_extension(&new)
+                        && scan
+                            .as_ref()
+                            .map_or(false, |s| !s.rules.is_ignored_in_tree(&new, false));
+                    if !kept {
+                        cache.delete_video(&old);
+                        if item.is_video() {
+                            changes.push(WatchChange::Removed(item.id().to_string(), old));
+                        }
+                        continue;
+                    }
+                    cache.move_video(&old, &new);
+                    changes.push(WatchChange::Moved(item.id().to_string(), old, new));
+                }
+                // The videos now live under the new folder, drop what is
+                // left of the old one
+                if folder_moved {
+                    changes.push(WatchChange::FolderRemoved(from.clone()));
+                }
+            }
+            EventKind::Remove(_) | EventKind::Modify(ModifyKind::Name(RenameMode::From)) => {
+                for path in &event.paths {
+                    remove_videos(cache, path, &mut changes);
+                    // Roots stay in the index while they are unavailable
+                    if !roots.contains_key(path) {
+                        changes.push(WatchChange::FolderRemoved(path.clone()));
+                    }
+                }
+            }
+            EventKind::Create(_)
+            | EventKind::Modify(ModifyKind::Name(_))
+            | EventKind::Modify(ModifyKind::Data(_)) => {
+                for path in &event.paths {
+                    if !path.exists() {
+                        remove_videos(cache, path, &mut changes);
+                    } else if let Some((root, settings)) = find_root(roots, path) {
+                        let mut scan = RootScan::new(root, settings);
+                        add_videos(cache, &mut scan, path, &mut changes);
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    changes
+}
+
+// Lock order is db, video cache, videos and then folders
+fn apply_changes(app: &AppHandle, cache: &mut VideoCache, changes: Vec<WatchChange>) {
+    let state = app.state::<AppState>();
+    let db_guard = state.db.lock().unwrap();
+    let mut cache_guard = state.video_cache.lock().unwrap();
+    let videos_guard = state.videos.lock().unwrap();
+    let mut folders_guard = state.folders.lock().unwrap();
+    let (db, shared, videos) = match (
+        db_guard.as_ref(),
+        cache_guard.as_mut(),
+        videos_guard.as_ref(),
+    ) {
+        (Some(db), Some(shared), Some(videos)) => (db, shared, videos),
+        _ => return,
+    };
+    shared.merge(cache.take_changes());
+    let dropped = shared.commit(db);
+    let mut folders = folders_guard.as_mut();
+    for change in changes {
+        match change {
+            WatchChange::Added(mut video, size) => {
+                video.update_meta(videos.get(&video.id));
+                if let Some(folders) = folders.as_mut() {
+                    folders.add_video(video.clone(), size);
+                }
+                debug!("Video added {}", video.path().display());
+                let folder = video.path().parent().map(Path::to_path_buf);
+                let _ = app.emit_all(
+                    "video_added",
+                    VideoAddedEmitEvent {
+                        video,
+                        folder: folder.unwrap_or_default(),
+                    },
+                );
+            }
+            WatchChange::Removed(id, path) => {
+                if let Some(folders) = folders.as_mut() {
+                    folders.remove_video(&path);
+                }
+                let _ = app.emit_all("video_removed", VideoRemovedEmitEvent { id, path });
+            }
+            WatchChange::Moved(id, from, to) => {
+                if let Some(folders) = folders.as_mut() {
+                    folders.move_video(&from, &to);
+                }
+                let _ = app.emit_all("video_moved", VideoMovedEmitEvent { id, from, to });
+            }
+            WatchChange::FolderMoved(from, to) => {
+                match database::move_folder_entries(db, &from, &to) {
+                    Ok(moves) => {
+                        if let Some(folders) = folders.as_mut() {
+                            folders.move_entries(&moves);
+                        }
+                    }
+                    Err(e) => error!("Folder entries can't be moved: {}", e),
+                }
+            }
+            WatchChange::FolderRemoved(path) => {
+                if let Some(folders) = folders.as_mut() {
+                    folders.remove(&path);
+                }
+            }
+        }
+    }
+    thumbnail::delete_chapter_thumbnails(thumbnail::get_thumbnail_save_location(app), &dropped);
+}
+
+fn find_root<'a, V>(roots: &'a HashMap<PathBuf, V>, path: &Path) -> Option<(&'a PathBuf, &'a V)> {
+    roots
+        .iter()
+        .filter(|(root, _)| path.starts_with(root))
+        .max_by_key(|(root, _)| root.components().count())
+}
+
+fn remove_videos(cache: &mut VideoCache, path: &Path, changes: &mut Vec<WatchChange>) {
+    for (old, item) in cache.get_videos_in(path) {
+        cache.delete_video(&old);
+        if item.is_video() {
+            changes.push(WatchChange::Removed(item.id().to_string(), old));
+        }
+    }
+}
+
+// Settings of the root an event belongs to and the directories walked while
+// handling it
+struct RootScan<'a> {
+    root: &'a Path,
+    identity: IdentityStrategy,
+    rules: ScanRules,
+    traversal: Traversal,
+}
+
+impl<'a> RootScan<'a> {
+    fn new(root: &'a Path, settings: &ScanSettings) -> Self {
+        Self {
+            root,
+            identity: settings.identity,
+            rules: ScanRules::new(root, &settings.rules),
+            traversal: Traversal::new(settings.follow_symlinks),
+        }
+    }
+}
+
+fn add_videos(
+    cache: &mut VideoCache,
+    scan: &mut RootScan,
+    path: &Path,
+    changes: &mut Vec<WatchChange>,
+) {
+    let metadata = match scan.traversal.metadata(path) {
+        Some(metadata) => metadata,
+        None => return,
+    };
+    if metadata.is_dir() {
+        if scan.rules.is_ignored_in_tree(path, true) || !scan.traversal.enter(path) {
+            return;
+        }
+        if let Ok(entries) = std::fs::read_dir(path) {
+            entries
+                .filter_map(Result::ok)
+                .for_each(|e| add_videos(cache, scan, &e.path(), changes));
+        }
+        return;
+    }
+    if !has_video_extension(path) || scan.rules.is_ignored_in_tree(path, false) {
+        return;
+    }
+    let previous = cache
+        .get_video(path)
+        .filter(|v| v.is_video())
+        .map(|v| v.id().to_string());
+    let item = match probe_cached(path, cache, scan.identity) {
+        Some(item) if item.is_video() => item,
+        _ => return,
+    };
+    let depth = path
+        .parent()
+        .and_then(|p| p.strip_prefix(scan.root).ok())
+        .map(|p| p.components().count())
+        .unwrap_or(0);
+    let video = VideoFile::new(path, depth, item.id().to_string());
+    if previous.as_deref() == Some(video.id.as_str()) {
+        return;
+    }
+    if let Some(id) = previous {
+        changes.push(WatchChange::Removed(id, path.to_path_buf()));
+    }
+    changes.push(WatchChange::Added(video, item.filesize()));
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use notify::event::{CreateKind, RemoveKind};
+
+    use super::*;
+    use crate::scanrules::{ScanRule, IGNORE_FILE_NAME};
+
+    // Fresh copy of the test/ fixtures the events can change
+    fn fixtures(name: &str) -> PathBuf {
+        let root =
+            std::env::temp_dir().join(format!("vidlib-watcher-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        copy_dir(
+            &Path::new(env!("CARGO_MANIFEST_DIR")).join("../test"),
+            &root,
+        );
+        root
+    }
+
+    fn copy_dir(from: &Path, to: &Path) {
+        fs::create_dir_all(to).unwrap();
+        for entry in fs::read_dir(from).unwrap() {
+            let entry = entry.unwrap();
+            let target = to.join(entry.file_name());
+            if entry.file_type().unwrap().is_dir() {
+                copy_dir(&entry.path(), &target);
+            } else {
+                fs::copy(entry.path(), target).unwrap();
+            }
+        }
+    }
+
+    fn roots(root: &Path, rules: Vec<ScanRule>) -> HashMap<PathBuf, ScanSettings> {
+        let settings = ScanSettings {
+            rules,
+            ..ScanSettings::default()
+        };
+        HashMap::from([(root.to_path_buf(), settings)])
+    }
+
+    fn event(kind: EventKind, paths: &[&Path]) -> Event {
+        paths
+            .iter()
+            .fold(Event::new(kind), |e, p| e.add_path(p.to_path_buf()))
+    }
+
+    fn rename(from: &Path, to: &Path) -> Event {
+        fs::rename(from, to).unwrap();
+        event(
+            EventKind::Modify(ModifyKind::Name(RenameMode::Both)),
+            &[from, to],
+        )
+    }
+
+    // Handles the events and applies the changes to the cache the way the
+    // merge does
+    fn handle(
+        cache: &mut VideoCache,
+        roots: &HashMap<PathBuf, ScanSettings>,
+        events: &[Event],
+    ) -> Vec<WatchChange> {
+        let changes = handle_events(cache, roots, events);
+        cache.take_changes();
+        changes
+    }
+
+    fn added(changes: &[WatchChange]) -> Vec<PathBuf> {
+        let mut paths: Vec<PathBuf> = changes
+            .iter()
+            .filter_map(|c| match c {
+                WatchChange::Added(video, _) => Some(video.path().clone()),
+                _ => None,
+            })
+            .collect();
+        paths.sort();
+        paths
+    }
+
+    fn removed(changes: &[WatchChange]) -> Vec<PathBuf> {
+        changes
+            .iter()
+            .filter_map(|c| match c {
+                WatchChange::Removed(_, path) => Some(path.clone()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn moved(changes: &[WatchChange]) -> Vec<(PathBuf, PathBuf)> {
+        changes
+            .iter()
+            .filter_map(|c| match c {
+                WatchChange::Moved(_, from, to) => Some((from.clone(), to.clone())),
+                _ => None,
+            })
+            .collect()
+    }
+
+    // Adds every video below the root to the cache
+    fn index(cache: &mut VideoCache, roots: &HashMap<PathBuf, ScanSettings>, root: &Path) {
+        handle(
+            cache,
+            roots,
+            &[event(EventKind::Create(CreateKind::Folder), &[root])],
+        );
+    }
+
+    #[test]
+    fn created_folders_add_their_videos() {
+        let root = fixtures("create");
+        let roots = roots(&root, vec![]);
+        let mut cache = VideoCache::new();
+        let changes = handle(
+            &mut cache,
+            &roots,
+            &[event(
+                EventKind::Create(CreateKind::Folder),
+                &[&root.join("a")],
+            )],
+        );
+        assert_eq!(added(&changes), vec![root.join("a/test_a.mp4")]);
+        match &changes[0] {
+            WatchChange::Added(_, size) => {
+                assert_eq!(
+                    *size,
+                    fs::metadata(root.join("a/test_a.mp4")).unwrap().len()
+                );
+            }
+            _ => panic!("Expected an added video"),
+        }
+        assert!(cache.get_video(root.join("a/test_a.mp4")).is_some());
+        assert!(cache.get_video(root.join("b/test_b.mp4")).is_none());
+    }
+
+    #[test]
+    fn created_files_follow_the_scan_rules() {
+        let root = fixtures("create-rules");
+        fs::write(root.join(IGNORE_FILE_NAME), "b/\n").unwrap();
+        let roots = roots(&root, vec![ScanRule::new("*.mkv".into(), false)]);
+        let mut cache = VideoCache::new();
+        let changes = handle(
+            &mut cache,
+            &roots,
+            &[event(EventKind::Create(CreateKind::Folder), &[&root])],
+        );
+        assert_eq!(
+            added(&changes),
+            vec![root.join("a/test_a.mp4"), root.join("test.mp4")]
+        );
+        assert!(cache.get_video(root.join("test.mkv")).is_none());
+    }
+
+    #[test]
+    fn unchanged_files_are_not_added_again() {
+        let root = fixtures("modify");
+        let roots = roots(&root, vec![]);
+        let mut cache = VideoCache::new();
+        index(&mut cache, &roots, &root);
+        let changes = handle(
+            &mut cache,
+            &roots,
+            &[event(
+                EventKind::Create(CreateKind::File),
+                &[&root.join("test.mp4")],
+            )],
+        );
+        assert!(changes.is_empty());
+    }
+
+    #[test]
+    fn removed_folders_drop_their_videos() {
+        let root = fixtures("remove");
+        let roots = roots(&root, vec![]);
+        let mut cache = VideoCache::new();
+        index(&mut cache, &roots, &root);
+        fs::remove_dir_all(root.join("a")).unwrap();
+        let changes = handle(
+            &mut cache,
+            &roots,
+            &[event(
+                EventKind::Remove(RemoveKind::Folder),
+                &[&root.join("a")],
+            )],
+        );
+        assert_eq!(removed(&changes), vec![root.join("a/test_a.mp4")]);
+        assert!(
+            matches!(changes.last(), Some(WatchChange::FolderRemoved(p)) if p == &root.join("a"))
+        );
+        assert!(cache.get_video(root.join("a/test_a.mp4")).is_none());
+        assert!(cache.get_video(root.join("b/test_b.mp4")).is_some());
+    }
+
+    #[test]
+    fn removed_roots_stay_in_the_index() {
+        let root = fixtures("remove-root");
+        let roots = roots(&root, vec![]);
+        let mut cache = VideoCache::new();
+        index(&mut cache, &roots, &root);
+        let changes = handle(
+            &mut cache,
+            &roots,
+            &[event(EventKind::Remove(RemoveKind::Folder), &[&root])],
+        );
+        assert_eq!(removed(&changes).len(), 4);
+        assert!(!changes
+            .iter()
+            .any(|c| matches!(c, WatchChange::FolderRemoved(_))));
+    }
+
+    #[test]
+    fn moved_folders_keep_their_videos() {
+        let root = fixtures("move");
+        let roots = roots(&root, vec![]);
+        let mut cache = VideoCache::new();
+        index(&mut cache, &roots, &root);
+        let id = cache
+            .get_video(root.join("a/test_a.mp4"))
+            .unwrap()
+            .id()
+            .to_string();
+        let changes = handle(
+            &mut cache,
+            &roots,
+            &[rename(&root.join("a"), &root.join("c"))],
+        );
+        assert!(
+            matches!(&changes[0], WatchChange::FolderMoved(from, to) if from == &root.join("a") && to == &root.join("c"))
+        );
+        assert_eq!(
+            moved(&changes),
+            vec![(root.join("a/test_a.mp4"), root.join("c/test_a.mp4"))]
+        );
+        assert!(
+            matches!(changes.last(), Some(WatchChange::FolderRemoved(p)) if p == &root.join("a"))
+        );
+        assert!(cache.get_video(root.join("a/test_a.mp4")).is_none());
+        assert_eq!(cache.get_video(root.join("c/test_a.mp4")).unwrap().id(), id);
+    }
+
+    #[test]
+    fn moving_into_an_ignored_folder_removes_the_video() {
+        let root = fixtures("move-ignored");
+        let roots = roots(&root, vec![]);
+        let mut cache = VideoCache::new();
+        index(&mut cache, &roots, &root);
+        fs::write(root.join("b").join(IGNORE_FILE_NAME), "*.mp4\n").unwrap();
+        let changes = handle(
+            &mut cache,
+            &roots,
+            &[rename(
+                &root.join("a/test_a.mp4"),
+                &root.join("b/test_a.mp4"),
+            )],
+        );
+        assert!(moved(&changes).is_empty());
+        assert_eq!(removed(&changes), vec![root.join("a/test_a.mp4")]);
+        assert!(cache.get_video(root.join("b/test_a.mp4")).is_none());
+    }
+
+    #[test]
+    fn moving_out_of_the_library_removes_the_video() {
+        let root = fixtures("move-out");
+        let outside = fixtures("move-out-target");
+        let roots = roots(&root, vec![]);
+        let mut cache = VideoCache::new();
+        index(&mut cache, &roots, &root);
+        let changes = handle(
+            &mut cache,
+            &roots,
+            &[rename(&root.join("test.mp4"), &outside.join("moved.mp4"))],
+        );
+        assert_eq!(removed(&changes), vec![root.join("test.mp4")]);
+        assert!(cache.get_video(outside.join("moved.mp4")).is_none());
+    }
+
+    #[test]
+    fn moving_an_unknown_file_in_adds_it() {
+        let root = fixtures("move-in");
+        let outside = fixtures("move-in-source");
+        let roots = roots(&root, vec![]);
+        let mut cache = VideoCache::new();
+        let changes = handle(
+            &mut cache,
+            &roots,
+            &[rename(&outside.join("test.mp4"), &root.join("a/moved.mp4"))],
+        );
+        assert_eq!(added(&changes), vec![root.join("a/moved.mp4")]);
+    }
+}
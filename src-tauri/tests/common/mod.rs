@@ -0,0 +1,50 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use vidlib::filescan::{FileScan, FolderInfo, ScanSettings};
+use vidlib::scanreport::ScanReport;
+use vidlib::state::VideoCache;
+use vidlib::EmitProgress;
+
+// Fresh copy of the test/ fixtures, so a test can add, move and delete files
+// without touching the ones in the repository
+pub fn fixtures(name: &str) -> PathBuf {
+    let root = std::env::temp_dir().join(format!("vidlib-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&root);
+    copy_dir(
+        &Path::new(env!("CARGO_MANIFEST_DIR")).join("../test"),
+        &root,
+    );
+    root
+}
+
+fn copy_dir(from: &Path, to: &Path) {
+    fs::create_dir_all(to).unwrap();
+    for entry in fs::read_dir(from).unwrap() {
+        let entry = entry.unwrap();
+        let target = to.join(entry.file_name());
+        if entry.file_type().unwrap().is_dir() {
+            copy_dir(&entry.path(), &target);
+        } else {
+            fs::copy(entry.path(), target).unwrap();
+        }
+    }
+}
+
+pub fn scan(path: &Path, cache: &mut VideoCache) -> (FolderInfo, ScanReport) {
+    scan_with(path, cache, ScanSettings::default())
+}
+
+pub fn scan_with(
+    path: &Path,
+    cache: &mut VideoCache,
+    settings: ScanSettings,
+) -> (FolderInfo, ScanReport) {
+    let mut scan = FileScan::new(path, Some(cache)).with_settings(settings);
+    let info = scan.run(&|_: EmitProgress| {}).unwrap();
+    (info, scan.into_report())
+}
+
+pub fn names<T>(items: &[T], name: impl Fn(&T) -> &str) -> Vec<String> {
+    items.iter().map(|i| name(i).to_string()).collect()
+}
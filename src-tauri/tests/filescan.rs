@@ -0,0 +1,354 @@
+mod common;
+
+use std::fs;
+use std::io::Write;
+
+use vidlib::filescan::{FolderInfo, ScanSettings};
+use vidlib::folderscan::FolderTree;
+use vidlib::identity::IdentityStrategy;
+use vidlib::scanreport::ScanIssue;
+use vidlib::scanrules::ScanRule;
+use vidlib::state::{VideoCache, VideoCacheItem};
+use xxhash_rust::xxh3::xxh3_64;
+
+use common::{fixtures, names, scan, scan_with};
+
+fn folder<'a>(info: &'a FolderInfo, name: &str) -> &'a FolderInfo {
+    info.folders().iter().find(|f| f.name() == name).unwrap()
+}
+
+#[test]
+fn scan_lists_videos_and_folders() {
+    let root = fixtures("scan-lists");
+    fs::write(root.join("notes.txt"), "not a video").unwrap();
+    fs::write(root.join("broken.mp4"), "not a video either").unwrap();
+    let mut cache = VideoCache::new();
+
+    let (info, report) = scan(&root, &mut cache);
+
+    assert_eq!(names(info.videos(), |v| v.name()), ["test.mkv", "test.mp4"]);
+    assert_eq!(names(info.folders(), |f| f.name()), ["a", "b"]);
+    assert_eq!(
+        names(folder(&info, "a").videos(), |v| v.name()),
+        ["test_a.mp4"]
+    );
+    assert_eq!(
+        names(folder(&info, "b").videos(), |v| v.name()),
+        ["test_b.mp4"]
+    );
+    // Only files with a video extension are probed, and ffmpeg rejects the fake one
+    assert!(cache.get_video(root.join("notes.txt")).is_none());
+    assert!(!cache.get_video(root.join("broken.mp4")).unwrap().is_video());
+    assert!(report
+        .entries()
+        .iter()
+        .any(|e| e.path() == &root.join("broken.mp4") && e.issue() == ScanIssue::Rejected));
+    assert_eq!((report.files(), report.videos()), (5, 4));
+}
+
+#[test]
+fn scan_ids_match_identity_hash() {
+    let root = fixtures("scan-ids");
+    let mut cache = VideoCache::new();
+
+    let (info, _) = scan(&root, &mut cache);
+
+    let videos: Vec<_> = info
+        .videos()
+        .iter()
+        .chain(info.folders().iter().flat_map(|f| f.videos()))
+        .collect();
+    assert_eq!(videos.len(), 4);
+    for video in &videos {
+        let size = video.path().metadata().unwrap().len();
+        let id = IdentityStrategy::default()
+            .hash_file(video.path(), size)
+            .unwrap();
+        assert!(!video.id.is_empty());
+        assert_eq!(video.id, id);
+        assert_eq!(cache.get_video(video.path()).unwrap().id(), id);
+    }
+    // test/test.mp4 and test/b/test_b.mp4 have the same content
+    let id = |name: &str| videos.iter().find(|v| v.name() == name).unwrap().id.clone();
+    assert_eq!(id("test.mp4"), id("test_b.mp4"));
+    assert_ne!(id("test.mp4"), id("test_a.mp4"));
+}
+
+#[test]
+fn scan_skips_hidden_folders() {
+    let root = fixtures("scan-hidden");
+    fs::create_dir(root.join(".hidden")).unwrap();
+    fs::copy(root.join("test.mp4"), root.join(".hidden/hidden.mp4")).unwrap();
+    let mut cache = VideoCache::new();
+
+    let (info, report) = scan(&root, &mut cache);
+
+    assert_eq!(names(info.folders(), |f| f.name()), ["a", "b"]);
+    assert!(cache.get_video(root.join(".hidden/hidden.mp4")).is_none());
+    assert!(report
+        .entries()
+        .iter()
+        .any(|e| e.path() == &root.join(".hidden") && e.issue() == ScanIssue::Skipped));
+}
+
+#[test]
+fn folder_tree_follows_scanned_paths() {
+    let root = fixtures("scan-tree");
+    let mut cache = VideoCache::new();
+    let (info, _) = scan(&root, &mut cache);
+    let mut tree = FolderTree::default();
+
+    tree.insert(&info, &cache);
+
+    let roots = tree.roots(None);
+    assert_eq!(roots.len(), 1);
+    assert_eq!(roots[0].path(), &root);
+    assert_eq!(roots[0].id(), info.id());
+    assert_eq!(roots[0].total_videos(), 4);
+    assert_eq!(roots[0].total_size(), 14929 + 23247 * 2 + 20319);
+
+    let a = tree.get_by_path(root.join("a")).unwrap();
+    assert_eq!(a.name(), "a");
+    assert_eq!(a.id(), folder(&info, "a").id());
+    assert_eq!(tree.get_parent(a.id()).unwrap().id(), info.id());
+    // Folders are found through every / separated segment of their path
+    let parent = root.parent().unwrap();
+    assert!(tree.get_by_path(parent).is_none());
+    assert!(tree.get_by_path(root.join("c")).is_none());
+
+    let page = tree.get_children(info.id(), 0, 10, None).unwrap();
+    assert_eq!(names(page.folders(), |f| f.name()), ["a", "b"]);
+    assert_eq!(names(page.videos(), |v| v.name()), ["test.mkv", "test.mp4"]);
+    let page = tree.get_children(info.id(), 1, 2, None).unwrap();
+    assert_eq!(names(page.folders(), |f| f.name()), ["b"]);
+    assert_eq!(names(page.videos(), |v| v.name()), ["test.mkv"]);
+}
+
+#[test]
+fn rescan_reuses_current_cache_entries() {
+    let root = fixtures("rescan");
+    let mut cache = VideoCache::new();
+    scan(&root, &mut cache);
+    let path = root.join("test.mkv");
+    let cached = cache.get_video(&path).unwrap().clone();
+    // A stand-in id only survives the rescan if the file is not hashed again
+    cache.add_video(
+        &path,
+        VideoCacheItem::new(cached.filesize(), cached.mtime(), "cached".into(), true),
+    );
+
+    let (info, _) = scan(&root, &mut cache);
+
+    let video = info
+        .videos()
+        .iter()
+        .find(|v| v.name() == "test.mkv")
+        .unwrap();
+    assert_eq!(video.id, "cached");
+    assert_eq!(cache.get_video(&path).unwrap().id(), "cached");
+}
+
+#[test]
+fn rescan_probes_modified_files() {
+    let root = fixtures("rescan-modified");
+    let mut cache = VideoCache::new();
+    scan(&root, &mut cache);
+    let path = root.join("test.mkv");
+    let cached = cache.get_video(&path).unwrap().clone();
+    cache.add_video(
+        &path,
+        VideoCacheItem::new(cached.filesize(), cached.mtime(), "cached".into(), true),
+    );
+    let mut file = fs::OpenOptions::new().append(true).open(&path).unwrap();
+    file.write_all(&[0; 16]).unwrap();
+    drop(file);
+
+    let (info, _) = scan(&root, &mut cache);
+
+    let size = path.metadata().unwrap().len();
+    let id = IdentityStrategy::default().hash_file(&path, size).unwrap();
+    let video = info
+        .videos()
+        .iter()
+        .find(|v| v.name() == "test.mkv")
+        .unwrap();
+    assert_eq!(video.id, id);
+    assert_eq!(cache.get_video(&path).unwrap().filesize(), size);
+}
+
+#[test]
+fn rescan_reports_moved_videos() {
+    let root = fixtures("moved");
+    let mut cache = VideoCache::new();
+    scan(&root, &mut cache);
+    // Stands in for committing the scan to the database
+    cache.take_changes();
+    let id = cache
+        .get_video(root.join("test.mkv"))
+        .unwrap()
+        .id()
+        .to_string();
+    fs::rename(root.join("test.mkv"), root.join("a/moved.mkv")).unwrap();
+
+    let (info, _) = scan(&root, &mut cache);
+    cache.take_changes();
+
+    assert_eq!(info.moved().len(), 1);
+    let moved = &info.moved()[0];
+    assert_eq!(moved.id(), id);
+    assert_eq!(moved.from(), &root.join("test.mkv"));
+    assert_eq!(moved.to(), &root.join("a/moved.mkv"));
+    assert!(cache.get_video(root.join("test.mkv")).is_none());
+    assert_eq!(cache.get_video(root.join("a/moved.mkv")).unwrap().id(), id);
+    assert_eq!(cache.paths_by_id()[&id], [root.join("a/moved.mkv")]);
+}
+
+#[test]
+fn rescan_keeps_copies_and_drops_deleted_files() {
+    let root = fixtures("copied");
+    let mut cache = VideoCache::new();
+    scan(&root, &mut cache);
+    cache.take_changes();
+    fs::copy(root.join("test.mkv"), root.join("b/copy.mkv")).unwrap();
+    fs::remove_file(root.join("a/test_a.mp4")).unwrap();
+
+    let (info, _) = scan(&root, &mut cache);
+    cache.take_changes();
+
+    assert!(info.moved().is_empty());
+    assert!(cache.get_video(root.join("test.mkv")).is_some());
+    assert!(cache.get_video(root.join("b/copy.mkv")).is_some());
+    assert!(cache.get_video(root.join("a/test_a.mp4")).is_none());
+    assert_eq!(
+        cache.location_counts()[cache.get_video(root.join("test.mkv")).unwrap().id()],
+        2
+    );
+}
+
+fn rules(rules: &[(&str, bool)]) -> ScanSettings {
+    ScanSettings {
+        rules: rules
+            .iter()
+            .map(|(pattern, include)| ScanRule::new(pattern.to_string(), *include))
+            .collect(),
+        ..Default::default()
+    }
+}
+
+#[test]
+fn scan_rules_exclude_and_include_globs() {
+    let root = fixtures("rules-globs");
+    let mut cache = VideoCache::new();
+
+    let (info, report) = scan_with(&root, &mut cache, rules(&[("a", false)]));
+    assert_eq!(names(info.folders(), |f| f.name()), ["b"]);
+    assert!(report
+        .entries()
+        .iter()
+        .any(|e| e.path() == &root.join("a") && e.issue() == ScanIssue::Skipped));
+
+    let (info, _) = scan_with(&root, &mut cache, rules(&[("**/*.mkv", true)]));
+    assert_eq!(names(info.videos(), |v| v.name()), ["test.mkv"]);
+    assert!(folder(&info, "a").is_empty());
+    assert!(folder(&info, "b").is_empty());
+}
+
+#[test]
+fn scan_rules_follow_ignore_files() {
+    let root = fixtures("rules-ignore-file");
+    fs::copy(root.join("b/test_b.mp4"), root.join("b/keep.mp4")).unwrap();
+    fs::write(root.join(".vidlibignore"), "*.mkv\n").unwrap();
+    // The nested file adds to the rules above it and can whitelist files
+    fs::write(root.join("b/.vidlibignore"), "*.mp4\n!keep.mp4\n").unwrap();
+    let mut cache = VideoCache::new();
+
+    let (info, _) = scan(&root, &mut cache);
+
+    assert_eq!(names(info.videos(), |v| v.name()), ["test.mp4"]);
+    assert_eq!(
+        names(folder(&info, "a").videos(), |v| v.name()),
+        ["test_a.mp4"]
+    );
+    assert_eq!(
+        names(folder(&info, "b").videos(), |v| v.name()),
+        ["keep.mp4"]
+    );
+}
+
+#[test]
+fn scan_rules_retire_ignored_cache_rows() {
+    let root = fixtures("rules-retire");
+    let mut cache = VideoCache::new();
+    scan(&root, &mut cache);
+    cache.take_changes();
+
+    scan_with(&root, &mut cache, rules(&[("a", false), ("*.mkv", false)]));
+    cache.take_changes();
+
+    assert!(cache.get_video(root.join("a/test_a.mp4")).is_none());
+    assert!(cache.get_video(root.join("test.mkv")).is_none());
+    assert!(cache.get_video(root.join("test.mp4")).is_some());
+    assert!(cache.get_video(root.join("b/test_b.mp4")).is_some());
+}
+
+#[cfg(unix)]
+#[test]
+fn scan_ends_symlink_loops() {
+    use std::os::unix::fs::symlink;
+
+    let root = fixtures("symlink-loop");
+    symlink(&root, root.join("a/loop")).unwrap();
+    let mut cache = VideoCache::new();
+
+    let (info, report) = scan(&root, &mut cache);
+
+    assert_eq!(names(info.folders(), |f| f.name()), ["a", "b"]);
+    assert!(folder(&info, "a").folders().is_empty());
+    assert!(report
+        .entries()
+        .iter()
+        .any(|e| e.path() == &root.join("a/loop") && e.issue() == ScanIssue::Skipped));
+}
+
+#[cfg(unix)]
+#[test]
+fn scan_reports_unfollowed_and_broken_symlinks() {
+    use std::os::unix::fs::symlink;
+
+    let root = fixtures("symlink-settings");
+    symlink(root.join("test.mkv"), root.join("linked.mkv")).unwrap();
+    symlink(root.join("missing.mkv"), root.join("broken.mkv")).unwrap();
+    let mut cache = VideoCache::new();
+
+    let (info, report) = scan(&root, &mut cache);
+    assert_eq!(
+        names(info.videos(), |v| v.name()),
+        ["linked.mkv", "test.mkv", "test.mp4"]
+    );
+    assert!(report
+        .entries()
+        .iter()
+        .any(|e| e.path() == &root.join("broken.mkv") && e.issue() == ScanIssue::Unreadable));
+
+    let settings = ScanSettings {
+        follow_symlinks: false,
+        ..Default::default()
+    };
+    let (info, report) = scan_with(&root, &mut cache, settings);
+    assert_eq!(names(info.videos(), |v| v.name()), ["test.mkv", "test.mp4"]);
+    assert!(report
+        .entries()
+        .iter()
+        .any(|e| e.path() == &root.join("linked.mkv") && e.issue() == ScanIssue::Skipped));
+}
+
+#[cfg(unix)]
+#[test]
+fn folder_ids_hash_the_normalised_path() {
+    let id = FolderInfo::new("/videos/shows", 0).id().to_string();
+
+    assert_eq!(id, format!("{:x}", xxh3_64(b"/videos/shows")));
+    assert_eq!(FolderInfo::new("/videos/shows/", 0).id(), id);
+    assert_eq!(FolderInfo::new("/videos/./shows", 0).id(), id);
+    assert_ne!(FolderInfo::new("/videos/movies", 0).id(), id);
+}